@@ -1,5 +1,13 @@
-use neural_network::{activations::SIGMOID, matrix::Matrix, network::Network};
-use std::{env, str::FromStr as StdFromStr};
+use neural_network::{
+    activations::SIGMOID,
+    callbacks::{Checkpointer, EarlyStopping},
+    checkpoint::Checkpoint,
+    history::TrainingHistory,
+    matrix::Matrix,
+    metrics::{ConfusionMatrix, Metric, DEFAULT_THRESHOLD},
+    network::Network,
+};
+use std::{env, path::PathBuf, process, str::FromStr as StdFromStr};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -12,14 +20,44 @@ struct Cli {
     forward: bool,
     #[structopt(short, long)]
     looped_forward: bool,
+    /// Print the confusion matrix and metrics of the network on the training data.
+    #[structopt(short, long)]
+    evaluate: bool,
     #[structopt(short, long)]
     inputs: Option<String>,
+    /// Seed for the weight initialization; identical seeds give identical trained weights.
+    #[structopt(short, long)]
+    seed: Option<u64>,
+    /// Load the network from this file instead of initializing a new one.
+    #[structopt(long, parse(from_os_str))]
+    load: Option<PathBuf>,
+    /// Save the network to this file after training; `.json` files are saved as JSON, others in binary.
+    #[structopt(long, parse(from_os_str))]
+    save: Option<PathBuf>,
+    /// Write a checkpoint to this file every `--checkpoint-every` epochs and when Ctrl-C is pressed.
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+    /// The number of epochs between two checkpoints.
+    #[structopt(long, default_value = "10000")]
+    checkpoint_every: usize,
+    /// Continue training from the checkpoint file, if it exists.
+    #[structopt(long)]
+    resume: bool,
+    /// Write the training history to this file; `.csv` files are written as CSV, others as JSON.
+    #[structopt(long, parse(from_os_str))]
+    history: Option<PathBuf>,
+    /// Stop training once the loss has not improved for this many epochs.
+    #[structopt(long)]
+    patience: Option<usize>,
+    /// With `--patience`, end training with the weights of the epoch with the lowest loss.
+    #[structopt(long)]
+    restore_best: bool,
 }
 
 fn split_inputs(s: String) -> Vec<Vec<f64>> {
     let mut inputs: Vec<Vec<f64>> = Vec::<Vec<f64>>::new();
     let mut vector: Vec<f64> = Vec::<f64>::new();
-    let split = s.split(|c| c == ',' || c == ' '); // split the string on the comma and the space
+    let split = s.split([',', ' ']); // split the string on the comma and the space
     let mut pass = 0;
     for i in split {
         if i.is_empty() {
@@ -74,11 +112,83 @@ fn main() {
         ]
     };
 
-    let mut network = Network::new(vec![2, 3, 1], SIGMOID, 0.5);
+    if args.load.is_some() && args.seed.is_some() {
+        println!("--seed only initializes a new network and cannot be combined with --load");
+        process::exit(1);
+    }
+    let mut network = match args.load {
+        Some(ref path) => {
+            let mut network = Network::load(path).unwrap_or_else(|error| {
+                println!("Error loading {}: {}", path.display(), error);
+                process::exit(1);
+            });
+            // A loaded network has no checkpoint configuration, so the checkpointer is attached here.
+            if let Some(ref checkpoint) = args.checkpoint {
+                let mut checkpointer = Checkpointer::new(checkpoint.clone(), args.checkpoint_every);
+                checkpointer.on_interrupt = true;
+                network.add_callback(checkpointer);
+            }
+            network
+        }
+        None => match args.checkpoint {
+            Some(ref path) => {
+                let mut builder = Network::builder()
+                    .layers(vec![2, 3, 1])
+                    .activation(SIGMOID)
+                    .learning_rate(0.5)
+                    .checkpoint_path(path.clone())
+                    .checkpoint_every(args.checkpoint_every)
+                    .checkpoint_on_interrupt(true);
+                if let Some(seed) = args.seed {
+                    builder = builder.seed(seed);
+                }
+                builder.build().unwrap_or_else(|error| panic!("{}", error))
+            }
+            None => Network::new(vec![2, 3, 1], SIGMOID, 0.5, args.seed),
+        },
+    };
+
+    if let Some(patience) = args.patience {
+        let mut stopping = EarlyStopping::new(patience);
+        stopping.restore_best = args.restore_best;
+        network.add_callback(stopping);
+    }
+
+    if args.resume {
+        match args.checkpoint {
+            Some(ref path) if path.exists() => {
+                let resumed = Checkpoint::load(path).and_then(|checkpoint| network.resume_from(&checkpoint));
+                match resumed {
+                    Ok(()) => println!("Resuming from {}", path.display()),
+                    Err(error) => {
+                        println!("Error resuming from {}: {}", path.display(), error);
+                        process::exit(1);
+                    }
+                }
+            }
+            Some(ref path) => println!("No checkpoint at {}, starting from scratch", path.display()),
+            None => println!("--resume needs a --checkpoint file"),
+        }
+    }
     let targets = vec![vec![0.0], vec![1.0], vec![0.0], vec![1.0]];
 
     if args.train {
-        training(&mut network, &inputs, &targets);
+        let history = training(&mut network, &inputs, &targets);
+        if let Some(last) = history.last() {
+            println!("Final loss {} after {:.1}s", last.loss, history.total_seconds());
+        }
+        if let Some(ref path) = args.history {
+            if let Err(error) = history.save(path) {
+                println!("Error saving {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    if let Some(ref path) = args.save {
+        match network.save(path) {
+            Ok(()) => println!("Saved the network to {}", path.display()),
+            Err(error) => println!("Error saving {}: {}", path.display(), error),
+        }
     }
 
     // check for the forward argument
@@ -92,8 +202,12 @@ fn main() {
         }
     }
 
+    if args.evaluate {
+        evaluate(&mut network, &inputs, &targets);
+    }
+
     // check for the help argument or the absence of both the train and forward arguments
-    if args.help || !args.train && !args.forward {
+    if args.help || !args.train && !args.forward && !args.evaluate {
         usage(args);
         return;
     }
@@ -111,6 +225,34 @@ fn main() {
             "	{} --forward  <-- forward process the network",
             env::args().next().unwrap_or_else(|| "program".to_string())
         );
+        println!(
+            "	{} --seed <n>  <-- initialize the network reproducibly from seed n",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
+        println!(
+            "	{} --save <file>  <-- save the network after training (JSON if the file ends in .json)",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
+        println!(
+            "	{} --load <file>  <-- load a saved network instead of initializing a new one",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
+        println!(
+            "	{} --train --checkpoint <file> [--checkpoint-every <n>] [--resume]  <-- checkpoint training, and resume it",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
+        println!(
+            "	{} --train --history <file>  <-- write the loss of every epoch as CSV or JSON",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
+        println!(
+            "	{} --train --patience <n> [--restore-best]  <-- stop once the loss has not improved for n epochs",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
+        println!(
+            "	{} --train --evaluate  <-- print the confusion matrix and metrics on the training data",
+            env::args().next().unwrap_or_else(|| "program".to_string())
+        );
     }
 
     fn forward_pass(network: &mut Network, inputs: &[Vec<f64>]) {
@@ -122,9 +264,22 @@ fn main() {
         }
     }
 
-    fn training(network: &mut Network, inputs: &[Vec<f64>], targets: &[Vec<f64>]) {
+    fn evaluate(network: &mut Network, inputs: &[Vec<f64>], targets: &[Vec<f64>]) {
+        if inputs.len() != targets.len() {
+            println!("--evaluate needs one target per input, {} inputs given", inputs.len());
+            return;
+        }
+        let outputs = network.predict(inputs);
+        println!("{}", ConfusionMatrix::from_outputs(&outputs, targets, DEFAULT_THRESHOLD));
+        let metrics = [Metric::Accuracy, Metric::Precision, Metric::Recall, Metric::F1, Metric::RocAuc, Metric::MeanSquaredError];
+        for (name, value) in network.evaluate(inputs, targets, &metrics) {
+            println!("{:>9}: {:.4}", name, value);
+        }
+    }
+
+    fn training(network: &mut Network, inputs: &[Vec<f64>], targets: &[Vec<f64>]) -> TrainingHistory {
         let epochs = 100000;
         println!("Training {} epochs", epochs);
-        network.train(inputs.to_vec(), targets.to_vec(), epochs);
+        network.train(inputs.to_vec(), targets.to_vec(), epochs)
     }
 }
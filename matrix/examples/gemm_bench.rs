@@ -0,0 +1,39 @@
+//! Compares the blocked `dot_multiply` against the naive reference implementation.
+//!
+//! Run with optimisations enabled, otherwise the numbers are meaningless:
+//!
+//!     cargo run --release -p matrix --example gemm_bench
+use matrix::matrix::Matrix;
+use std::time::{Duration, Instant};
+
+/// Runs `func` `runs` times and returns the fastest time.
+fn best_of<F: FnMut() -> Matrix>(runs: usize, mut func: F) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            let result = func();
+            let elapsed = start.elapsed();
+            assert!(!result.data.is_empty());
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for &size in &[512, 1024] {
+        let a = Matrix::random(size, size);
+        let b = Matrix::random(size, size);
+        let naive = best_of(3, || a.dot_multiply_naive(&b));
+        let blocked = best_of(3, || a.dot_multiply(&b));
+        let gflops = |time: Duration| 2.0 * (size as f64).powi(3) / time.as_secs_f64() / 1e9;
+        println!(
+            "{size}x{size}: naive {:>8.1} ms ({:.2} GFLOP/s), blocked {:>8.1} ms ({:.2} GFLOP/s), speedup {:.1}x",
+            naive.as_secs_f64() * 1e3,
+            gflops(naive),
+            blocked.as_secs_f64() * 1e3,
+            gflops(blocked),
+            naive.as_secs_f64() / blocked.as_secs_f64(),
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The error type returned by the fallible `try_` operations on `Matrix`.
+///
+/// Every variant carries enough information to explain what went wrong without
+/// needing the matrices themselves, so the error can be logged or propagated by
+/// long-running callers (such as a training loop) instead of aborting the process.
+pub enum MatrixError {
+    /// Two matrices that must have identical dimensions did not.
+    ///
+    /// Returned by element-wise operations such as `add`, `subtract` and `elementwise_multiply`.
+    ShapeMismatch {
+        /// The name of the operation that was attempted.
+        operation: &'static str,
+        /// The `(rows, cols)` of the left-hand matrix.
+        left: (usize, usize),
+        /// The `(rows, cols)` of the right-hand matrix.
+        right: (usize, usize),
+    },
+    /// The number of columns of the left matrix did not match the number of rows of the right matrix.
+    ///
+    /// Returned by `dot_multiply`.
+    DimensionMismatch {
+        /// The `(rows, cols)` of the left-hand matrix.
+        left: (usize, usize),
+        /// The `(rows, cols)` of the right-hand matrix.
+        right: (usize, usize),
+    },
+    /// The length of the data vector does not equal `rows * cols`.
+    InvalidDataLength {
+        /// The requested number of rows.
+        rows: usize,
+        /// The requested number of columns.
+        cols: usize,
+        /// The actual length of the data vector.
+        len: usize,
+    },
+    /// A matrix with zero rows or zero columns was requested.
+    EmptyMatrix {
+        /// The requested number of rows.
+        rows: usize,
+        /// The requested number of columns.
+        cols: usize,
+    },
+}
+
+/// Implements the `fmt::Display` trait for `MatrixError`, producing a human readable description of the error.
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatrixError::ShapeMismatch { operation, left, right } => write!(
+                f,
+                "Cannot {} matrices with different dimensions: {}x{} and {}x{}",
+                operation, left.0, left.1, right.0, right.1
+            ),
+            MatrixError::DimensionMismatch { left, right } => write!(
+                f,
+                "Cannot dot multiply a {}x{} matrix by a {}x{} matrix: {} columns do not match {} rows",
+                left.0, left.1, right.0, right.1, left.1, right.0
+            ),
+            MatrixError::InvalidDataLength { rows, cols, len } => write!(
+                f,
+                "Invalid Size: a {}x{} matrix needs {} elements but {} were given",
+                rows,
+                cols,
+                rows * cols,
+                len
+            ),
+            MatrixError::EmptyMatrix { rows, cols } => write!(
+                f,
+                "Cannot create an empty {}x{} matrix",
+                rows, cols
+            ),
+        }
+    }
+}
+
+impl Error for MatrixError {}
@@ -0,0 +1,483 @@
+//! In-place, allocation-free operations on `Matrix`.
+//!
+//! The methods in `matrix.rs` return a new `Matrix` for every call, which is convenient but
+//! allocates a fresh buffer each time. The methods in this module write their result into a
+//! matrix that already exists, so a caller that keeps its buffers around (such as the training
+//! loop of a network) can run without allocating at all.
+//!
+//! The `gemm` family follows the BLAS convention `out = alpha * op(a) * op(b) + beta * out`,
+//! where `op` is either the matrix itself or its transpose. Small products read the operands
+//! in place with swapped strides. Large products are packed into per-thread buffers that are
+//! reused between calls and handed to the cache-blocked kernel in `kernels.rs`.
+use crate::error::MatrixError;
+use crate::kernels;
+use crate::matrix::Matrix;
+use crate::parallel;
+use crate::scalar::Scalar;
+
+/// The number of elements handed to each thread by `map_inplace` when it runs in parallel.
+const MAP_CHUNK: usize = 4096;
+
+/// Products with fewer multiply-adds than this use the simple strided loop, where the cost of
+/// packing the operands would outweigh the benefit of the blocked kernel.
+const BLOCKED_THRESHOLD: usize = 32 * 32 * 32;
+
+impl<T: Scalar> Matrix<T> {
+    /// Adds `other` to the current matrix element by element, in place.
+    ///
+    /// # Arguments
+    /// * `other` - The matrix to add.
+    ///
+    /// # Panics
+    /// Panics if the dimensions of the two matrices do not match.
+    pub fn add_assign(&mut self, other: &Matrix<T>) {
+        self.assert_same_shape("add", other);
+        for (value, other) in self.data.iter_mut().zip(&other.data) {
+            *value += *other;
+        }
+    }
+
+    /// Subtracts `other` from the current matrix element by element, in place.
+    ///
+    /// # Arguments
+    /// * `other` - The matrix to subtract.
+    ///
+    /// # Panics
+    /// Panics if the dimensions of the two matrices do not match.
+    pub fn subtract_assign(&mut self, other: &Matrix<T>) {
+        self.assert_same_shape("subtract", other);
+        for (value, other) in self.data.iter_mut().zip(&other.data) {
+            *value -= *other;
+        }
+    }
+
+    /// Multiplies the current matrix by `other` element by element, in place.
+    ///
+    /// # Arguments
+    /// * `other` - The matrix to multiply element-wise with the current matrix.
+    ///
+    /// # Panics
+    /// Panics if the dimensions of the two matrices do not match.
+    pub fn elementwise_multiply_assign(&mut self, other: &Matrix<T>) {
+        self.assert_same_shape("elementwise multiply", other);
+        for (value, other) in self.data.iter_mut().zip(&other.data) {
+            *value *= *other;
+        }
+    }
+
+    /// Applies the given function `func` to each element of the matrix, replacing the element with the result.
+    ///
+    /// # Parameters
+    /// - `func`: A closure that takes a reference to a `T` value and returns a new `T` value.
+    ///   It must be `Sync` so that large matrices can be mapped in parallel.
+    pub fn map_inplace<F>(&mut self, func: F)
+    where
+        F: Fn(&T) -> T + Sync,
+    {
+        let len = self.data.len();
+        parallel::for_each_chunk_mut(&mut self.data, MAP_CHUNK, len, |_, chunk| {
+            for value in chunk.iter_mut() {
+                *value = func(value);
+            }
+        });
+    }
+
+    /// Multiplies every element of the matrix by `factor`, in place.
+    ///
+    /// # Arguments
+    /// * `factor` - The scalar to multiply each element by.
+    pub fn scale_inplace(&mut self, factor: T) {
+        for value in self.data.iter_mut() {
+            *value *= factor;
+        }
+    }
+
+    /// Overwrites the elements of the matrix with the given values, keeping its dimensions.
+    ///
+    /// # Arguments
+    /// * `values` - The new elements, in row-major order.
+    ///
+    /// # Panics
+    /// Panics if `values.len()` does not equal `rows * cols`.
+    pub fn copy_from_slice(&mut self, values: &[T]) {
+        if values.len() != self.data.len() {
+            panic!(
+                "{}",
+                MatrixError::InvalidDataLength {
+                    rows: self.rows,
+                    cols: self.cols,
+                    len: values.len(),
+                }
+            );
+        }
+        self.data.copy_from_slice(values);
+    }
+
+    /// Adds the column vector `column` to every column of the matrix, in place.
+    ///
+    /// # Arguments
+    /// * `column` - A matrix with one column and as many rows as the current matrix.
+    ///
+    /// # Panics
+    /// Panics if `column` is not a column vector with the same number of rows.
+    pub fn add_column_assign(&mut self, column: &Matrix<T>) {
+        if column.cols != 1 || column.rows != self.rows {
+            panic!(
+                "{}",
+                MatrixError::ShapeMismatch {
+                    operation: "broadcast add",
+                    left: self.shape(),
+                    right: column.shape(),
+                }
+            );
+        }
+        for (row, &value) in self.data.chunks_mut(self.cols.max(1)).zip(&column.data) {
+            row.iter_mut().for_each(|element| *element += value);
+        }
+    }
+
+    /// Writes the sum of the columns of the matrix into the column vector `out`.
+    ///
+    /// # Arguments
+    /// * `out` - A matrix with one column and as many rows as the current matrix.
+    ///
+    /// # Panics
+    /// Panics if `out` is not a column vector with the same number of rows.
+    pub fn sum_columns_into(&self, out: &mut Matrix<T>) {
+        if out.cols != 1 || out.rows != self.rows {
+            panic!(
+                "{}",
+                MatrixError::ShapeMismatch {
+                    operation: "sum columns into",
+                    left: self.shape(),
+                    right: out.shape(),
+                }
+            );
+        }
+        for (row, sum) in self.data.chunks(self.cols.max(1)).zip(out.data.iter_mut()) {
+            *sum = row.iter().copied().sum();
+        }
+    }
+
+    /// Changes the number of columns of the matrix, keeping its number of rows.
+    ///
+    /// The existing allocation is reused when it is large enough, so a buffer that is resized
+    /// back and forth between a few sizes only allocates for the largest one. The contents of
+    /// the matrix are unspecified after a resize.
+    ///
+    /// # Arguments
+    /// * `cols` - The new number of columns.
+    pub fn resize_cols(&mut self, cols: usize) {
+        self.cols = cols;
+        self.data.resize(self.rows * cols, T::zero());
+    }
+
+    /// Computes `out = alpha * a * b + beta * out` without allocating.
+    ///
+    /// # Arguments
+    /// * `out` - The matrix that receives the result; it must be `a.rows` by `b.cols`.
+    /// * `a` - The left matrix.
+    /// * `b` - The right matrix.
+    /// * `alpha` - The factor applied to the product.
+    /// * `beta` - The factor applied to the existing contents of `out`. When `beta` is zero the
+    ///   previous contents of `out` are ignored entirely, even if they are `NaN`.
+    ///
+    /// # Panics
+    /// Panics if the dimensions do not line up. See `try_gemm_into` for a non-panicking version.
+    pub fn gemm_into(out: &mut Matrix<T>, a: &Matrix<T>, b: &Matrix<T>, alpha: T, beta: T) {
+        Matrix::try_gemm_into(out, a, b, alpha, beta).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Computes `out = alpha * a * b + beta * out` without allocating.
+    ///
+    /// # Returns
+    /// `Ok(())`, or
+    /// - `MatrixError::DimensionMismatch` if `a.cols` does not equal `b.rows`.
+    /// - `MatrixError::ShapeMismatch` if `out` is not `a.rows` by `b.cols`.
+    pub fn try_gemm_into(
+        out: &mut Matrix<T>,
+        a: &Matrix<T>,
+        b: &Matrix<T>,
+        alpha: T,
+        beta: T,
+    ) -> Result<(), MatrixError> {
+        gemm(out, Operand::plain(a), Operand::plain(b), alpha, beta)
+    }
+
+    /// Computes `out = alpha * aᵀ * b + beta * out` without allocating or materialising `aᵀ`.
+    ///
+    /// # Panics
+    /// Panics if the dimensions do not line up. See `try_gemm_tn_into` for a non-panicking version.
+    pub fn gemm_tn_into(out: &mut Matrix<T>, a: &Matrix<T>, b: &Matrix<T>, alpha: T, beta: T) {
+        Matrix::try_gemm_tn_into(out, a, b, alpha, beta).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Computes `out = alpha * aᵀ * b + beta * out` without allocating or materialising `aᵀ`.
+    ///
+    /// # Returns
+    /// `Ok(())`, or the same errors as `try_gemm_into` for the transposed left operand.
+    pub fn try_gemm_tn_into(
+        out: &mut Matrix<T>,
+        a: &Matrix<T>,
+        b: &Matrix<T>,
+        alpha: T,
+        beta: T,
+    ) -> Result<(), MatrixError> {
+        gemm(out, Operand::transposed(a), Operand::plain(b), alpha, beta)
+    }
+
+    /// Computes `out = alpha * a * bᵀ + beta * out` without allocating or materialising `bᵀ`.
+    ///
+    /// # Panics
+    /// Panics if the dimensions do not line up. See `try_gemm_nt_into` for a non-panicking version.
+    pub fn gemm_nt_into(out: &mut Matrix<T>, a: &Matrix<T>, b: &Matrix<T>, alpha: T, beta: T) {
+        Matrix::try_gemm_nt_into(out, a, b, alpha, beta).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Computes `out = alpha * a * bᵀ + beta * out` without allocating or materialising `bᵀ`.
+    ///
+    /// # Returns
+    /// `Ok(())`, or the same errors as `try_gemm_into` for the transposed right operand.
+    pub fn try_gemm_nt_into(
+        out: &mut Matrix<T>,
+        a: &Matrix<T>,
+        b: &Matrix<T>,
+        alpha: T,
+        beta: T,
+    ) -> Result<(), MatrixError> {
+        gemm(out, Operand::plain(a), Operand::transposed(b), alpha, beta)
+    }
+}
+
+/// A read-only view of a matrix, optionally transposed, used by the `gemm` kernel.
+struct Operand<'a, T> {
+    data: &'a [T],
+    transposed: bool,
+    rows: usize,
+    cols: usize,
+    row_stride: usize,
+    col_stride: usize,
+}
+
+impl<'a, T: Scalar> Operand<'a, T> {
+    fn plain(matrix: &'a Matrix<T>) -> Self {
+        Operand {
+            data: &matrix.data,
+            transposed: false,
+            rows: matrix.rows,
+            cols: matrix.cols,
+            row_stride: matrix.cols,
+            col_stride: 1,
+        }
+    }
+
+    fn transposed(matrix: &'a Matrix<T>) -> Self {
+        Operand {
+            data: &matrix.data,
+            transposed: true,
+            rows: matrix.cols,
+            cols: matrix.rows,
+            row_stride: 1,
+            col_stride: matrix.cols,
+        }
+    }
+
+    fn at(&self, row: usize, col: usize) -> T {
+        self.data[row * self.row_stride + col * self.col_stride]
+    }
+}
+
+/// The shared kernel behind `gemm_into`, `gemm_tn_into` and `gemm_nt_into`, and `dot_multiply`.
+///
+/// With `alpha = 1.0` and `beta = 0.0` the result equals `a.dot_multiply_naive(&b)` up to
+/// rounding, since the blocked kernel sums the products in a different order.
+fn gemm<T: Scalar>(out: &mut Matrix<T>, a: Operand<T>, b: Operand<T>, alpha: T, beta: T) -> Result<(), MatrixError> {
+    if a.cols != b.rows {
+        return Err(MatrixError::DimensionMismatch {
+            left: (a.rows, a.cols),
+            right: (b.rows, b.cols),
+        });
+    }
+    if out.rows != a.rows || out.cols != b.cols {
+        return Err(MatrixError::ShapeMismatch {
+            operation: "store a matrix product in",
+            left: out.shape(),
+            right: (a.rows, b.cols),
+        });
+    }
+    let (m, n, k) = (a.rows, b.cols, a.cols);
+    if m * n * k < BLOCKED_THRESHOLD {
+        gemm_strided(out, &a, &b, alpha, beta);
+        return Ok(());
+    }
+    // The blocked kernel wants `a` as `m x k` and `b` as its transpose, `n x k`, both row-major.
+    let a_len = if a.transposed { m * k } else { 0 };
+    let b_len = if b.transposed { 0 } else { k * n };
+    kernels::with_packing(a_len, b_len, |a_buffer, b_buffer| {
+        let a_packed: &[T] = if a.transposed {
+            kernels::pack_transposed(a.data, k, m, a_buffer);
+            a_buffer
+        } else {
+            a.data
+        };
+        let bt: &[T] = if b.transposed {
+            b.data
+        } else {
+            kernels::pack_transposed(b.data, k, n, b_buffer);
+            b_buffer
+        };
+        kernels::gemm(m, n, k, a_packed, bt, &mut out.data, alpha, beta);
+    });
+    Ok(())
+}
+
+/// Computes `out = alpha * a * b + beta * out` by reading the operands with their strides.
+fn gemm_strided<T: Scalar>(out: &mut Matrix<T>, a: &Operand<T>, b: &Operand<T>, alpha: T, beta: T) {
+    for i in 0..a.rows {
+        for j in 0..b.cols {
+            let mut sum = T::zero();
+            for k in 0..a.cols {
+                sum += a.at(i, k) * b.at(k, j);
+            }
+            let target = &mut out.data[i * out.cols + j];
+            *target = if beta == T::zero() {
+                alpha * sum
+            } else {
+                alpha * sum + beta * *target
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+/// Tests for the in-place operations, each checked against its allocating counterpart.
+mod tests {
+    use crate::error::MatrixError;
+    use crate::matrix;
+    use crate::matrix::Matrix;
+
+    fn a() -> Matrix<f64> {
+        matrix![1.0, 2.0, 3.0;
+                4.0, 5.0, 6.0]
+    }
+
+    fn b() -> Matrix<f64> {
+        matrix![7.0, 8.0;
+                9.0, 10.0;
+                11.0, 12.0]
+    }
+
+    #[test]
+    fn test_elementwise_assign() {
+        let mut m = a();
+        m.add_assign(&a());
+        assert_eq!(m, a().add(&a()));
+        m.subtract_assign(&a());
+        assert_eq!(m, a());
+        m.elementwise_multiply_assign(&a());
+        assert_eq!(m, a().elementwise_multiply(&a()));
+    }
+
+    #[test]
+    fn test_map_and_scale_inplace() {
+        let mut m = a();
+        m.map_inplace(|x| x * x);
+        assert_eq!(m, a().map(|x| x * x));
+        m.scale_inplace(0.5);
+        assert_eq!(m, a().map(|x| x * x * 0.5));
+    }
+
+    #[test]
+    fn test_column_operations() {
+        let mut m = a();
+        m.add_column_assign(&Matrix::from(vec![10.0, 20.0]));
+        assert_eq!(m, matrix![11.0, 12.0, 13.0;
+                              24.0, 25.0, 26.0]);
+        let mut sums = Matrix::zeros(2, 1);
+        a().sum_columns_into(&mut sums);
+        assert_eq!(sums, Matrix::from(vec![6.0, 15.0]));
+
+        let mut m = a();
+        m.resize_cols(1);
+        assert_eq!(m.shape(), (2, 1));
+        m.resize_cols(4);
+        assert_eq!((m.shape(), m.data.len()), ((2, 4), 8));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot broadcast add matrices with different dimensions: 2x3 and 3x1")]
+    fn test_add_column_assign_panics_on_wrong_rows() {
+        a().add_column_assign(&Matrix::from(vec![1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn test_gemm_into_matches_dot_multiply() {
+        let mut out = Matrix::zeros(2, 2);
+        Matrix::gemm_into(&mut out, &a(), &b(), 1.0, 0.0);
+        assert_eq!(out, a().dot_multiply(&b()));
+    }
+
+    #[test]
+    fn test_gemm_into_alpha_beta() {
+        let mut out = matrix![1.0, 1.0;
+                              1.0, 1.0];
+        Matrix::gemm_into(&mut out, &a(), &b(), 2.0, 3.0);
+        let expected = a().dot_multiply(&b()).map(|x| 2.0 * x + 3.0);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_gemm_transposed_variants() {
+        let mut out = Matrix::zeros(3, 3);
+        Matrix::gemm_tn_into(&mut out, &a(), &a(), 1.0, 0.0);
+        assert_eq!(out, a().transpose().dot_multiply(&a()));
+
+        let mut out = Matrix::zeros(2, 2);
+        Matrix::gemm_nt_into(&mut out, &a(), &a(), 1.0, 0.0);
+        assert_eq!(out, a().dot_multiply(&a().transpose()));
+    }
+
+    #[test]
+    fn test_try_gemm_into_errors() {
+        let mut out = Matrix::zeros(2, 2);
+        assert_eq!(
+            Matrix::try_gemm_into(&mut out, &a(), &a(), 1.0, 0.0),
+            Err(MatrixError::DimensionMismatch { left: (2, 3), right: (2, 3) })
+        );
+        let mut out = Matrix::zeros(3, 3);
+        assert!(matches!(
+            Matrix::try_gemm_into(&mut out, &a(), &b(), 1.0, 0.0),
+            Err(MatrixError::ShapeMismatch { left: (3, 3), right: (2, 2), .. })
+        ));
+    }
+
+    #[test]
+    fn test_gemm_blocked_path_matches_naive() {
+        let a = Matrix::random(70, 90);
+        let b = Matrix::random(90, 50);
+        let expected = a.dot_multiply_naive(&b);
+
+        let mut out = Matrix::zeros(70, 50);
+        Matrix::gemm_into(&mut out, &a, &b, 1.0, 0.0);
+        assert_close(&out, &expected);
+
+        let mut out = Matrix::zeros(70, 50);
+        Matrix::gemm_tn_into(&mut out, &a.transpose(), &b, 1.0, 0.0);
+        assert_close(&out, &expected);
+
+        let mut out = Matrix::zeros(70, 50);
+        Matrix::gemm_nt_into(&mut out, &a, &b.transpose(), 1.0, 0.0);
+        assert_close(&out, &expected);
+
+        let mut out = expected.clone();
+        Matrix::gemm_into(&mut out, &a, &b, -2.0, 3.0);
+        assert_close(&out, &expected);
+    }
+
+    fn assert_close(actual: &Matrix, expected: &Matrix) {
+        assert_eq!(actual.shape(), expected.shape());
+        for (x, y) in actual.data.iter().zip(&expected.data) {
+            assert!((x - y).abs() <= 1e-9 * y.abs().max(1.0), "{} != {}", x, y);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Low-level inner kernels for matrix multiplication.
+//!
+//! The blocked GEMM in this module works on a left operand `a` stored row-major (`m x k`) and a
+//! right operand that has already been packed as its transpose `bt` (`n x k`), so that every
+//! element of the result is the dot product of two contiguous slices. The dot products are
+//! computed four columns at a time so each row of `a` is loaded once per four outputs.
+//!
+//! The dot products themselves come from `Scalar::dot` and `Scalar::dot4`, which have two
+//! implementations:
+//! - A portable kernel that keeps `LANES` independent accumulators. Because the lanes never
+//!   depend on each other the compiler is free to map them onto whatever SIMD registers the
+//!   target has (SSE2 on x86_64, NEON on aarch64), and it still works as plain scalar code on
+//!   targets without SIMD, or for element types without a dedicated kernel.
+//! - AVX2 + FMA kernels for `f32` and `f64` on x86_64, used only when the running CPU supports them.
+use crate::parallel;
+use crate::scalar::Scalar;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The number of independent accumulators used by the portable kernel.
+const LANES: usize = 4;
+/// The number of rows of `a` processed per block, and handed to each thread in parallel mode.
+const BLOCK_M: usize = 64;
+/// The number of rows of `bt` (columns of the result) processed per block.
+const BLOCK_N: usize = 64;
+/// The length of the slice of the shared dimension processed per block.
+const BLOCK_K: usize = 256;
+
+thread_local! {
+    /// Reusable packing buffers, one pair per element type, so repeated multiplications on the
+    /// same thread do not allocate.
+    static PACKING: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Runs `func` with two packing buffers of at least `a_len` and `b_len` elements.
+///
+/// The buffers are kept per thread and only grow, so after the first call with a given size no
+/// further allocation happens. They are taken out of the thread-local map while `func` runs, so
+/// a nested call on the same thread, e.g. from a parallel task stolen by a waiting worker,
+/// allocates buffers of its own instead of borrowing the map twice.
+pub(crate) fn with_packing<T: Scalar, R>(
+    a_len: usize,
+    b_len: usize,
+    func: impl FnOnce(&mut [T], &mut [T]) -> R,
+) -> R {
+    let (mut a, mut b) = PACKING.with(|buffers| {
+        buffers.borrow_mut().remove(&TypeId::of::<T>()).map_or_else(
+            || (Vec::<T>::new(), Vec::<T>::new()),
+            |entry| *entry.downcast().expect("packing buffers are keyed by their element type"),
+        )
+    });
+    if a.len() < a_len {
+        a.resize(a_len, T::zero());
+    }
+    if b.len() < b_len {
+        b.resize(b_len, T::zero());
+    }
+    let result = func(&mut a[..a_len], &mut b[..b_len]);
+    PACKING.with(|buffers| buffers.borrow_mut().insert(TypeId::of::<T>(), Box::new((a, b))));
+    result
+}
+
+/// Writes the transpose of the `rows x cols` row-major matrix `source` into `target`.
+///
+/// The copy is done in square tiles so both the reads and the writes stay within cache lines.
+pub(crate) fn pack_transposed<T: Scalar>(source: &[T], rows: usize, cols: usize, target: &mut [T]) {
+    const TILE: usize = 32;
+    for ii in (0..rows).step_by(TILE) {
+        for jj in (0..cols).step_by(TILE) {
+            for i in ii..(ii + TILE).min(rows) {
+                for j in jj..(jj + TILE).min(cols) {
+                    target[j * rows + i] = source[i * cols + j];
+                }
+            }
+        }
+    }
+}
+
+/// Computes `out = alpha * a * btᵀ + beta * out` with a cache-blocked loop.
+///
+/// # Arguments
+/// * `m`, `n`, `k` - The result is `m x n` and the shared dimension is `k`.
+/// * `a` - The left operand, `m x k` row-major.
+/// * `bt` - The transpose of the right operand, `n x k` row-major.
+/// * `out` - The result, `m x n` row-major.
+/// * `alpha` - The factor applied to the product.
+/// * `beta` - The factor applied to the existing contents of `out`; zero ignores them entirely.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gemm<T: Scalar>(
+    m: usize,
+    n: usize,
+    k: usize,
+    a: &[T],
+    bt: &[T],
+    out: &mut [T],
+    alpha: T,
+    beta: T,
+) {
+    if beta == T::zero() {
+        out.iter_mut().for_each(|value| *value = T::zero());
+    } else if beta != T::one() {
+        out.iter_mut().for_each(|value| *value *= beta);
+    }
+    if n == 0 {
+        return;
+    }
+    // Each block of `BLOCK_M` rows of the result only depends on the matching rows of `a`, so
+    // blocks can be computed independently, and in parallel when the `parallel` feature is on.
+    parallel::for_each_chunk_mut(out, BLOCK_M * n, m * n * k, |block, out_block| {
+        let first_row = block * BLOCK_M;
+        let rows = out_block.len() / n;
+        for kk in (0..k).step_by(BLOCK_K) {
+            let k_end = (kk + BLOCK_K).min(k);
+            for jj in (0..n).step_by(BLOCK_N) {
+                let j_end = (jj + BLOCK_N).min(n);
+                for row in 0..rows {
+                    let i = first_row + row;
+                    let a_row = &a[i * k + kk..i * k + k_end];
+                    let out_row = &mut out_block[row * n..(row + 1) * n];
+                    let b_row = |j: usize| &bt[j * k + kk..j * k + k_end];
+                    let mut j = jj;
+                    while j + 4 <= j_end {
+                        let sums = T::dot4(a_row, [b_row(j), b_row(j + 1), b_row(j + 2), b_row(j + 3)]);
+                        for (offset, &sum) in sums.iter().enumerate() {
+                            out_row[j + offset] += alpha * sum;
+                        }
+                        j += 4;
+                    }
+                    while j < j_end {
+                        out_row[j] += alpha * T::dot(a_row, b_row(j));
+                        j += 1;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Returns `true` if the running CPU supports the AVX2 + FMA kernels.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn has_avx2_fma() -> bool {
+    is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+}
+
+/// The portable kernels, written so the compiler can vectorize them on any target.
+pub(crate) mod portable {
+    use super::LANES;
+    use crate::scalar::Scalar;
+
+    pub(crate) fn dot<T: Scalar>(a: &[T], b: &[T]) -> T {
+        let mut acc = [T::zero(); LANES];
+        let chunks = a.len() / LANES;
+        for c in 0..chunks {
+            for lane in 0..LANES {
+                acc[lane] += a[c * LANES + lane] * b[c * LANES + lane];
+            }
+        }
+        let mut sum = (acc[0] + acc[1]) + (acc[2] + acc[3]);
+        for i in chunks * LANES..a.len() {
+            sum += a[i] * b[i];
+        }
+        sum
+    }
+
+    pub(crate) fn dot4<T: Scalar>(a: &[T], b: [&[T]; 4]) -> [T; 4] {
+        [dot(a, b[0]), dot(a, b[1]), dot(a, b[2]), dot(a, b[3])]
+    }
+}
+
+/// The AVX2 + FMA kernels for x86_64.
+///
+/// The safe wrappers may only be called after `has_avx2_fma` returned `true`.
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod x86 {
+    /// Generates a kernel module for one element type and its 256-bit vector type.
+    macro_rules! avx_kernels {
+        ($name:ident, $t:ty, $lanes:expr, $zero:ident, $load:ident, $fmadd:ident, $store:ident) => {
+            pub(crate) mod $name {
+                use std::arch::x86_64::*;
+
+                const LANES: usize = $lanes;
+
+                pub(crate) fn dot(a: &[$t], b: &[$t]) -> $t {
+                    // SAFETY: only called after `has_avx2_fma` returned `true`.
+                    unsafe { dot4_avx(a, [b, b, b, b], 1)[0] }
+                }
+
+                pub(crate) fn dot4(a: &[$t], b: [&[$t]; 4]) -> [$t; 4] {
+                    // SAFETY: only called after `has_avx2_fma` returned `true`.
+                    unsafe { dot4_avx(a, b, 4) }
+                }
+
+                /// Computes the dot product of `a` with the first `count` slices of `b`.
+                #[target_feature(enable = "avx2,fma")]
+                unsafe fn dot4_avx(a: &[$t], b: [&[$t]; 4], count: usize) -> [$t; 4] {
+                    let len = b[..count].iter().fold(a.len(), |len, row| len.min(row.len()));
+                    let chunks = len / LANES;
+                    let mut acc = [$zero(); 4];
+                    for c in 0..chunks {
+                        let x = $load(a.as_ptr().add(c * LANES));
+                        for (acc, row) in acc.iter_mut().zip(b.iter()).take(count) {
+                            let y = $load(row.as_ptr().add(c * LANES));
+                            *acc = $fmadd(x, y, *acc);
+                        }
+                    }
+                    let mut sums = [0.0; 4];
+                    for (sum, (acc, row)) in sums.iter_mut().zip(acc.iter().zip(b.iter())).take(count) {
+                        let mut lanes = [0.0; LANES];
+                        $store(lanes.as_mut_ptr(), *acc);
+                        *sum = lanes.iter().sum();
+                        for i in chunks * LANES..len {
+                            *sum += a[i] * row[i];
+                        }
+                    }
+                    sums
+                }
+            }
+        };
+    }
+
+    avx_kernels!(f64x4, f64, 4, _mm256_setzero_pd, _mm256_loadu_pd, _mm256_fmadd_pd, _mm256_storeu_pd);
+    avx_kernels!(f32x8, f32, 8, _mm256_setzero_ps, _mm256_loadu_ps, _mm256_fmadd_ps, _mm256_storeu_ps);
+}
+
+#[cfg(test)]
+/// Tests that each available kernel agrees with a straightforward scalar dot product, and that
+/// the packing buffers can be used re-entrantly.
+mod tests {
+    use super::*;
+
+    fn scalar_dot(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    fn values(len: usize, offset: f64) -> Vec<f64> {
+        (0..len).map(|i| ((i as f64) * 0.37 + offset).sin()).collect()
+    }
+
+    /// Checks the portable kernels and whichever kernels `Scalar` selects for `T`.
+    fn check_kernels<T: crate::scalar::Float>(tolerance: f64) {
+        type Dot<T> = fn(&[T], &[T]) -> T;
+        type Dot4<T> = fn(&[T], [&[T]; 4]) -> [T; 4];
+        let kernels: [(Dot<T>, Dot4<T>); 2] = [(portable::dot, portable::dot4), (T::dot, T::dot4)];
+        for (dot, dot4) in kernels {
+            for len in [0, 1, 3, 4, 5, 9, 17, 64, 257] {
+                let a = values(len, 0.0);
+                let b: Vec<Vec<f64>> = (0..4).map(|r| values(len, r as f64 + 1.0)).collect();
+                let expected: Vec<f64> = b.iter().map(|row| scalar_dot(&a, row)).collect();
+                let convert = |v: &[f64]| v.iter().map(|&x| T::from_f64(x)).collect::<Vec<T>>();
+                let a = convert(&a);
+                let b: Vec<Vec<T>> = b.iter().map(|row| convert(row)).collect();
+                assert!((dot(&a, &b[0]).to_f64() - expected[0]).abs() < tolerance);
+                let sums = dot4(&a, [&b[0], &b[1], &b[2], &b[3]]);
+                for (sum, expected) in sums.iter().zip(&expected) {
+                    assert!((sum.to_f64() - expected).abs() < tolerance);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_kernels_match_scalar_dot() {
+        check_kernels::<f64>(1e-9);
+        check_kernels::<f32>(1e-3);
+    }
+
+    #[test]
+    fn test_integer_kernel() {
+        let a: Vec<i64> = (0..19).collect();
+        let b: Vec<i64> = (0..19).map(|x| 2 * x - 5).collect();
+        let expected: i64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+        assert_eq!(<i64 as Scalar>::dot(&a, &b), expected);
+    }
+
+    #[test]
+    fn test_nested_packing() {
+        let sum = with_packing::<f64, _>(4, 2, |a, b| {
+            a.fill(1.0);
+            b.fill(2.0);
+            // A nested call gets buffers of its own instead of a second borrow of the same ones.
+            let inner = with_packing::<f64, _>(3, 3, |a, b| {
+                a.fill(3.0);
+                b.fill(4.0);
+                a.iter().chain(b.iter()).sum::<f64>()
+            });
+            inner + a.iter().chain(b.iter()).sum::<f64>()
+        });
+        assert_eq!(sum, 21.0 + 8.0);
+        with_packing::<f64, _>(4, 3, |a, b| assert!(a.len() == 4 && b.len() == 3));
+    }
+
+    #[test]
+    fn test_pack_transposed() {
+        let source: Vec<f64> = (0..35).map(|i| i as f64).collect();
+        let mut target = vec![0.0; 35];
+        pack_transposed(&source, 5, 7, &mut target);
+        for i in 0..5 {
+            for j in 0..7 {
+                assert_eq!(target[j * 5 + i], source[i * 7 + j]);
+            }
+        }
+    }
+}
@@ -1,2 +1,8 @@
+pub mod error;
+pub mod inplace;
+mod kernels;
 pub mod macros;
-pub mod matrix;
\ No newline at end of file
+pub mod matrix;
+pub mod ops;
+pub mod parallel;
+pub mod scalar;
\ No newline at end of file
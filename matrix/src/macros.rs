@@ -5,7 +5,7 @@ use crate::matrix::Matrix;  // context, but not directly used, so results in a c
 macro_rules! matrix {
     ( $( $($val:expr),+ );* $(;)? ) => {
         {
-            let mut data = Vec::<f64>::new();
+            let mut data = Vec::new();
             let mut rows = 0;
             let mut cols = 0;
             $(
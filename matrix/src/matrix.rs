@@ -1,19 +1,25 @@
+use crate::error::MatrixError;
+use crate::parallel;
+use crate::scalar::{Float, Scalar};
 use rand::Rng;
 use std::fmt;
 
 #[derive(Debug, Clone)]
-/// A matrix data structure that stores a 2D array of floating-point values.
+/// A matrix data structure that stores a 2D array of numeric values.
+///
+/// The element type `T` can be any `Scalar` (`f32`, `f64` or a primitive integer) and defaults
+/// to `f64`. Operations that need floating-point arithmetic, such as `random`, require `T: Float`.
 ///
 /// The `Matrix` struct has three public fields:
 /// - `rows`: the number of rows in the matrix
 /// - `cols`: the number of columns in the matrix
-/// - `data`: a `Vec<f64>` that stores the matrix elements in row-major order
-pub struct Matrix {
+/// - `data`: a `Vec<T>` that stores the matrix elements in row-major order
+pub struct Matrix<T = f64> {
     pub rows: usize,
     pub cols: usize,
-    pub data: Vec<f64>,
+    pub data: Vec<T>,
 }
-impl Matrix {
+impl<T: Scalar> Matrix<T> {
     // // Debugging function, helps to see the calling function, I used it to find where the matrices were created
     // pub fn get_caller_function_name() -> String {
     //     let mut s = String::new();
@@ -34,41 +40,45 @@ impl Matrix {
     ///
     /// # Panics
     /// Panics if the dimensions of the current matrix and the provided matrix do not match.
-    pub fn elementwise_multiply(&self, other: &Matrix) -> Matrix {
-        if self.rows != other.rows || self.cols != other.cols {
-            panic!("Attempted to multiply by matrix of incorrect dimensions");
-        }
-        let mut result_data = vec![0.0; self.cols * self.rows];
-        for (i, &value) in self.data.iter().enumerate() {
-            result_data[i] = value * other.data[i]
-        }
-        Matrix {
+    /// See `try_elementwise_multiply` for a non-panicking version.
+    pub fn elementwise_multiply(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_elementwise_multiply(other)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Performs element-wise multiplication between the current matrix and the provided matrix.
+    ///
+    /// # Arguments
+    /// * `other` - The matrix to multiply element-wise with the current matrix.
+    ///
+    /// # Returns
+    /// A new `Matrix` instance containing the result of the element-wise multiplication,
+    /// or `MatrixError::ShapeMismatch` if the dimensions of the two matrices do not match.
+    pub fn try_elementwise_multiply(&self, other: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.check_same_shape("elementwise multiply", other)?;
+        let result_data = parallel::zip_map(&self.data, &other.data, |x, y| x * y);
+        Ok(Matrix {
             rows: self.rows,
             cols: self.cols,
             data: result_data,
-        }
+        })
     }
 
-    /// Creates a new `Matrix` instance the given number of rows and columns.
-    /// Where each value is randomly generated.
+    /// Creates a new `Matrix` instance with the given number of rows, columns, and data.
     ///
     /// # Arguments
     /// * `rows` - The number of rows in the matrix.
     /// * `cols` - The number of columns in the matrix.
+    /// * `data` - A vector containing the data for the matrix.
+    ///
+    /// # Panics
+    /// Panics if the matrix would be empty, or if the length of the `data` vector does not match the expected size of the matrix.
+    /// See `try_new` for a non-panicking version.
     ///
     /// # Returns
-    /// A new `Matrix` instance with random values.
-    pub fn random(rows: usize, cols: usize) -> Matrix {
-        let mut buffer = Vec::<f64>::with_capacity(rows * cols);
-        for _ in 0..buffer.capacity() {
-            let num = rand::thread_rng().gen_range(0.0..1.0);
-            buffer.push(num);
-        }
-        Matrix {
-            rows,
-            cols,
-            data: buffer,
-        }
+    /// A new `Matrix` instance with the specified rows, columns, and data.
+    pub fn new(rows: usize, cols: usize, data: Vec<T>) -> Matrix<T> {
+        Matrix::try_new(rows, cols, data).unwrap_or_else(|error| panic!("{}", error))
     }
 
     /// Creates a new `Matrix` instance with the given number of rows, columns, and data.
@@ -76,29 +86,37 @@ impl Matrix {
     /// # Arguments
     /// * `rows` - The number of rows in the matrix.
     /// * `cols` - The number of columns in the matrix.
-    /// * `data` - A vector containing the data for the matrix.
-    ///
-    /// # Panics
-    /// Panics if the length of the `data` vector does not match the expected size of the matrix.
+    /// * `data` - A vector containing the data for the matrix, in row-major order.
     ///
     /// # Returns
-    /// A new `Matrix` instance with the specified rows, columns, and data.
-    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Matrix {
-        assert!(data.len() - 1 != rows * cols, "Invalid Size");
-        Matrix { rows, cols, data }
+    /// A new `Matrix` instance with the specified rows, columns, and data, or
+    /// - `MatrixError::EmptyMatrix` if `rows` or `cols` is zero.
+    /// - `MatrixError::InvalidDataLength` if `data.len()` is not `rows * cols`.
+    pub fn try_new(rows: usize, cols: usize, data: Vec<T>) -> Result<Matrix<T>, MatrixError> {
+        if rows == 0 || cols == 0 {
+            return Err(MatrixError::EmptyMatrix { rows, cols });
+        }
+        if data.len() != rows * cols {
+            return Err(MatrixError::InvalidDataLength {
+                rows,
+                cols,
+                len: data.len(),
+            });
+        }
+        Ok(Matrix { rows, cols, data })
     }
 
     /// CCreates a new `Matrix` instance with the given number of rows and columns.
-    /// Where all elements are initialized to 0.0.
+    /// Where all elements are initialized to zero.
     ///
     /// # Arguments
     /// * `rows` - The number of rows in the matrix.
     /// * `cols` - The number of columns in the matrix.
     ///
     /// # Returns
-    /// A new `Matrix` instance with all elements set to 0.0.
-    pub fn zeros(rows: usize, cols: usize) -> Matrix {
-        let buffer: Vec<f64> = vec![0.0; rows * cols];
+    /// A new `Matrix` instance with all elements set to zero.
+    pub fn zeros(rows: usize, cols: usize) -> Matrix<T> {
+        let buffer: Vec<T> = vec![T::zero(); rows * cols];
         Matrix {
             rows,
             cols,
@@ -114,23 +132,31 @@ impl Matrix {
     ///
     /// # Panics
     /// Panics if the dimensions of the two matrices do not match.
+    /// See `try_add` for a non-panicking version.
     ///
     /// # Returns
     /// A new `Matrix` instance with the result of adding the two input matrices.
-    pub fn add(&self, other: &Matrix) -> Matrix {
-        if self.rows != other.rows || self.cols != other.cols {
-            panic!("Attempted to add matrix of incorrect dimensions");
-        }
-        let mut buffer: Vec<f64> = Vec::<f64>::with_capacity(self.rows * self.cols);
-        for i in 0..self.data.len() {
-            let result = self.data[i] + other.data[i];
-            buffer.push(result);
-        }
-        Matrix {
+    pub fn add(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_add(other).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Adds two matrices together and returns a new matrix with the result.
+    ///
+    /// # Arguments
+    /// * `self` - The first matrix to add.
+    /// * `other` - The second matrix to add.
+    ///
+    /// # Returns
+    /// A new `Matrix` instance with the result of adding the two input matrices,
+    /// or `MatrixError::ShapeMismatch` if the dimensions of the two matrices do not match.
+    pub fn try_add(&self, other: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.check_same_shape("add", other)?;
+        let buffer = parallel::zip_map(&self.data, &other.data, |x, y| x + y);
+        Ok(Matrix {
             rows: self.rows,
             cols: self.cols,
             data: buffer,
-        }
+        })
     }
 
     /// Subtracts two matrices and returns a new matrix with the result.
@@ -141,24 +167,31 @@ impl Matrix {
     ///
     /// # Panics
     /// Panics if the dimensions of the two matrices do not match.
+    /// See `try_subtract` for a non-panicking version.
     ///
     /// # Returns
     /// A new `Matrix` instance with the result of subtracting the two input matrices.
-    pub fn subtract(&self, other: &Matrix) -> Matrix {
-        assert!(
-            self.rows == other.rows && self.cols == other.cols,
-            "Cannot subtract matrices with different dimensions"
-        );
-        let mut buffer: Vec<f64> = Vec::<f64>::with_capacity(self.rows * self.cols);
-        for i in 0..self.data.len() {
-            let result = self.data[i] - other.data[i];
-            buffer.push(result);
-        }
-        Matrix {
+    pub fn subtract(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_subtract(other).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Subtracts two matrices and returns a new matrix with the result.
+    ///
+    /// # Arguments
+    /// * `self` - The first matrix to subtract.
+    /// * `other` - The second matrix to subtract.
+    ///
+    /// # Returns
+    /// A new `Matrix` instance with the result of subtracting the two input matrices,
+    /// or `MatrixError::ShapeMismatch` if the dimensions of the two matrices do not match.
+    pub fn try_subtract(&self, other: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.check_same_shape("subtract", other)?;
+        let buffer = parallel::zip_map(&self.data, &other.data, |x, y| x - y);
+        Ok(Matrix {
             rows: self.rows,
             cols: self.cols,
             data: buffer,
-        }
+        })
     }
 
     /// Computes the dot product of two matrices and returns a new matrix with the result.
@@ -169,22 +202,59 @@ impl Matrix {
     ///
     /// # Panics
     /// Panics if the number of columns in the first matrix does not match the number of rows in the second matrix.
+    /// See `try_dot_multiply` for a non-panicking version.
     ///
     /// # Returns
     /// A new `Matrix` instance with the result of the dot product of the two input matrices.
-    pub fn dot_multiply(&self, other: &Matrix) -> Matrix {
+    pub fn dot_multiply(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_dot_multiply(other)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Computes the dot product of two matrices and returns a new matrix with the result.
+    ///
+    /// # Arguments
+    /// * `self` - The first matrix.
+    /// * `other` - The second matrix.
+    ///
+    /// Large products use a cache-blocked, SIMD-accelerated kernel (see `gemm_into`).
+    ///
+    /// # Returns
+    /// A new `Matrix` instance with the result of the dot product of the two input matrices,
+    /// or `MatrixError::DimensionMismatch` if the number of columns in the first matrix does not
+    /// match the number of rows in the second matrix.
+    pub fn try_dot_multiply(&self, other: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
         // // for debugging, print function name and args passed, the the call details:
         // log_vars!(self, other);
         // println!("{}",Self::get_caller_function_name());
         // // for debugging, print size of the self and other matrices
         // println!("Matrix Sizes - self.rows: {}, self.cols: {}, other.rows: {}, other.cols: {}", self.rows, self.cols, other.rows, other.cols);
+        let mut result = Matrix::zeros(self.rows, other.cols);
+        Matrix::try_gemm_into(&mut result, self, other, T::one(), T::zero())?;
+        Ok(result)
+    }
+
+    /// Computes the dot product of two matrices with a straightforward triple loop.
+    ///
+    /// This is the original, unblocked implementation. It is kept as a reference to validate the
+    /// blocked kernel behind `dot_multiply` against, and as a baseline for benchmarks.
+    ///
+    /// # Panics
+    /// Panics if the number of columns in the first matrix does not match the number of rows in the second matrix.
+    pub fn dot_multiply_naive(&self, other: &Matrix<T>) -> Matrix<T> {
         if self.cols != other.rows {
-            panic!("Attempted to multiply by matrix of incorrect dimensions");
+            panic!(
+                "{}",
+                MatrixError::DimensionMismatch {
+                    left: self.shape(),
+                    right: other.shape(),
+                }
+            );
         }
-        let mut result_data: Vec<f64> = vec![0.0; self.rows * other.cols];
+        let mut result_data: Vec<T> = vec![T::zero(); self.rows * other.cols];
         for i in 0..self.rows {
             for j in 0..other.cols {
-                let mut sum = 0.0;
+                let mut sum = T::zero();
                 for k in 0..self.cols {
                     sum += self.data[i * self.cols + k] * other.data[k * other.cols + j];
                 }
@@ -202,13 +272,14 @@ impl Matrix {
     ///
     /// # Returns
     /// A new `Matrix` instance with the transpose of the input matrix.
-    pub fn transpose(&self) -> Matrix {
-        let mut buffer: Vec<f64> = vec![0.0; self.rows * self.cols];
-        for i in 0..self.rows {
-            for j in 0..self.cols {
-                buffer[j * self.rows + i] = self.data[i * self.cols + j];
+    pub fn transpose(&self) -> Matrix<T> {
+        let mut buffer: Vec<T> = vec![T::zero(); self.rows * self.cols];
+        // Each row of the result is a column of the original, so rows can be filled independently.
+        parallel::for_each_chunk_mut(&mut buffer, self.rows, self.data.len(), |j, row| {
+            for (i, value) in row.iter_mut().enumerate() {
+                *value = self.data[i * self.cols + j];
             }
-        }
+        });
         Matrix {
             rows: self.cols,
             cols: self.rows,
@@ -219,33 +290,107 @@ impl Matrix {
     /// Applies the given function `func` to each element of the matrix and returns a new matrix with the transformed values.
     ///
     /// # Parameters
-    /// - `func`: A closure that takes a reference to a `f64` value and returns a new `f64` value.
+    /// - `func`: A closure that takes a reference to a `T` value and returns a new `T` value.
+    ///   It must be `Sync` so that large matrices can be mapped in parallel.
     ///
     /// # Returns
     /// A new `Matrix` instance with the same dimensions as the original matrix, but with each element transformed by the provided function.
-    pub fn map<F>(&mut self, func: F) -> Matrix
+    pub fn map<F>(&mut self, func: F) -> Matrix<T>
     where
-        F: Fn(&f64) -> f64,
+        F: Fn(&T) -> T + Sync,
     {
-        let mut result = Matrix {
+        Matrix {
             rows: self.rows,
             cols: self.cols,
-            data: Vec::with_capacity(self.data.len()),
-        };
-        result.data.extend(self.data.iter().map(|&val| func(&val)));
-        result
+            data: parallel::map(&self.data, func),
+        }
+    }
+
+    /// Returns the dimensions of the matrix as a `(rows, cols)` tuple.
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Checks that `other` has the same dimensions as the current matrix.
+    ///
+    /// # Arguments
+    /// * `operation` - The name of the operation being attempted, used in the error.
+    /// * `other` - The matrix to compare against.
+    ///
+    /// # Returns
+    /// `Ok(())` if the dimensions match, otherwise `MatrixError::ShapeMismatch`.
+    pub(crate) fn check_same_shape(&self, operation: &'static str, other: &Matrix<T>) -> Result<(), MatrixError> {
+        if self.rows != other.rows || self.cols != other.cols {
+            return Err(MatrixError::ShapeMismatch {
+                operation,
+                left: self.shape(),
+                right: other.shape(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Panics with the `MatrixError` describing the mismatch if `other` differs in shape.
+    pub(crate) fn assert_same_shape(&self, operation: &'static str, other: &Matrix<T>) {
+        if let Err(error) = self.check_same_shape(operation, other) {
+            panic!("{}", error);
+        }
+    }
+}
+
+impl<T: Float> Matrix<T> {
+    /// Creates a new `Matrix` instance the given number of rows and columns.
+    /// Where each value is randomly generated.
+    ///
+    /// The values come from the thread-local random number generator, so they differ on every run.
+    /// Use `random_with` and a seeded generator for reproducible values.
+    ///
+    /// # Arguments
+    /// * `rows` - The number of rows in the matrix.
+    /// * `cols` - The number of columns in the matrix.
+    ///
+    /// # Returns
+    /// A new `Matrix` instance with random values.
+    pub fn random(rows: usize, cols: usize) -> Matrix<T> {
+        Matrix::random_with(&mut rand::thread_rng(), rows, cols)
+    }
+
+    /// Creates a new `Matrix` instance the given number of rows and columns.
+    /// Where each value is drawn uniformly from `[0, 1)` using the given random number generator.
+    ///
+    /// The values are drawn in row-major order, so a generator seeded with the same value always
+    /// produces the same matrix.
+    ///
+    /// # Arguments
+    /// * `rng` - The random number generator to draw the values from.
+    /// * `rows` - The number of rows in the matrix.
+    /// * `cols` - The number of columns in the matrix.
+    ///
+    /// # Returns
+    /// A new `Matrix` instance with random values.
+    pub fn random_with(rng: &mut impl Rng, rows: usize, cols: usize) -> Matrix<T> {
+        let mut buffer = Vec::<T>::with_capacity(rows * cols);
+        for _ in 0..buffer.capacity() {
+            let num = rng.gen_range(T::zero()..T::one());
+            buffer.push(num);
+        }
+        Matrix {
+            rows,
+            cols,
+            data: buffer,
+        }
     }
 }
 
-/// Converts a `Vec<f64>` into a `Matrix` with a single column.
+/// Converts a `Vec<T>` into a `Matrix` with a single column.
 ///
 /// # Parameters
-/// - `vec`: The `Vec<f64>` to convert.
+/// - `vec`: The `Vec<T>` to convert.
 ///
 /// # Returns
-/// A new `Matrix` instance with the same elements as the input `Vec<f64>`, and a single column.
-impl From<Vec<f64>> for Matrix {
-    fn from(vec: Vec<f64>) -> Self {
+/// A new `Matrix` instance with the same elements as the input `Vec<T>`, and a single column.
+impl<T> From<Vec<T>> for Matrix<T> {
+    fn from(vec: Vec<T>) -> Self {
         let rows = vec.len();
         let cols = 1;
         Matrix {
@@ -263,7 +408,7 @@ impl From<Vec<f64>> for Matrix {
 /// If the dimensions are equal, the method compares each element of the matrices. 
 /// If any element is not equal, the matrices are considered not equal. 
 /// If all elements are equal, the matrices are considered equal.
-impl PartialEq for Matrix {
+impl<T: PartialEq> PartialEq for Matrix<T> {
     fn eq(&self, other: &Self) -> bool {
         if self.rows != other.rows || self.cols != other.cols {
             return false;
@@ -282,7 +427,7 @@ impl PartialEq for Matrix {
 ///
 /// The `fmt` method iterates over the rows and columns of the matrix, writing each element to the provided `fmt::Formatter`. 
 /// Columns are separated by a tab character, and rows are separated by a newline character.
-impl fmt::Display for Matrix {
+impl<T: fmt::Display> fmt::Display for Matrix<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for row in 0..self.rows {
             for col in 0..self.cols {
@@ -309,6 +454,8 @@ impl fmt::Display for Matrix {
 /// - Adding two matrices
 /// - Transposing matrices of different dimensions
 /// - Applying a mapping function to each element of a matrix
+/// - Returning a `MatrixError` from the fallible `try_` operations
+/// - Comparing the blocked dot product against the naive reference on random shapes
 mod tests {
     use super::*;
     use crate::matrix;
@@ -326,6 +473,17 @@ mod tests {
         }
     }
     #[test]
+    fn test_random_with_seed_is_reproducible() {
+        use rand::SeedableRng;
+        let mut first = rand::rngs::StdRng::seed_from_u64(42);
+        let mut second = rand::rngs::StdRng::seed_from_u64(42);
+        let a: Matrix = Matrix::random_with(&mut first, 4, 5);
+        let b: Matrix = Matrix::random_with(&mut second, 4, 5);
+        assert_eq!(a, b);
+        let c: Matrix = Matrix::random_with(&mut first, 4, 5);
+        assert_ne!(a, c);
+    }
+    #[test]
     fn test_elementwise_multiply() {
         // Create two matrices for testing
         let matrix1 = Matrix::new(2, 2, vec![1.0, 2.0, 3.0, 4.0]);
@@ -442,4 +600,82 @@ mod tests {
         };
         assert_eq!(transformed, expected);
     }
+    #[test]
+    fn test_try_new_invalid_length() {
+        let result = Matrix::try_new(2, 2, vec![1.0, 2.0, 3.0]);
+        assert_eq!(
+            result,
+            Err(MatrixError::InvalidDataLength { rows: 2, cols: 2, len: 3 })
+        );
+    }
+    #[test]
+    fn test_try_new_empty() {
+        let result = Matrix::<f64>::try_new(0, 3, vec![]);
+        assert_eq!(result, Err(MatrixError::EmptyMatrix { rows: 0, cols: 3 }));
+    }
+    #[test]
+    #[should_panic(expected = "Invalid Size")]
+    fn test_new_invalid_length() {
+        let _ = Matrix::new(2, 3, vec![1.0, 2.0, 3.0, 4.0]);
+    }
+    #[test]
+    fn test_try_add_shape_mismatch() {
+        let a = matrix![1.0, 2.0;
+                        3.0, 4.0];
+        let b = matrix![1.0, 2.0, 3.0];
+        assert_eq!(
+            a.try_add(&b),
+            Err(MatrixError::ShapeMismatch { operation: "add", left: (2, 2), right: (1, 3) })
+        );
+    }
+    #[test]
+    fn test_try_elementwise_multiply_shape_mismatch() {
+        let a = matrix![1.0, 2.0];
+        let b = matrix![1.0; 2.0];
+        assert!(matches!(
+            a.try_elementwise_multiply(&b),
+            Err(MatrixError::ShapeMismatch { left: (1, 2), right: (2, 1), .. })
+        ));
+    }
+    #[test]
+    fn test_try_dot_multiply() {
+        let a = matrix![1.0, 2.0;
+                        3.0, 4.0];
+        let b = matrix![1.0; 1.0];
+        assert_eq!(a.try_dot_multiply(&b), Ok(matrix![3.0; 7.0]));
+        assert_eq!(
+            a.try_dot_multiply(&matrix![1.0, 1.0]),
+            Err(MatrixError::DimensionMismatch { left: (2, 2), right: (1, 2) })
+        );
+    }
+    #[test]
+    fn test_dot_multiply_matches_naive_random_shapes() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let rows = rng.gen_range(1..150);
+            let shared = rng.gen_range(1..150);
+            let cols = rng.gen_range(1..150);
+            let a: Matrix = Matrix::random(rows, shared);
+            let b: Matrix = Matrix::random(shared, cols);
+            let result = a.dot_multiply(&b);
+            let expected = a.dot_multiply_naive(&b);
+            assert_eq!(result.shape(), expected.shape());
+            for (x, y) in result.data.iter().zip(&expected.data) {
+                assert!((x - y).abs() <= 1e-10 * y.abs().max(1.0), "{} != {}", x, y);
+            }
+        }
+    }
+    #[test]
+    fn test_generic_element_types() {
+        let a: Matrix<f32> = Matrix::new(2, 2, vec![1.0, 2.0, 3.0, 4.0]);
+        let b: Matrix<f32> = Matrix::new(2, 1, vec![0.5, 0.25]);
+        assert_eq!(a.dot_multiply(&b), Matrix::new(2, 1, vec![1.0, 2.5]));
+        let random: Matrix<f32> = Matrix::random(3, 3);
+        assert!(random.data.iter().all(|x| (0.0..1.0).contains(x)));
+
+        let c: Matrix<i64> = matrix![1, 2, 3;
+                                     4, 5, 6];
+        assert_eq!(c.dot_multiply(&c.transpose()), matrix![14, 32; 32, 77]);
+        assert_eq!(c.add(&c).subtract(&c), c);
+    }
 }
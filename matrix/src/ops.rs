@@ -0,0 +1,323 @@
+//! Operator overloading for `Matrix`.
+//!
+//! The operators let matrix code read like ordinary algebra:
+//! - `a + b`, `a - b` and `-a` are element-wise.
+//! - `a * b` between two matrices is the matrix (dot) product, the same as `Matrix::dot_multiply`.
+//! - `a * s` and `s * a` between a matrix and a scalar of its element type scale every element.
+//!   The scalar-on-the-left form is only available for the primitive numeric types.
+//!
+//! Every operator is implemented for both `Matrix` and `&Matrix`. When an owned `Matrix` is
+//! passed, its buffer is reused for the result so no new allocation is made; borrow the
+//! operands (`&a + &b`) when they are still needed afterwards.
+//!
+//! Like the panicking methods on `Matrix`, the operators panic when the dimensions do not match.
+//! Use the `try_` methods to get a `MatrixError` instead.
+use crate::matrix::Matrix;
+use crate::scalar::Scalar;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+impl<T: Scalar> AddAssign<&Matrix<T>> for Matrix<T> {
+    fn add_assign(&mut self, rhs: &Matrix<T>) {
+        Matrix::add_assign(self, rhs);
+    }
+}
+
+impl<T: Scalar> AddAssign<Matrix<T>> for Matrix<T> {
+    fn add_assign(&mut self, rhs: Matrix<T>) {
+        *self += &rhs;
+    }
+}
+
+impl<T: Scalar> SubAssign<&Matrix<T>> for Matrix<T> {
+    fn sub_assign(&mut self, rhs: &Matrix<T>) {
+        self.subtract_assign(rhs);
+    }
+}
+
+impl<T: Scalar> SubAssign<Matrix<T>> for Matrix<T> {
+    fn sub_assign(&mut self, rhs: Matrix<T>) {
+        *self -= &rhs;
+    }
+}
+
+/// Scales every element of the matrix by `rhs` in place.
+impl<T: Scalar> MulAssign<T> for Matrix<T> {
+    fn mul_assign(&mut self, rhs: T) {
+        self.scale_inplace(rhs);
+    }
+}
+
+/// Replaces the matrix with the matrix product `self * rhs`.
+impl<T: Scalar> MulAssign<&Matrix<T>> for Matrix<T> {
+    fn mul_assign(&mut self, rhs: &Matrix<T>) {
+        *self = Matrix::dot_multiply(self, rhs);
+    }
+}
+
+impl<T: Scalar> MulAssign<Matrix<T>> for Matrix<T> {
+    fn mul_assign(&mut self, rhs: Matrix<T>) {
+        *self *= &rhs;
+    }
+}
+
+impl<T: Scalar> Add<&Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn add(mut self, rhs: &Matrix<T>) -> Matrix<T> {
+        self += rhs;
+        self
+    }
+}
+
+impl<T: Scalar> Add<Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn add(mut self, rhs: Matrix<T>) -> Matrix<T> {
+        self += &rhs;
+        self
+    }
+}
+
+impl<T: Scalar> Add<Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn add(self, mut rhs: Matrix<T>) -> Matrix<T> {
+        // Addition is commutative, so the right-hand buffer can be reused.
+        rhs += self;
+        rhs
+    }
+}
+
+impl<T: Scalar> Add<&Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn add(self, rhs: &Matrix<T>) -> Matrix<T> {
+        Matrix::add(self, rhs)
+    }
+}
+
+impl<T: Scalar> Sub<&Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn sub(mut self, rhs: &Matrix<T>) -> Matrix<T> {
+        self -= rhs;
+        self
+    }
+}
+
+impl<T: Scalar> Sub<Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn sub(mut self, rhs: Matrix<T>) -> Matrix<T> {
+        self -= &rhs;
+        self
+    }
+}
+
+impl<T: Scalar> Sub<Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn sub(self, mut rhs: Matrix<T>) -> Matrix<T> {
+        self.assert_same_shape("subtract", &rhs);
+        for (value, other) in rhs.data.iter_mut().zip(&self.data) {
+            *value = *other - *value;
+        }
+        rhs
+    }
+}
+
+impl<T: Scalar> Sub<&Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn sub(self, rhs: &Matrix<T>) -> Matrix<T> {
+        Matrix::subtract(self, rhs)
+    }
+}
+
+impl<T: Scalar + Neg<Output = T>> Neg for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn neg(mut self) -> Matrix<T> {
+        for value in self.data.iter_mut() {
+            *value = -*value;
+        }
+        self
+    }
+}
+
+impl<T: Scalar + Neg<Output = T>> Neg for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn neg(self) -> Matrix<T> {
+        -self.clone()
+    }
+}
+
+/// The matrix product of two matrices, the same as `Matrix::dot_multiply`.
+impl<T: Scalar> Mul<&Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, rhs: &Matrix<T>) -> Matrix<T> {
+        Matrix::dot_multiply(self, rhs)
+    }
+}
+
+impl<T: Scalar> Mul<Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, rhs: Matrix<T>) -> Matrix<T> {
+        Matrix::dot_multiply(self, &rhs)
+    }
+}
+
+impl<T: Scalar> Mul<&Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, rhs: &Matrix<T>) -> Matrix<T> {
+        Matrix::dot_multiply(&self, rhs)
+    }
+}
+
+impl<T: Scalar> Mul<Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, rhs: Matrix<T>) -> Matrix<T> {
+        Matrix::dot_multiply(&self, &rhs)
+    }
+}
+
+impl<T: Scalar> Mul<T> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(mut self, rhs: T) -> Matrix<T> {
+        self *= rhs;
+        self
+    }
+}
+
+impl<T: Scalar> Mul<T> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, rhs: T) -> Matrix<T> {
+        self.clone() * rhs
+    }
+}
+
+/// Implements `scalar * matrix` for each primitive type, which cannot be done generically.
+macro_rules! impl_scalar_left_mul {
+    ($($t:ty),*) => {
+        $(
+            impl Mul<Matrix<$t>> for $t {
+                type Output = Matrix<$t>;
+
+                fn mul(self, rhs: Matrix<$t>) -> Matrix<$t> {
+                    rhs * self
+                }
+            }
+
+            impl Mul<&Matrix<$t>> for $t {
+                type Output = Matrix<$t>;
+
+                fn mul(self, rhs: &Matrix<$t>) -> Matrix<$t> {
+                    self * rhs.clone()
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_left_mul!(f32, f64, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+#[cfg(test)]
+/// Tests for the operator implementations on `Matrix`.
+///
+/// Each operator is checked against the equivalent named method, for both owned and borrowed operands.
+mod tests {
+    use crate::matrix;
+    use crate::matrix::Matrix;
+
+    fn a() -> Matrix<f64> {
+        matrix![1.0, 2.0;
+                3.0, 4.0]
+    }
+
+    fn b() -> Matrix<f64> {
+        matrix![5.0, 6.0;
+                7.0, 8.0]
+    }
+
+    #[test]
+    fn test_add_operator() {
+        let expected = a().add(&b());
+        assert_eq!(&a() + &b(), expected);
+        assert_eq!(a() + &b(), expected);
+        assert_eq!(&a() + b(), expected);
+        assert_eq!(a() + b(), expected);
+    }
+
+    #[test]
+    fn test_sub_operator() {
+        let expected = a().subtract(&b());
+        assert_eq!(&a() - &b(), expected);
+        assert_eq!(a() - &b(), expected);
+        assert_eq!(&a() - b(), expected);
+        assert_eq!(a() - b(), expected);
+    }
+
+    #[test]
+    fn test_mul_operator_is_dot_product() {
+        let expected = a().dot_multiply(&b());
+        assert_eq!(&a() * &b(), expected);
+        assert_eq!(a() * &b(), expected);
+        assert_eq!(&a() * b(), expected);
+        assert_eq!(a() * b(), expected);
+    }
+
+    #[test]
+    fn test_scalar_mul_operator() {
+        let expected = matrix![2.0, 4.0;
+                               6.0, 8.0];
+        assert_eq!(a() * 2.0, expected);
+        assert_eq!(&a() * 2.0, expected);
+        assert_eq!(2.0 * a(), expected);
+        assert_eq!(2.0 * &a(), expected);
+    }
+
+    #[test]
+    fn test_neg_operator() {
+        let expected = matrix![-1.0, -2.0;
+                               -3.0, -4.0];
+        assert_eq!(-a(), expected);
+        assert_eq!(-&a(), expected);
+    }
+
+    #[test]
+    fn test_assign_operators() {
+        let mut m = a();
+        m += &b();
+        assert_eq!(m, a().add(&b()));
+        m -= b();
+        assert_eq!(m, a());
+        m *= 3.0;
+        assert_eq!(m, a() * 3.0);
+        m *= &b();
+        assert_eq!(m, (a() * 3.0).dot_multiply(&b()));
+    }
+
+    #[test]
+    fn test_integer_operators() {
+        let a: Matrix<i32> = matrix![1, 2;
+                                     3, 4];
+        let b: Matrix<i32> = matrix![5, 6;
+                                     7, 8];
+        assert_eq!(&a + &b, matrix![6, 8; 10, 12]);
+        assert_eq!(&a * &b, matrix![19, 22; 43, 50]);
+        assert_eq!(2 * -a, matrix![-2, -4; -6, -8]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot add matrices with different dimensions")]
+    fn test_add_operator_different_dimensions() {
+        let _ = a() + matrix![1.0, 2.0, 3.0];
+    }
+}
@@ -0,0 +1,134 @@
+//! Optional multi-threaded execution of `Matrix` operations.
+//!
+//! When the crate is built with the `parallel` feature, large element-wise operations
+//! (`add`, `subtract`, `elementwise_multiply`, `map`), `transpose` and the blocked matrix
+//! product are split across the rayon thread pool. Small operations always run on the calling
+//! thread, because handing work to other threads costs more than it saves.
+//!
+//! The cut-off is the amount of scalar work an operation does: the number of elements for
+//! element-wise operations and `transpose`, and the number of multiply-adds (`m * n * k`) for
+//! matrix products. It defaults to `DEFAULT_THRESHOLD` and can be changed at runtime with
+//! `set_threshold`.
+//!
+//! Every element of the result is computed by exactly the same arithmetic as on the serial
+//! path, so results are bit-identical with and without the feature.
+//!
+//! Without the `parallel` feature these functions still exist, so callers do not need to
+//! `cfg` their configuration code, but every operation runs serially.
+use crate::scalar::Scalar;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// The default amount of scalar work above which an operation runs in parallel.
+pub const DEFAULT_THRESHOLD: usize = 1 << 16;
+
+static THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD);
+
+/// Sets the amount of scalar work above which operations run in parallel.
+///
+/// # Arguments
+/// * `work` - The new threshold. `0` parallelizes every operation, `usize::MAX` none.
+pub fn set_threshold(work: usize) {
+    THRESHOLD.store(work, Ordering::Relaxed);
+}
+
+/// Returns the amount of scalar work above which operations run in parallel.
+pub fn threshold() -> usize {
+    THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if the crate was built with the `parallel` feature.
+pub fn is_enabled() -> bool {
+    cfg!(feature = "parallel")
+}
+
+/// Returns `true` if an operation doing `work` scalar operations should run in parallel.
+pub(crate) fn should_parallelize(work: usize) -> bool {
+    is_enabled() && work >= threshold()
+}
+
+/// Combines two slices element by element into a new vector.
+pub(crate) fn zip_map<T, F>(a: &[T], b: &[T], func: F) -> Vec<T>
+where
+    T: Scalar,
+    F: Fn(T, T) -> T + Sync,
+{
+    if should_parallelize(a.len()) {
+        #[cfg(feature = "parallel")]
+        return a.par_iter().zip(b.par_iter()).map(|(&x, &y)| func(x, y)).collect();
+    }
+    a.iter().zip(b).map(|(&x, &y)| func(x, y)).collect()
+}
+
+/// Applies `func` to each element of a slice, collecting the results into a new vector.
+pub(crate) fn map<T, F>(a: &[T], func: F) -> Vec<T>
+where
+    T: Scalar,
+    F: Fn(&T) -> T + Sync,
+{
+    if should_parallelize(a.len()) {
+        #[cfg(feature = "parallel")]
+        return a.par_iter().map(&func).collect();
+    }
+    a.iter().map(func).collect()
+}
+
+/// Calls `func` with each row-sized chunk of `data` and its index.
+///
+/// # Arguments
+/// * `data` - The buffer to split.
+/// * `chunk_len` - The length of each chunk; the last chunk may be shorter.
+/// * `work` - The total amount of scalar work, compared against the threshold.
+/// * `func` - Called with the index of the chunk and the chunk itself.
+pub(crate) fn for_each_chunk_mut<T, F>(data: &mut [T], chunk_len: usize, work: usize, func: F)
+where
+    T: Scalar,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    if chunk_len == 0 {
+        return;
+    }
+    if should_parallelize(work) {
+        #[cfg(feature = "parallel")]
+        return data
+            .par_chunks_mut(chunk_len)
+            .enumerate()
+            .for_each(|(index, chunk)| func(index, chunk));
+    }
+    data.chunks_mut(chunk_len)
+        .enumerate()
+        .for_each(|(index, chunk)| func(index, chunk));
+}
+
+#[cfg(all(test, feature = "parallel"))]
+/// Tests that the parallel paths give bit-identical results to the serial ones.
+///
+/// The threshold is global, so the tests only change it around their own computations and
+/// restore the previous value afterwards; any other test running at the same time is still
+/// correct, whether it runs serially or in parallel.
+mod tests {
+    use crate::matrix::Matrix;
+
+    /// Runs `func` with the parallel threshold set to `threshold`, then restores the previous one.
+    fn with_threshold<R>(threshold: usize, func: impl FnOnce() -> R) -> R {
+        let previous = super::threshold();
+        super::set_threshold(threshold);
+        let result = func();
+        super::set_threshold(previous);
+        result
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let a: Matrix<f64> = Matrix::random(300, 257);
+        let b = Matrix::random(300, 257);
+        let c = Matrix::random(257, 130);
+        let compute = || (a.add(&b), a.clone().map(|x| x.sin()), a.transpose(), a.dot_multiply(&c));
+        let expected = with_threshold(usize::MAX, compute);
+        let previous = super::threshold();
+        assert_eq!(with_threshold(0, compute), expected);
+        assert_eq!(super::threshold(), previous);
+    }
+}
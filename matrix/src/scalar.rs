@@ -0,0 +1,182 @@
+//! The numeric traits that a `Matrix` element type must implement.
+//!
+//! - `Scalar` is the minimum needed for the arithmetic operations (`add`, `subtract`,
+//!   `elementwise_multiply`, `dot_multiply`, ...). It is implemented for `f32`, `f64` and the
+//!   primitive integer types.
+//! - `Float` adds the transcendental functions and conversions that neural networks need
+//!   (activation functions, random initialization, learning rates). It is implemented for
+//!   `f32` and `f64`.
+//!
+//! `Scalar::dot` and `Scalar::dot4` are the inner kernels of the blocked matrix product. The
+//! default implementations are portable; `f32` and `f64` override them with AVX2 + FMA kernels
+//! when the running CPU supports them.
+use crate::kernels;
+use rand::distributions::uniform::SampleUniform;
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// A numeric type that can be stored in a `Matrix`.
+pub trait Scalar:
+    Copy
+    + Debug
+    + Display
+    + PartialEq
+    + PartialOrd
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + Sum
+{
+    /// The additive identity.
+    fn zero() -> Self;
+
+    /// The multiplicative identity.
+    fn one() -> Self;
+
+    /// Computes the dot product of two slices of equal length.
+    fn dot(a: &[Self], b: &[Self]) -> Self {
+        kernels::portable::dot(a, b)
+    }
+
+    /// Computes the dot product of `a` with each of the four slices in `b`.
+    fn dot4(a: &[Self], b: [&[Self]; 4]) -> [Self; 4] {
+        kernels::portable::dot4(a, b)
+    }
+}
+
+/// A floating-point `Scalar`, with the functions needed by neural networks.
+pub trait Float: Scalar + Neg<Output = Self> + SampleUniform {
+    /// Converts an `f64` to this type, rounding if necessary.
+    fn from_f64(value: f64) -> Self;
+    /// Converts this value to an `f64`.
+    fn to_f64(self) -> f64;
+    /// Returns `e` raised to the power of `self`.
+    fn exp(self) -> Self;
+    /// Returns the natural logarithm of `self`.
+    fn ln(self) -> Self;
+    /// Returns the square root of `self`.
+    fn sqrt(self) -> Self;
+    /// Returns `self` raised to the power of `exponent`.
+    fn powf(self, exponent: Self) -> Self;
+    /// Returns the hyperbolic tangent of `self`.
+    fn tanh(self) -> Self;
+    /// Returns the absolute value of `self`.
+    fn abs(self) -> Self;
+    /// Returns the larger of `self` and `other`.
+    fn max(self, other: Self) -> Self;
+    /// Returns the smaller of `self` and `other`.
+    fn min(self, other: Self) -> Self;
+    /// Returns `true` if `self` is neither infinite nor `NaN`.
+    fn is_finite(self) -> bool;
+    /// The difference between `1.0` and the next larger representable number.
+    fn epsilon() -> Self;
+}
+
+macro_rules! impl_scalar_int {
+    ($($t:ty),*) => {
+        $(
+            impl Scalar for $t {
+                fn zero() -> Self {
+                    0
+                }
+
+                fn one() -> Self {
+                    1
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_int!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+macro_rules! impl_float {
+    ($t:ident, $avx:ident) => {
+        impl Scalar for $t {
+            fn zero() -> Self {
+                0.0
+            }
+
+            fn one() -> Self {
+                1.0
+            }
+
+            fn dot(a: &[Self], b: &[Self]) -> Self {
+                #[cfg(target_arch = "x86_64")]
+                if kernels::has_avx2_fma() {
+                    return kernels::x86::$avx::dot(a, b);
+                }
+                kernels::portable::dot(a, b)
+            }
+
+            fn dot4(a: &[Self], b: [&[Self]; 4]) -> [Self; 4] {
+                #[cfg(target_arch = "x86_64")]
+                if kernels::has_avx2_fma() {
+                    return kernels::x86::$avx::dot4(a, b);
+                }
+                kernels::portable::dot4(a, b)
+            }
+        }
+
+        impl Float for $t {
+            fn from_f64(value: f64) -> Self {
+                value as $t
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn exp(self) -> Self {
+                $t::exp(self)
+            }
+
+            fn ln(self) -> Self {
+                $t::ln(self)
+            }
+
+            fn sqrt(self) -> Self {
+                $t::sqrt(self)
+            }
+
+            fn powf(self, exponent: Self) -> Self {
+                $t::powf(self, exponent)
+            }
+
+            fn tanh(self) -> Self {
+                $t::tanh(self)
+            }
+
+            fn abs(self) -> Self {
+                $t::abs(self)
+            }
+
+            fn max(self, other: Self) -> Self {
+                $t::max(self, other)
+            }
+
+            fn min(self, other: Self) -> Self {
+                $t::min(self, other)
+            }
+
+            fn is_finite(self) -> bool {
+                $t::is_finite(self)
+            }
+
+            fn epsilon() -> Self {
+                $t::EPSILON
+            }
+        }
+    };
+}
+
+impl_float!(f32, f32x8);
+impl_float!(f64, f64x4);
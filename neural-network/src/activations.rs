@@ -1,26 +1,292 @@
-use std::f64::consts::E;
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::{E, PI};
 
-#[derive(Clone,Copy,Debug)]
+/// The `alpha` constant of SELU.
+const SELU_ALPHA: f64 = 1.673_263_242_354_377_3;
+/// The `scale` constant of SELU.
+const SELU_SCALE: f64 = 1.050_700_987_355_480_5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 /// An activation function and its derivative.
 ///
 /// Activation functions are used in neural networks to introduce non-linearity
-/// into the model. The `function` field is the activation function itself, and
-/// the `derivative` field is the derivative of the activation function, which
-/// is used during backpropagation.
-pub struct Activation {
-    /// The activation function.
-    pub function: fn(&f64) -> f64,
-    /// The derivative of the activation function.
-    pub derivative: fn(&f64) -> f64,
+/// into the model. `apply` is the activation function itself, and `derivative`
+/// is its derivative with respect to the input of the function (the
+/// pre-activation), which is used during backpropagation.
+///
+/// Every activation except `Softmax` is applied to each neuron independently.
+/// `Softmax` normalizes over all the neurons of a layer, so it can only be used
+/// through `forward` and `backward`, which work on whole layers.
+pub enum Activation {
+    /// `1 / (1 + e^-x)`, mapping any input to `(0, 1)`.
+    Sigmoid,
+    /// `max(0, x)`.
+    Relu,
+    /// `x` for positive inputs, `alpha * x` otherwise.
+    LeakyRelu { alpha: f64 },
+    /// `x` for positive inputs, `alpha * (e^x - 1)` otherwise.
+    Elu { alpha: f64 },
+    /// The self-normalizing `scale * elu(x)` with the fixed `alpha` and `scale` of Klambauer et al.
+    Selu,
+    /// The hyperbolic tangent, mapping any input to `(-1, 1)`.
+    Tanh,
+    /// `ln(1 + e^x)`, a smooth approximation of ReLU.
+    Softplus,
+    /// The Gaussian error linear unit, using the `tanh` approximation.
+    Gelu,
+    /// Swish, also known as SiLU: `x * sigmoid(x)`.
+    Swish,
+    /// The piecewise linear `clamp(x / 6 + 1 / 2, 0, 1)`.
+    HardSigmoid,
+    /// The identity, for regression outputs.
+    Linear,
+    /// `e^x_i / sum_j e^x_j` over the neurons of a layer, for multi-class outputs.
+    Softmax,
+}
+
+/// A compatibility alias for `Activation::Sigmoid`.
+pub const SIGMOID: Activation = Activation::Sigmoid;
+
+impl Activation {
+    /// Returns `true` if the activation is applied to each neuron independently.
+    pub fn is_elementwise(&self) -> bool {
+        !matches!(self, Activation::Softmax)
+    }
+
+    /// Applies the activation function to a single value.
+    ///
+    /// # Panics
+    /// Panics for `Softmax`, which is not defined on a single value; use `forward` instead.
+    pub fn apply<T: Float>(&self, x: T) -> T {
+        let one = T::one();
+        match *self {
+            Activation::Sigmoid => sigmoid(x),
+            Activation::Relu => x.max(T::zero()),
+            Activation::LeakyRelu { alpha } => if x > T::zero() { x } else { T::from_f64(alpha) * x },
+            Activation::Elu { alpha } => elu(x, T::from_f64(alpha)),
+            Activation::Selu => T::from_f64(SELU_SCALE) * elu(x, T::from_f64(SELU_ALPHA)),
+            Activation::Tanh => x.tanh(),
+            // `max(x, 0) + ln(1 + e^-|x|)` does not overflow for large inputs.
+            Activation::Softplus => x.max(T::zero()) + (one + (-x.abs()).exp()).ln(),
+            Activation::Gelu => T::from_f64(0.5) * x * (one + gelu_inner(x).tanh()),
+            Activation::Swish => x * sigmoid(x),
+            Activation::HardSigmoid => (x / T::from_f64(6.0) + T::from_f64(0.5)).max(T::zero()).min(one),
+            Activation::Linear => x,
+            Activation::Softmax => panic!("Softmax is not an element-wise activation"),
+        }
+    }
+
+    /// Returns the derivative of the activation function at `x`.
+    ///
+    /// # Arguments
+    /// * `x` - The input of the activation function, not its output.
+    ///
+    /// # Panics
+    /// Panics for `Softmax`, which is not defined on a single value; use `backward` instead.
+    pub fn derivative<T: Float>(&self, x: T) -> T {
+        let (zero, one) = (T::zero(), T::one());
+        match *self {
+            Activation::Sigmoid => {
+                let s = sigmoid(x);
+                s * (one - s)
+            }
+            Activation::Relu => if x > zero { one } else { zero },
+            Activation::LeakyRelu { alpha } => if x > zero { one } else { T::from_f64(alpha) },
+            Activation::Elu { alpha } => if x > zero { one } else { T::from_f64(alpha) * x.exp() },
+            Activation::Selu => {
+                let slope = if x > zero { one } else { T::from_f64(SELU_ALPHA) * x.exp() };
+                T::from_f64(SELU_SCALE) * slope
+            }
+            Activation::Tanh => {
+                let t = x.tanh();
+                one - t * t
+            }
+            Activation::Softplus => sigmoid(x),
+            Activation::Gelu => {
+                let t = gelu_inner(x).tanh();
+                let inner_derivative =
+                    T::from_f64((2.0 / PI).sqrt()) * (one + T::from_f64(3.0 * 0.044715) * x * x);
+                T::from_f64(0.5) * (one + t) + T::from_f64(0.5) * x * (one - t * t) * inner_derivative
+            }
+            Activation::Swish => {
+                let s = sigmoid(x);
+                s + x * s * (one - s)
+            }
+            Activation::HardSigmoid => {
+                if x > T::from_f64(-3.0) && x < T::from_f64(3.0) {
+                    one / T::from_f64(6.0)
+                } else {
+                    zero
+                }
+            }
+            Activation::Linear => one,
+            Activation::Softmax => panic!("Softmax is not an element-wise activation"),
+        }
+    }
+
+    /// Applies the activation to a layer.
+    ///
+    /// Each column of `inputs` is one sample, so `Softmax` normalizes each column separately.
+    ///
+    /// # Arguments
+    /// * `inputs` - The pre-activations of the layer.
+    /// * `outputs` - The matrix to write the activations to, with the same shape as `inputs`.
+    pub fn forward<T: Float>(&self, inputs: &Matrix<T>, outputs: &mut Matrix<T>) {
+        outputs.copy_from_slice(&inputs.data);
+        if self.is_elementwise() {
+            let activation = *self;
+            outputs.map_inplace(move |&x| activation.apply(x));
+            return;
+        }
+        for col in 0..outputs.cols {
+            let column = (0..outputs.rows).map(|row| row * outputs.cols + col);
+            // Subtracting the largest input keeps `exp` from overflowing.
+            let largest = column.clone().map(|i| outputs.data[i]).fold(outputs.data[col], T::max);
+            let mut sum = T::zero();
+            for i in column.clone() {
+                outputs.data[i] = (outputs.data[i] - largest).exp();
+                sum += outputs.data[i];
+            }
+            for i in column {
+                outputs.data[i] = outputs.data[i] / sum;
+            }
+        }
+    }
+
+    /// Backpropagates a gradient through the activation of a layer.
+    ///
+    /// # Arguments
+    /// * `inputs` - The pre-activations of the layer.
+    /// * `outputs` - The activations of the layer, as computed by `forward`.
+    /// * `gradient` - The gradient with respect to the outputs, overwritten with the gradient
+    ///   with respect to the inputs.
+    pub fn backward<T: Float>(&self, inputs: &Matrix<T>, outputs: &Matrix<T>, gradient: &mut Matrix<T>) {
+        if self.is_elementwise() {
+            for (gradient, &x) in gradient.data.iter_mut().zip(&inputs.data) {
+                *gradient *= self.derivative(x);
+            }
+            return;
+        }
+        // The softmax Jacobian is `diag(y) - y yᵀ`, so the product with `g` is `y ∘ (g - y·g)`.
+        for col in 0..outputs.cols {
+            let column = (0..outputs.rows).map(|row| row * outputs.cols + col);
+            let projection: T = column.clone().map(|i| outputs.data[i] * gradient.data[i]).sum();
+            for i in column {
+                gradient.data[i] = outputs.data[i] * (gradient.data[i] - projection);
+            }
+        }
+    }
+}
+
+fn sigmoid<T: Float>(x: T) -> T {
+    T::one() / (T::one() + T::from_f64(E).powf(-x))
 }
 
-/// The sigmoid activation function and its derivative.
+fn elu<T: Float>(x: T, alpha: T) -> T {
+    if x > T::zero() {
+        x
+    } else {
+        alpha * (x.exp() - T::one())
+    }
+}
+
+/// The argument of `tanh` in the GELU approximation, `sqrt(2 / pi) * (x + 0.044715 x^3)`.
+fn gelu_inner<T: Float>(x: T) -> T {
+    T::from_f64((2.0 / PI).sqrt()) * (x + T::from_f64(0.044715) * x * x * x)
+}
+
+#[cfg(test)]
+/// Tests for the `Activation` functions.
 ///
-/// The sigmoid function is a common activation function used in neural networks.
-/// It maps any input value to a value between 0 and 1, making it useful for
-/// binary classification problems. The derivative of the sigmoid function is
-/// also provided, which is used during backpropagation.
-pub const SIGMOID: Activation = Activation {
-    function: |x| 1.0 / (1.0 + E.powf(-x)),
-    derivative: |x| x * (1.0 - x),
-};
\ No newline at end of file
+/// The tests cover:
+/// - Every derivative against central finite differences
+/// - Softmax outputs summing to one per column
+/// - The softmax backward pass against finite differences
+/// - Known values of the element-wise functions
+mod tests {
+    use super::*;
+
+    const ELEMENTWISE: [Activation; 11] = [
+        Activation::Sigmoid,
+        Activation::Relu,
+        Activation::LeakyRelu { alpha: 0.1 },
+        Activation::Elu { alpha: 1.5 },
+        Activation::Selu,
+        Activation::Tanh,
+        Activation::Softplus,
+        Activation::Gelu,
+        Activation::Swish,
+        Activation::HardSigmoid,
+        Activation::Linear,
+    ];
+
+    #[test]
+    fn test_derivatives_match_finite_differences() {
+        let epsilon = 1e-6;
+        // The points avoid the kinks of ReLU (0) and hard sigmoid (-3 and 3).
+        for activation in ELEMENTWISE {
+            for x in [-4.1, -2.5, -1.3, -0.4, 0.3, 0.9, 2.2, 3.7] {
+                let numeric = (activation.apply(x + epsilon) - activation.apply(x - epsilon)) / (2.0 * epsilon);
+                let analytic = activation.derivative(x);
+                assert!(
+                    (numeric - analytic).abs() < 1e-6,
+                    "{:?} at {}: {} vs {}",
+                    activation,
+                    x,
+                    analytic,
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_softmax_columns_sum_to_one() {
+        let inputs = Matrix::new(3, 2, vec![1.0, 1000.0, 2.0, 1001.0, 3.0, 999.0]);
+        let mut outputs = Matrix::zeros(3, 2);
+        Activation::Softmax.forward(&inputs, &mut outputs);
+        for col in 0..2 {
+            let sum: f64 = (0..3).map(|row| outputs.data[row * 2 + col]).sum();
+            assert!((sum - 1.0).abs() < 1e-12);
+        }
+        assert!((outputs.data[4] - E * E / (1.0 + E + E * E)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_softmax_backward_matches_finite_differences() {
+        let inputs = Matrix::new(4, 1, vec![0.3, -1.2, 2.0, 0.7]);
+        let upstream = [0.5, -1.0, 0.25, 2.0];
+        // The scalar whose gradient is checked is `sum_i upstream_i * softmax(x)_i`.
+        let objective = |inputs: &Matrix<f64>| {
+            let mut outputs = Matrix::zeros(4, 1);
+            Activation::Softmax.forward(inputs, &mut outputs);
+            outputs.data.iter().zip(&upstream).map(|(y, g)| y * g).sum::<f64>()
+        };
+        let mut outputs = Matrix::zeros(4, 1);
+        Activation::Softmax.forward(&inputs, &mut outputs);
+        let mut gradient = Matrix::from(upstream.to_vec());
+        Activation::Softmax.backward(&inputs, &outputs, &mut gradient);
+        let epsilon = 1e-6;
+        for i in 0..4 {
+            let mut plus = inputs.clone();
+            plus.data[i] += epsilon;
+            let mut minus = inputs.clone();
+            minus.data[i] -= epsilon;
+            let numeric = (objective(&plus) - objective(&minus)) / (2.0 * epsilon);
+            assert!((numeric - gradient.data[i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(Activation::Relu.apply(-2.0), 0.0);
+        assert_eq!(Activation::LeakyRelu { alpha: 0.1 }.apply(-2.0), -0.2);
+        assert_eq!(Activation::HardSigmoid.apply(10.0), 1.0);
+        assert_eq!(Activation::Linear.apply(-3.5), -3.5);
+        assert!((Activation::Sigmoid.apply(0.0f32) - 0.5).abs() < 1e-6);
+        assert!((Activation::Softplus.apply(1000.0) - 1000.0).abs() < 1e-9);
+        assert!((Activation::Selu.apply(1.0) - SELU_SCALE).abs() < 1e-12);
+    }
+}
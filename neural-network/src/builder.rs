@@ -0,0 +1,549 @@
+//! The configuration of a `Network`, and the builder used to create one.
+//!
+//! `Network::new` covers the common case; the builder exposes every option:
+//!
+//! ```
+//! use neural_network::activations::Activation;
+//! use neural_network::initializers::Initializer;
+//! use neural_network::network::Network;
+//!
+//! let network: Network = Network::builder()
+//!     .layers(vec![2, 8, 1])
+//!     .seed(42)
+//!     .activation(Activation::Relu)
+//!     .layer_activation(1, Activation::Sigmoid)
+//!     .weight_initializer(Initializer::XavierUniform)
+//!     .bias_initializer(Initializer::Zeros)
+//!     .layer_weight_initializer(1, Initializer::LeCunNormal)
+//!     .build()
+//!     .unwrap();
+//! ```
+use crate::activations::Activation;
+use crate::callbacks::{Callback, Checkpointer, ProgressBar};
+use crate::dropout;
+use crate::initializers::Initializer;
+use crate::losses::{Loss, MeanSquaredError};
+use crate::metrics::{Metric, DEFAULT_THRESHOLD};
+use crate::network::Network;
+use crate::normalization::Normalization;
+use crate::optimizers::{Optimizer, Sgd};
+use crate::regularizers::Regularizer;
+use crate::schedules::{Constant, LrSchedule};
+use derive_builder::Builder;
+use matrix::scalar::Float;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Builder, Clone, Debug)]
+#[builder(
+    name = "NetworkBuilder",
+    pattern = "owned",
+    build_fn(private, name = "build_config", validate = "Self::validate")
+)]
+/// The options used to create a `Network`.
+pub struct NetworkConfig<T: Float = f64> {
+    /// The number of neurons in each layer of the network, starting with the input layer.
+    pub layers: Vec<usize>,
+    /// The activation function of every layer without an override.
+    #[builder(default = "Activation::Sigmoid")]
+    pub activation: Activation,
+    /// Per-layer activation functions, keyed by the index of the layer transition.
+    #[builder(default, setter(custom))]
+    pub layer_activations: BTreeMap<usize, Activation>,
+    /// The loss function minimized by training.
+    #[builder(default = "Arc::new(MeanSquaredError)", setter(custom))]
+    pub loss: Arc<dyn Loss<T>>,
+    /// The update rule applied to the weights and biases after each step.
+    #[builder(default = "Box::new(Sgd::new())", setter(custom))]
+    pub optimizer: Box<dyn Optimizer<T>>,
+    /// The learning rate to use for the network.
+    #[builder(default = "T::from_f64(0.5)")]
+    pub learning_rate: T,
+    /// Adjusts the learning rate as training progresses.
+    #[builder(default = "Box::new(Constant)", setter(custom))]
+    pub schedule: Box<dyn LrSchedule>,
+    /// The number of samples in each training step: `1` updates the parameters after every
+    /// sample, the number of samples trains on the full batch.
+    #[builder(default = "1")]
+    pub batch_size: usize,
+    /// Whether the samples are reshuffled at the start of every epoch.
+    #[builder(default = "true")]
+    pub shuffle: bool,
+    /// The fraction of the samples passed to `Network::train` held out as a validation set, taken
+    /// from the end of the samples; `0` trains on every sample.
+    #[builder(default)]
+    pub validation_split: f64,
+    /// The metrics computed on the validation set at the end of every epoch.
+    #[builder(default, setter(custom))]
+    pub metrics: Vec<Metric>,
+    /// The output from which a single-output classifier predicts the positive class, used by
+    /// `Network::evaluate` and the classification metrics.
+    #[builder(default = "DEFAULT_THRESHOLD")]
+    pub threshold: f64,
+    /// The seed for the random number generator that initializes the weights and shuffles the
+    /// samples; `None` seeds it from the operating system.
+    #[builder(default, setter(strip_option))]
+    pub seed: Option<u64>,
+    /// The callbacks called by `Network::train`, in order, besides the progress bar and the checkpointer.
+    #[builder(default, setter(custom))]
+    pub callbacks: Vec<Box<dyn Callback<T>>>,
+    /// Whether `Network::train` shows a progress bar.
+    #[builder(default = "true")]
+    pub progress_bar: bool,
+    /// The file `Network::train` writes checkpoints to; `None` disables checkpointing.
+    #[builder(default, setter(into, strip_option))]
+    pub checkpoint_path: Option<PathBuf>,
+    /// Writes a checkpoint every this many epochs; `0` only writes one when training is interrupted.
+    #[builder(default)]
+    pub checkpoint_every: usize,
+    /// Whether Ctrl-C stops training at the end of the current epoch and writes a checkpoint,
+    /// instead of ending the process.
+    #[builder(default)]
+    pub checkpoint_on_interrupt: bool,
+    /// The initializer for the weights of every layer without an override.
+    #[builder(default)]
+    pub weight_initializer: Initializer,
+    /// The initializer for the biases of every layer without an override.
+    #[builder(default)]
+    pub bias_initializer: Initializer,
+    /// Per-layer weight initializers, keyed by the index of the layer transition.
+    #[builder(default, setter(custom))]
+    pub layer_weight_initializers: BTreeMap<usize, Initializer>,
+    /// Per-layer bias initializers, keyed by the index of the layer transition.
+    #[builder(default, setter(custom))]
+    pub layer_bias_initializers: BTreeMap<usize, Initializer>,
+    /// The penalty on the weights of every layer without an override.
+    #[builder(default)]
+    pub regularizer: Regularizer,
+    /// Per-layer penalties, keyed by the index of the layer transition.
+    #[builder(default, setter(custom))]
+    pub layer_regularizers: BTreeMap<usize, Regularizer>,
+    /// Whether the penalty of each layer applies to its biases as well as its weights.
+    #[builder(default)]
+    pub regularize_biases: bool,
+    /// The dropout rate of every hidden layer without an override; `0` disables dropout.
+    #[builder(default)]
+    pub dropout: f64,
+    /// Per-layer dropout rates, keyed by the index of the layer transition.
+    #[builder(default, setter(custom))]
+    pub layer_dropouts: BTreeMap<usize, f64>,
+    /// The normalization of every hidden layer without an override.
+    #[builder(default)]
+    pub normalization: Normalization,
+    /// Per-layer normalizations, keyed by the index of the layer transition.
+    #[builder(default, setter(custom))]
+    pub layer_normalizations: BTreeMap<usize, Normalization>,
+}
+
+impl<T: Float> NetworkConfig<T> {
+    /// Returns the activation function applied to the outputs of layer `layer + 1`.
+    pub fn activation_for(&self, layer: usize) -> Activation {
+        *self.layer_activations.get(&layer).unwrap_or(&self.activation)
+    }
+
+    /// Returns the initializer for the weights between layer `layer` and layer `layer + 1`.
+    pub fn weight_initializer_for(&self, layer: usize) -> Initializer {
+        *self.layer_weight_initializers.get(&layer).unwrap_or(&self.weight_initializer)
+    }
+
+    /// Returns the initializer for the biases of layer `layer + 1`.
+    pub fn bias_initializer_for(&self, layer: usize) -> Initializer {
+        *self.layer_bias_initializers.get(&layer).unwrap_or(&self.bias_initializer)
+    }
+
+    /// Returns the penalty on the parameters of layer transition `layer`.
+    pub fn regularizer_for(&self, layer: usize) -> Regularizer {
+        *self.layer_regularizers.get(&layer).unwrap_or(&self.regularizer)
+    }
+
+    /// Returns the dropout rate of the outputs of layer `layer + 1`, always `0` for the output layer.
+    pub fn dropout_for(&self, layer: usize) -> f64 {
+        if layer + 2 >= self.layers.len() {
+            return 0.0;
+        }
+        *self.layer_dropouts.get(&layer).unwrap_or(&self.dropout)
+    }
+
+    /// Returns the normalization of the pre-activations of layer `layer + 1`; the default
+    /// normalization only applies to the hidden layers.
+    pub fn normalization_for(&self, layer: usize) -> Normalization {
+        match self.layer_normalizations.get(&layer) {
+            Some(&normalization) => normalization,
+            None if layer + 2 < self.layers.len() => self.normalization,
+            None => Normalization::None,
+        }
+    }
+
+    /// Returns every callback of the network: the progress bar if enabled, the configured
+    /// callbacks, then a `Checkpointer` if a checkpoint path is set.
+    pub fn all_callbacks(&self) -> Vec<Box<dyn Callback<T>>> {
+        let mut callbacks: Vec<Box<dyn Callback<T>>> = vec![];
+        if self.progress_bar {
+            callbacks.push(Box::new(ProgressBar::new()));
+        }
+        callbacks.extend(self.callbacks.iter().cloned());
+        if let Some(path) = &self.checkpoint_path {
+            let mut checkpointer = Checkpointer::new(path.clone(), self.checkpoint_every);
+            checkpointer.on_interrupt = self.checkpoint_on_interrupt;
+            callbacks.push(Box::new(checkpointer));
+        }
+        callbacks
+    }
+}
+
+impl<T: Float> NetworkBuilder<T> {
+    /// Overrides the activation function of a single layer transition.
+    ///
+    /// # Arguments
+    /// * `layer` - The index of the layer transition, `0` being the first hidden layer.
+    /// * `activation` - The activation function applied to the outputs of that layer.
+    pub fn layer_activation(mut self, layer: usize, activation: Activation) -> Self {
+        self.layer_activations
+            .get_or_insert_with(BTreeMap::new)
+            .insert(layer, activation);
+        self
+    }
+
+    /// Sets the loss function minimized by training.
+    ///
+    /// # Arguments
+    /// * `loss` - The loss function, `MeanSquaredError` by default.
+    pub fn loss(mut self, loss: impl Loss<T> + 'static) -> Self {
+        self.loss = Some(Arc::new(loss));
+        self
+    }
+
+    /// Sets the update rule applied to the weights and biases after each step.
+    ///
+    /// # Arguments
+    /// * `optimizer` - The optimizer, plain `Sgd` by default.
+    pub fn optimizer(mut self, optimizer: impl Optimizer<T> + 'static) -> Self {
+        self.optimizer = Some(Box::new(optimizer));
+        self
+    }
+
+    /// Sets the schedule adjusting the learning rate as training progresses.
+    ///
+    /// # Arguments
+    /// * `schedule` - The schedule, a `Constant` learning rate by default.
+    pub fn schedule(mut self, schedule: impl LrSchedule + 'static) -> Self {
+        self.schedule = Some(Box::new(schedule));
+        self
+    }
+
+    /// Adds a metric computed on the validation set at the end of every epoch and recorded in
+    /// the training history.
+    ///
+    /// # Arguments
+    /// * `metric` - The metric to compute.
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metrics.get_or_insert_with(Vec::new).push(metric);
+        self
+    }
+
+    /// Adds a callback called by `Network::train`, after the callbacks added before it.
+    ///
+    /// # Arguments
+    /// * `callback` - The callback, e.g. `EarlyStopping` or `CsvLogger`.
+    pub fn callback(mut self, callback: impl Callback<T> + 'static) -> Self {
+        self.callbacks.get_or_insert_with(Vec::new).push(Box::new(callback));
+        self
+    }
+
+    /// Sets the activation function of every layer transition at once.
+    ///
+    /// # Arguments
+    /// * `activations` - One activation function per layer transition, in order.
+    pub fn activations(mut self, activations: Vec<Activation>) -> Self {
+        self.layer_activations = Some(activations.into_iter().enumerate().collect());
+        self
+    }
+
+    /// Overrides the weight initializer of a single layer transition.
+    ///
+    /// # Arguments
+    /// * `layer` - The index of the layer transition, `0` being the weights from the input layer.
+    /// * `initializer` - The initializer to use for those weights.
+    pub fn layer_weight_initializer(mut self, layer: usize, initializer: Initializer) -> Self {
+        self.layer_weight_initializers
+            .get_or_insert_with(BTreeMap::new)
+            .insert(layer, initializer);
+        self
+    }
+
+    /// Overrides the bias initializer of a single layer transition.
+    ///
+    /// # Arguments
+    /// * `layer` - The index of the layer transition, `0` being the biases of the first hidden layer.
+    /// * `initializer` - The initializer to use for those biases.
+    pub fn layer_bias_initializer(mut self, layer: usize, initializer: Initializer) -> Self {
+        self.layer_bias_initializers
+            .get_or_insert_with(BTreeMap::new)
+            .insert(layer, initializer);
+        self
+    }
+
+    /// Overrides the penalty of a single layer transition.
+    ///
+    /// # Arguments
+    /// * `layer` - The index of the layer transition, `0` being the weights from the input layer.
+    /// * `regularizer` - The penalty on the parameters of that layer.
+    pub fn layer_regularizer(mut self, layer: usize, regularizer: Regularizer) -> Self {
+        self.layer_regularizers
+            .get_or_insert_with(BTreeMap::new)
+            .insert(layer, regularizer);
+        self
+    }
+
+    /// Overrides the dropout rate of a single hidden layer.
+    ///
+    /// # Arguments
+    /// * `layer` - The index of the layer transition, `0` being the first hidden layer.
+    /// * `rate` - The probability of dropping each output of that layer during training.
+    pub fn layer_dropout(mut self, layer: usize, rate: f64) -> Self {
+        self.layer_dropouts
+            .get_or_insert_with(BTreeMap::new)
+            .insert(layer, rate);
+        self
+    }
+
+    /// Overrides the normalization of a single layer transition, including the output layer.
+    ///
+    /// # Arguments
+    /// * `layer` - The index of the layer transition, `0` being the first hidden layer.
+    /// * `normalization` - The normalization of the pre-activations of that layer.
+    pub fn layer_normalization(mut self, layer: usize, normalization: Normalization) -> Self {
+        self.layer_normalizations
+            .get_or_insert_with(BTreeMap::new)
+            .insert(layer, normalization);
+        self
+    }
+
+    /// Creates the configured `Network`.
+    ///
+    /// # Returns
+    /// The new `Network`, or an error if the configuration is incomplete or invalid.
+    pub fn build(self) -> Result<Network<T>, NetworkBuilderError> {
+        self.build_config().map(Network::from_config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.batch_size == Some(0) {
+            return Err("The batch size must be at least 1".to_string());
+        }
+        if let Some(split) = self.validation_split {
+            if !(0.0..1.0).contains(&split) {
+                return Err(format!("The validation split must be in [0, 1), got {}", split));
+            }
+        }
+        let initializers = [&self.weight_initializer, &self.bias_initializer].into_iter().flatten();
+        let layer_initializers = [&self.layer_weight_initializers, &self.layer_bias_initializers].into_iter().flatten();
+        if initializers.chain(layer_initializers.flat_map(|map| map.values())).any(Initializer::is_invalid) {
+            return Err("Initializers need finite parameters, low < high and a non-negative std_dev".to_string());
+        }
+        let regularizers = self.regularizer.iter().chain(self.layer_regularizers.iter().flat_map(|map| map.values()));
+        if regularizers.into_iter().any(Regularizer::is_invalid) {
+            return Err("Regularization coefficients must be finite and non-negative".to_string());
+        }
+        let rates = self.dropout.iter().chain(self.layer_dropouts.iter().flat_map(|map| map.values()));
+        if let Some(rate) = rates.into_iter().find(|&&rate| !dropout::is_valid_rate(rate)) {
+            return Err(format!("Dropout rates must be in [0, 1), got {}", rate));
+        }
+        let normalizations = self.normalization.iter().chain(self.layer_normalizations.iter().flat_map(|map| map.values()));
+        let normalizations: Vec<&Normalization> = normalizations.collect();
+        if normalizations.iter().any(|normalization| normalization.is_invalid()) {
+            return Err("Normalizations need a momentum in [0, 1) and a positive epsilon".to_string());
+        }
+        let batch_norm = normalizations.iter().any(|normalization| normalization.has_running_statistics());
+        if batch_norm && self.batch_size.unwrap_or(1) < 2 {
+            return Err("Batch normalization needs a batch size of at least 2".to_string());
+        }
+        let checkpoints = self.checkpoint_every.unwrap_or(0) > 0 || self.checkpoint_on_interrupt == Some(true);
+        if checkpoints && matches!(self.checkpoint_path, None | Some(None)) {
+            return Err("Checkpointing needs a checkpoint path".to_string());
+        }
+        let layers = match &self.layers {
+            Some(layers) => layers,
+            None => return Ok(()),
+        };
+        if layers.len() < 2 {
+            return Err(format!("A network needs at least 2 layers, got {}", layers.len()));
+        }
+        if layers.contains(&0) {
+            return Err("Every layer needs at least one neuron".to_string());
+        }
+        let transitions = layers.len() - 1;
+        let initializers = [&self.layer_weight_initializers, &self.layer_bias_initializers];
+        let initializer_layers = initializers.iter().filter_map(|map| map.as_ref()).flat_map(|map| map.keys());
+        let activation_layers = self.layer_activations.iter().flat_map(|map| map.keys());
+        let regularizer_layers = self.layer_regularizers.iter().flat_map(|map| map.keys());
+        let dropout_layers = self.layer_dropouts.iter().flat_map(|map| map.keys());
+        let normalization_layers = self.layer_normalizations.iter().flat_map(|map| map.keys());
+        let overrides = initializer_layers
+            .chain(activation_layers)
+            .chain(regularizer_layers)
+            .chain(dropout_layers)
+            .chain(normalization_layers);
+        for &layer in overrides {
+            if layer >= transitions {
+                return Err(format!(
+                    "Layer {} is out of range for a network with {} layer transitions",
+                    layer, transitions
+                ));
+            }
+        }
+        if self.layer_dropouts.iter().flat_map(|map| map.keys()).any(|&layer| layer == transitions - 1) {
+            return Err("Dropout cannot apply to the output layer".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `NetworkBuilder`.
+///
+/// The tests cover:
+/// - Per-layer initializer, activation, regularizer, dropout and normalization overrides
+/// - Validation errors
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_overrides() {
+        let config = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 3, 1])
+            .weight_initializer(Initializer::HeNormal)
+            .layer_weight_initializer(2, Initializer::XavierUniform)
+            .layer_bias_initializer(0, Initializer::Constant(0.1))
+            .build_config()
+            .unwrap();
+        assert_eq!(config.weight_initializer_for(0), Initializer::HeNormal);
+        assert_eq!(config.weight_initializer_for(2), Initializer::XavierUniform);
+        assert_eq!(config.bias_initializer_for(0), Initializer::Constant(0.1));
+        assert_eq!(config.bias_initializer_for(1), Initializer::default());
+        assert_eq!(config.activation_for(2), Activation::Sigmoid);
+        assert_eq!(config.regularizer_for(0), Regularizer::None);
+
+        let config = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 3, 1])
+            .activations(vec![Activation::Relu, Activation::Tanh, Activation::Linear])
+            .regularizer(Regularizer::L2(0.01))
+            .layer_regularizer(2, Regularizer::L1(0.1))
+            .build_config()
+            .unwrap();
+        assert_eq!(config.regularizer_for(0), Regularizer::L2(0.01));
+        assert_eq!(config.regularizer_for(2), Regularizer::L1(0.1));
+        assert_eq!(config.dropout_for(0), 0.0);
+        assert_eq!(config.activation_for(0), Activation::Relu);
+        assert_eq!(config.activation_for(1), Activation::Tanh);
+        assert_eq!(config.activation_for(2), Activation::Linear);
+
+        let config = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 3, 1])
+            .dropout(0.5)
+            .layer_dropout(1, 0.25)
+            .build_config()
+            .unwrap();
+        assert_eq!(config.dropout_for(0), 0.5);
+        assert_eq!(config.dropout_for(1), 0.25);
+        assert_eq!(config.dropout_for(2), 0.0);
+
+        let config = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 3, 1])
+            .normalization(Normalization::batch_norm())
+            .layer_normalization(1, Normalization::layer_norm())
+            .batch_size(4)
+            .build_config()
+            .unwrap();
+        assert_eq!(config.normalization_for(0), Normalization::batch_norm());
+        assert_eq!(config.normalization_for(1), Normalization::layer_norm());
+        assert_eq!(config.normalization_for(2), Normalization::None);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(NetworkBuilder::<f64>::default().build().is_err());
+        assert!(NetworkBuilder::<f64>::default().layers(vec![2]).build().is_err());
+        assert!(NetworkBuilder::<f64>::default().layers(vec![2, 0, 1]).build().is_err());
+        assert!(NetworkBuilder::<f64>::default().layers(vec![2, 1]).batch_size(0).build().is_err());
+        assert!(NetworkBuilder::<f64>::default().layers(vec![2, 1]).validation_split(1.0).build().is_err());
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 1])
+            .checkpoint_every(10)
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("checkpoint path"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .layer_weight_initializer(2, Initializer::Zeros)
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("out of range"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .layer_regularizer(2, Regularizer::L2(0.1))
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("out of range"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .regularizer(Regularizer::L1(-0.1))
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("non-negative"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .weight_initializer(Initializer::Uniform { low: 1.0, high: 1.0 })
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("low < high"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .layer_bias_initializer(1, Initializer::TruncatedNormal { mean: 0.0, std_dev: -0.1 })
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("std_dev"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .dropout(1.0)
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("[0, 1)"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .layer_dropout(1, 0.5)
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("output layer"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .normalization(Normalization::batch_norm())
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("batch size"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .layer_normalization(0, Normalization::LayerNorm { epsilon: 0.0 })
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("positive epsilon"));
+        let error = NetworkBuilder::<f64>::default()
+            .layers(vec![2, 3, 1])
+            .activations(vec![Activation::Relu; 3])
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("out of range"));
+    }
+}
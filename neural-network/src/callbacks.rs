@@ -0,0 +1,612 @@
+//! Callbacks, hooking into `Network::train` to report progress, log, checkpoint or stop early.
+//!
+//! `Network::train` calls every callback of the network, in the order they were added, at the
+//! start and end of the run, of every epoch and after every batch. The hooks see the network and
+//! the metrics of the batch or epoch, and can ask training to stop by returning `Control::Stop`:
+//! from `on_epoch_begin` the epoch is skipped, from the other hooks training stops once the
+//! current epoch is done, so that its record, checkpoint and log line are complete.
+//!
+//! The builder adds a `ProgressBar` unless `progress_bar(false)` is set, and a `Checkpointer`
+//! when a checkpoint path is given; other callbacks are added with `NetworkBuilder::callback`
+//! or `Network::add_callback`.
+//!
+//! # Example
+//! ```no_run
+//! use neural_network::callbacks::{CsvLogger, EarlyStopping};
+//! use neural_network::network::Network;
+//!
+//! let mut network: Network = Network::builder()
+//!     .layers(vec![2, 3, 1])
+//!     .seed(42)
+//!     .callback(EarlyStopping::new(100))
+//!     .callback(CsvLogger::new("xor.csv"))
+//!     .build()
+//!     .unwrap();
+//! let inputs = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0]];
+//! let targets = vec![vec![0.0], vec![1.0], vec![1.0], vec![0.0]];
+//! network.train(inputs, targets, 100_000);
+//! ```
+use crate::checkpoint::InterruptGuard;
+use crate::history::{self, EpochRecord};
+use crate::network::Network;
+use crate::serialization::SavedNetwork;
+use avance::AvanceBar;
+use matrix::scalar::Float;
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// Whether training goes on after a hook.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Control {
+    /// Training continues.
+    #[default]
+    Continue,
+    /// Training stops, at the latest at the end of the current epoch.
+    Stop,
+}
+
+/// What happened during one batch of training.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchRecord {
+    /// The number of the epoch, starting at one.
+    pub epoch: usize,
+    /// The index of the batch within the epoch, starting at zero.
+    pub batch: usize,
+    /// The number of samples in the batch.
+    pub samples: usize,
+    /// The mean loss over the samples of the batch, before the parameters were updated.
+    pub loss: f64,
+}
+
+/// Hooks called by `Network::train`.
+///
+/// Every hook has a default that does nothing, so a callback only implements the hooks it needs.
+pub trait Callback<T: Float>: Debug + Send + Sync + CallbackClone<T> {
+    /// Called once before the first epoch.
+    ///
+    /// # Arguments
+    /// * `network` - The network being trained; after `resume_from` its history holds the
+    ///   epochs before the checkpoint.
+    /// * `epochs` - The total number of epochs of the run.
+    fn on_train_begin(&mut self, _network: &Network<T>, _epochs: usize) {}
+
+    /// Called at the start of every epoch, before the samples are shuffled.
+    ///
+    /// # Arguments
+    /// * `network` - The network being trained.
+    /// * `epoch` - The number of the epoch, starting at one.
+    fn on_epoch_begin(&mut self, _network: &Network<T>, _epoch: usize) -> Control {
+        Control::Continue
+    }
+
+    /// Called after the parameters were updated with the gradients of a batch.
+    ///
+    /// # Arguments
+    /// * `network` - The network being trained.
+    /// * `batch` - The metrics of the batch.
+    fn on_batch_end(&mut self, _network: &Network<T>, _batch: &BatchRecord) -> Control {
+        Control::Continue
+    }
+
+    /// Called at the end of every epoch, once its record was added to the history.
+    ///
+    /// # Arguments
+    /// * `network` - The network being trained.
+    /// * `record` - The metrics of the epoch, also the last entry of `network.history()`.
+    fn on_epoch_end(&mut self, _network: &Network<T>, _record: &EpochRecord) -> Control {
+        Control::Continue
+    }
+
+    /// Called once after the last epoch, or after training was stopped.
+    ///
+    /// # Arguments
+    /// * `network` - The trained network, which the callback may modify.
+    fn on_train_end(&mut self, _network: &mut Network<T>) {}
+}
+
+/// Clones a boxed `Callback`, including its state.
+///
+/// This is implemented for every callback that implements `Clone`.
+pub trait CallbackClone<T: Float> {
+    /// Returns a boxed copy of the callback.
+    fn clone_box(&self) -> Box<dyn Callback<T>>;
+}
+
+impl<T: Float, C: Callback<T> + Clone + 'static> CallbackClone<T> for C {
+    fn clone_box(&self) -> Box<dyn Callback<T>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<T: Float> Clone for Box<dyn Callback<T>> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Shows a progress bar with the loss of the last epoch.
+#[derive(Default)]
+pub struct ProgressBar {
+    bar: Option<AvanceBar>,
+}
+
+impl ProgressBar {
+    /// Creates a progress bar, shown once training starts.
+    pub fn new() -> Self {
+        ProgressBar::default()
+    }
+}
+
+impl Clone for ProgressBar {
+    /// Returns a progress bar that is not shown yet; a bar on screen cannot be duplicated.
+    fn clone(&self) -> Self {
+        ProgressBar::new()
+    }
+}
+
+impl Debug for ProgressBar {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("ProgressBar").field("shown", &self.bar.is_some()).finish()
+    }
+}
+
+impl<T: Float> Callback<T> for ProgressBar {
+    fn on_train_begin(&mut self, network: &Network<T>, epochs: usize) {
+        let bar = AvanceBar::new(epochs.saturating_sub(network.history().len()) as u64);
+        bar.set_desc("Progress");
+        self.bar = Some(bar);
+    }
+
+    fn on_epoch_end(&mut self, _network: &Network<T>, record: &EpochRecord) -> Control {
+        if let Some(bar) = &self.bar {
+            bar.set_desc(format!("Loss {:.6}", record.loss));
+            bar.inc();
+        }
+        Control::Continue
+    }
+
+    fn on_train_end(&mut self, _network: &mut Network<T>) {
+        if let Some(bar) = self.bar.take() {
+            bar.close();
+        }
+    }
+}
+
+/// Writes the history to a CSV file as training progresses, one row per epoch.
+///
+/// The file has the columns of `TrainingHistory::write_csv`, with the metrics of the first epoch
+/// of the run. It is rewritten at the end of the first epoch of every run, including the epochs
+/// before the checkpoint of a resumed run, and flushed after every epoch. Failures to write are
+/// reported on standard error without stopping training.
+#[derive(Debug)]
+pub struct CsvLogger {
+    /// The file to write.
+    pub path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    metrics: Vec<String>,
+}
+
+impl CsvLogger {
+    /// Creates a logger writing to `path`.
+    ///
+    /// # Arguments
+    /// * `path` - The file to create or overwrite when training starts.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        CsvLogger {
+            path: path.into(),
+            writer: None,
+            metrics: vec![],
+        }
+    }
+
+    /// Creates the file and writes the header and every epoch recorded so far.
+    fn start(&mut self, records: &[EpochRecord]) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        self.metrics = records.last().map(|record| record.metrics.keys().cloned().collect()).unwrap_or_default();
+        let metrics: Vec<&str> = self.metrics.iter().map(String::as_str).collect();
+        history::write_csv_header(&mut writer, &metrics)?;
+        for record in records {
+            history::write_csv_row(&mut writer, record, &metrics)?;
+        }
+        writer.flush()?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    /// Appends one epoch to the file.
+    fn append(&mut self, record: &EpochRecord) -> io::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            let metrics: Vec<&str> = self.metrics.iter().map(String::as_str).collect();
+            history::write_csv_row(writer, record, &metrics)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Clone for CsvLogger {
+    /// Returns a logger for the same file that has not started writing yet.
+    fn clone(&self) -> Self {
+        CsvLogger::new(self.path.clone())
+    }
+}
+
+impl<T: Float> Callback<T> for CsvLogger {
+    fn on_train_begin(&mut self, _network: &Network<T>, _epochs: usize) {
+        self.writer = None;
+    }
+
+    fn on_epoch_end(&mut self, network: &Network<T>, record: &EpochRecord) -> Control {
+        let result = match self.writer {
+            None => self.start(&network.history().epochs),
+            Some(_) => self.append(record),
+        };
+        if let Err(error) = result {
+            eprintln!("Cannot write the training log {}: {}", self.path.display(), error);
+        }
+        Control::Continue
+    }
+
+    fn on_train_end(&mut self, _network: &mut Network<T>) {
+        self.writer = None;
+    }
+}
+
+/// Writes a checkpoint every `every` epochs and, with `on_interrupt`, stops training with a
+/// checkpoint when Ctrl-C is pressed.
+///
+/// Failures to write are reported on standard error without stopping training, so that a full
+/// disk does not end a long training run.
+#[derive(Debug)]
+pub struct Checkpointer {
+    /// The file checkpoints are written to.
+    pub path: PathBuf,
+    /// Writes a checkpoint every this many epochs; `0` only writes one when training is interrupted.
+    pub every: usize,
+    /// Whether Ctrl-C stops training at the end of the current epoch and writes a checkpoint,
+    /// instead of ending the process.
+    pub on_interrupt: bool,
+    interrupts: Option<InterruptGuard>,
+}
+
+impl Checkpointer {
+    /// Creates a checkpointer writing to `path` every `every` epochs.
+    ///
+    /// # Arguments
+    /// * `path` - The file to create or overwrite with each checkpoint.
+    /// * `every` - The number of epochs between checkpoints; `0` disables periodic checkpoints.
+    pub fn new(path: impl Into<PathBuf>, every: usize) -> Self {
+        Checkpointer {
+            path: path.into(),
+            every,
+            on_interrupt: false,
+            interrupts: None,
+        }
+    }
+}
+
+impl Clone for Checkpointer {
+    /// Returns a checkpointer with the same options that is not watching for interrupts yet.
+    fn clone(&self) -> Self {
+        Checkpointer {
+            path: self.path.clone(),
+            every: self.every,
+            on_interrupt: self.on_interrupt,
+            interrupts: None,
+        }
+    }
+}
+
+impl<T: Float> Callback<T> for Checkpointer {
+    fn on_train_begin(&mut self, _network: &Network<T>, _epochs: usize) {
+        self.interrupts = self.on_interrupt.then(InterruptGuard::new);
+    }
+
+    fn on_epoch_end(&mut self, network: &Network<T>, record: &EpochRecord) -> Control {
+        let interrupted = self.interrupts.as_ref().is_some_and(InterruptGuard::interrupted);
+        if interrupted || self.every > 0 && record.epoch % self.every == 0 {
+            if let Err(error) = network.checkpoint().save(&self.path) {
+                eprintln!("Cannot write the checkpoint {}: {}", self.path.display(), error);
+            }
+        }
+        if interrupted {
+            Control::Stop
+        } else {
+            Control::Continue
+        }
+    }
+
+    fn on_train_end(&mut self, _network: &mut Network<T>) {
+        self.interrupts = None;
+    }
+}
+
+/// Stops training once the monitored loss has not improved by more than `min_delta` for
+/// `patience` epochs; with a patience of zero, at the first epoch without improvement.
+///
+/// The validation loss is monitored when the epoch has one, the training loss otherwise. When a
+/// run is resumed, the epochs before the checkpoint count as well.
+///
+/// With `restore_best`, the callback keeps a copy of the parameters of the best epoch, including
+/// the state of the normalized layers, and puts them back into the network when training ends,
+/// whether it stopped early or not. The parameters of epochs before the checkpoint of a resumed
+/// run are not known, so the network is left unchanged if none of the resumed epochs improved
+/// on them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EarlyStopping<T: Float = f64> {
+    /// The number of epochs without improvement tolerated before stopping.
+    pub patience: usize,
+    /// The minimum decrease of the loss that counts as an improvement.
+    pub min_delta: f64,
+    /// Whether the network gets the parameters of the best epoch back when training ends.
+    pub restore_best: bool,
+    best: f64,
+    wait: usize,
+    best_parameters: Option<SavedNetwork>,
+    element: PhantomData<T>,
+}
+
+impl<T: Float> EarlyStopping<T> {
+    /// Creates a callback that stops training after `patience` epochs without improvement.
+    ///
+    /// # Arguments
+    /// * `patience` - The number of epochs without improvement tolerated.
+    pub fn new(patience: usize) -> Self {
+        EarlyStopping {
+            patience,
+            min_delta: 0.0,
+            restore_best: false,
+            best: f64::INFINITY,
+            wait: 0,
+            best_parameters: None,
+            element: PhantomData,
+        }
+    }
+
+    /// Returns the best loss seen so far, or infinity before the first epoch.
+    pub fn best(&self) -> f64 {
+        self.best
+    }
+
+    /// Records the loss of an epoch.
+    ///
+    /// # Returns
+    /// Whether the loss improved.
+    fn observe(&mut self, record: &EpochRecord) -> bool {
+        let loss = record.validation_loss.unwrap_or(record.loss);
+        if loss < self.best - self.min_delta {
+            self.best = loss;
+            self.wait = 0;
+            true
+        } else {
+            self.wait += 1;
+            false
+        }
+    }
+}
+
+impl<T: Float> Callback<T> for EarlyStopping<T> {
+    fn on_train_begin(&mut self, network: &Network<T>, _epochs: usize) {
+        self.best = f64::INFINITY;
+        self.wait = 0;
+        self.best_parameters = None;
+        for record in &network.history().epochs {
+            self.observe(record);
+        }
+    }
+
+    fn on_epoch_end(&mut self, network: &Network<T>, record: &EpochRecord) -> Control {
+        let improved = self.observe(record);
+        if improved && self.restore_best {
+            self.best_parameters = Some(network.to_saved());
+        }
+        if !improved && self.wait >= self.patience {
+            Control::Stop
+        } else {
+            Control::Continue
+        }
+    }
+
+    fn on_train_end(&mut self, network: &mut Network<T>) {
+        if let Some(saved) = self.best_parameters.take() {
+            network
+                .load_parameters(&saved)
+                .expect("The best parameters come from the same network");
+        }
+    }
+}
+
+#[cfg(test)]
+/// Tests for the callbacks.
+///
+/// The tests cover:
+/// - The order and number of hook calls, and stopping from a hook
+/// - Early stopping on a plateau, and restoring the best weights
+/// - Logging to CSV while training
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn xor() -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+        let inputs = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0]];
+        let targets = vec![vec![0.0], vec![1.0], vec![1.0], vec![0.0]];
+        (inputs, targets)
+    }
+
+    /// Records every hook call in a log shared with the test, and stops after one batch.
+    #[derive(Clone, Debug, Default)]
+    struct Recorder {
+        calls: Arc<Mutex<Vec<String>>>,
+        stop_after: Option<(usize, usize)>,
+    }
+
+    impl Recorder {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    impl Callback<f64> for Recorder {
+        fn on_train_begin(&mut self, network: &Network, epochs: usize) {
+            self.record(format!("train_begin {} {}", network.history().len(), epochs));
+        }
+
+        fn on_epoch_begin(&mut self, _network: &Network, epoch: usize) -> Control {
+            self.record(format!("epoch_begin {}", epoch));
+            Control::Continue
+        }
+
+        fn on_batch_end(&mut self, _network: &Network, batch: &BatchRecord) -> Control {
+            self.record(format!("batch_end {} {} {}", batch.epoch, batch.batch, batch.samples));
+            if self.stop_after == Some((batch.epoch, batch.batch)) {
+                Control::Stop
+            } else {
+                Control::Continue
+            }
+        }
+
+        fn on_epoch_end(&mut self, network: &Network, record: &EpochRecord) -> Control {
+            assert_eq!(network.history().last(), Some(record));
+            self.record(format!("epoch_end {}", record.epoch));
+            Control::Continue
+        }
+
+        fn on_train_end(&mut self, network: &mut Network) {
+            self.record(format!("train_end {}", network.history().len()));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let (inputs, targets) = xor();
+        let recorder = Recorder {
+            stop_after: Some((2, 0)),
+            ..Recorder::default()
+        };
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(1)
+            .batch_size(3)
+            .progress_bar(false)
+            .callback(recorder.clone())
+            .build()
+            .unwrap();
+        let history = network.train(inputs, targets, 5);
+        // The stop requested after the first batch of epoch 2 takes effect once that epoch is done.
+        assert_eq!(history.len(), 2);
+        let expected = [
+            "train_begin 0 5",
+            "epoch_begin 1",
+            "batch_end 1 0 3",
+            "batch_end 1 1 1",
+            "epoch_end 1",
+            "epoch_begin 2",
+            "batch_end 2 0 3",
+            "batch_end 2 1 1",
+            "epoch_end 2",
+            "train_end 2",
+        ];
+        assert_eq!(*recorder.calls.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_early_stopping() {
+        let mut stopping: EarlyStopping = EarlyStopping::new(2);
+        let losses = [1.0, 0.5, 0.6, 0.4, 0.4, 0.45];
+        let improved: Vec<bool> = losses
+            .iter()
+            .map(|&loss| stopping.observe(&EpochRecord { loss, ..EpochRecord::default() }))
+            .collect();
+        assert_eq!(improved, [true, true, false, true, false, false]);
+        assert_eq!(stopping.wait, 2);
+        assert_eq!(stopping.best(), 0.4);
+
+        // The validation loss is monitored in preference to the training loss.
+        let mut stopping: EarlyStopping = EarlyStopping::new(1);
+        let record = EpochRecord { loss: 0.1, validation_loss: Some(2.0), ..EpochRecord::default() };
+        assert!(stopping.observe(&record));
+        assert_eq!(stopping.best(), 2.0);
+
+        // A learning rate of zero never improves the loss, so training stops after the patience.
+        let (inputs, targets) = xor();
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(2)
+            .learning_rate(0.0)
+            .progress_bar(false)
+            .callback(EarlyStopping::new(3))
+            .build()
+            .unwrap();
+        assert_eq!(network.train(inputs.clone(), targets.clone(), 100).len(), 4);
+
+        // Without patience, training stops at the first epoch that does not improve.
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(2)
+            .learning_rate(0.0)
+            .progress_bar(false)
+            .callback(EarlyStopping::new(0))
+            .build()
+            .unwrap();
+        assert_eq!(network.train(inputs.clone(), targets.clone(), 100).len(), 2);
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(2)
+            .batch_size(4)
+            .progress_bar(false)
+            .callback(EarlyStopping::new(0))
+            .build()
+            .unwrap();
+        assert_eq!(network.train(inputs, targets, 5).len(), 5);
+    }
+
+    #[test]
+    fn test_early_stopping_restores_best_weights() {
+        let (inputs, _) = xor();
+        // Training towards ones makes the loss on zeros worse with every epoch.
+        let (ones, zeros) = (vec![vec![1.0]; 4], vec![vec![0.0]; 4]);
+        let mut stopping = EarlyStopping::new(3);
+        stopping.restore_best = true;
+        let build = |stopping: Option<EarlyStopping>| -> Network {
+            let mut builder = Network::builder().layers(vec![2, 3, 1]).seed(4).batch_size(4).progress_bar(false);
+            if let Some(stopping) = stopping {
+                builder = builder.callback(stopping);
+            }
+            builder.build().unwrap()
+        };
+        let mut network = build(Some(stopping));
+        let history = network.train_with_validation(inputs.clone(), ones.clone(), inputs.clone(), zeros.clone(), 50);
+        let validation_losses = history.validation_losses();
+        assert_eq!(validation_losses.len(), 4);
+        assert!(validation_losses.windows(2).all(|pair| pair[1] > pair[0]));
+
+        // The restored weights are those after the first epoch.
+        let mut first = build(None);
+        first.train(inputs.clone(), ones, 1);
+        assert_eq!(network.weights(), first.weights());
+        assert_eq!(network.biases(), first.biases());
+        assert_eq!(network.loss(&inputs, &zeros), validation_losses[0]);
+    }
+
+    #[test]
+    fn test_csv_logger() {
+        let (inputs, targets) = xor();
+        let path = std::env::temp_dir().join("neural_network_test_csv_logger.csv");
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(3)
+            .progress_bar(false)
+            .callback(CsvLogger::new(path.clone()))
+            .build()
+            .unwrap();
+        let history = network.train(inputs, targets, 3);
+        let logged = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut expected = vec![];
+        history.write_csv(&mut expected).unwrap();
+        assert_eq!(logged, String::from_utf8(expected).unwrap());
+    }
+}
@@ -0,0 +1,253 @@
+//! Checkpoints, saving the complete state of a training run so that it can be resumed.
+//!
+//! A `Checkpoint` holds the network, the state of its optimizer and learning-rate schedule, the
+//! number of completed epochs, the current order of the samples, the state of the random number
+//! generators and the training history. Resuming from it with `Network::resume_from`
+//! and calling `train` with the same data and number of epochs gives exactly the weights of an
+//! uninterrupted run.
+//!
+//! The `Checkpointer` callback, added by the builder when a checkpoint path is set, writes
+//! checkpoints at the end of an epoch, every `checkpoint_every` epochs and, with
+//! `checkpoint_on_interrupt`, when Ctrl-C is pressed. Checkpoints use the formats of
+//! the `serialization` module, chosen from the extension of the path; binary checkpoints start
+//! with `CHECKPOINT_MAGIC`.
+//!
+//! # Example
+//! ```no_run
+//! use neural_network::checkpoint::Checkpoint;
+//! use neural_network::network::Network;
+//!
+//! let mut network: Network = Network::builder()
+//!     .layers(vec![2, 3, 1])
+//!     .seed(42)
+//!     .checkpoint_path("xor.checkpoint")
+//!     .checkpoint_every(1000)
+//!     .checkpoint_on_interrupt(true)
+//!     .build()
+//!     .unwrap();
+//! if let Ok(checkpoint) = Checkpoint::load("xor.checkpoint") {
+//!     network.resume_from(&checkpoint).unwrap();
+//! }
+//! let inputs = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0]];
+//! let targets = vec![vec![0.0], vec![1.0], vec![1.0], vec![0.0]];
+//! network.train(inputs, targets, 100_000);
+//! ```
+use crate::history::TrainingHistory;
+use crate::optimizers::OptimizerState;
+use crate::schedules::ScheduleState;
+use crate::serialization::{self, Format, SavedNetwork, SerializationError};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+/// The bytes every checkpoint in the binary format starts with.
+pub const CHECKPOINT_MAGIC: [u8; 4] = *b"NNCK";
+
+/// The state of the random number generator of a `Network`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RngState {
+    /// The seed the generator was created from.
+    pub seed: [u8; 32],
+    /// The stream of the generator.
+    pub stream: u64,
+    /// The number of 32-bit words generated so far.
+    pub word_pos: u128,
+}
+
+impl RngState {
+    /// Captures the state of a generator.
+    pub fn of(rng: &ChaCha8Rng) -> Self {
+        RngState {
+            seed: rng.get_seed(),
+            stream: rng.get_stream(),
+            word_pos: rng.get_word_pos(),
+        }
+    }
+
+    /// Creates a generator continuing exactly where the captured one stopped.
+    pub fn restore(&self) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::from_seed(self.seed);
+        rng.set_stream(self.stream);
+        rng.set_word_pos(self.word_pos);
+        rng
+    }
+}
+
+/// The complete state of a training run at the end of an epoch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The version of the format, `FORMAT_VERSION` when written by this crate.
+    pub format_version: u32,
+    /// The layer sizes, activation functions, learning rate, weights and biases.
+    pub network: SavedNetwork,
+    /// The state of the optimizer.
+    pub optimizer: OptimizerState,
+    /// The state of the learning-rate schedule.
+    pub schedule: ScheduleState,
+    /// The number of completed epochs.
+    pub epoch: usize,
+    /// The order of the samples in the last epoch, which the next shuffle starts from.
+    pub order: Vec<usize>,
+    /// The state of the random number generator shuffling the samples.
+    pub rng: RngState,
+    /// The state of the random number generator drawing the dropout masks.
+    pub mask_rng: RngState,
+    /// The record of every completed epoch.
+    pub history: TrainingHistory,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint in the given format.
+    ///
+    /// # Arguments
+    /// * `writer` - Where to write the checkpoint.
+    /// * `format` - The format to write.
+    pub fn write_to(&self, writer: impl Write, format: Format) -> Result<(), SerializationError> {
+        serialization::write_versioned(self, CHECKPOINT_MAGIC, self.format_version, writer, format)
+    }
+
+    /// Reads a checkpoint written by `write_to` in either format.
+    ///
+    /// # Arguments
+    /// * `reader` - Where to read the checkpoint from.
+    pub fn read_from(reader: impl Read) -> Result<Self, SerializationError> {
+        let checkpoint: Checkpoint = serialization::read_versioned(reader, CHECKPOINT_MAGIC)?;
+        serialization::check_version(checkpoint.format_version)?;
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint to a file, in the format chosen by `Format::from_path`.
+    ///
+    /// The checkpoint is written to a temporary file next to `path` first, then renamed, so an
+    /// interruption while writing never leaves a truncated checkpoint behind.
+    ///
+    /// # Arguments
+    /// * `path` - The file to create or overwrite.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SerializationError> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let file = std::fs::File::create(&temporary)?;
+        self.write_to(io::BufWriter::new(file), Format::from_path(path))?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Reads a checkpoint from a file written by `save` in either format.
+    ///
+    /// # Arguments
+    /// * `path` - The file to read.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SerializationError> {
+        let file = std::fs::File::open(path)?;
+        Checkpoint::read_from(io::BufReader::new(file))
+    }
+}
+
+/// Whether a training run that checkpoints on interrupt is in progress.
+static TRAINING: AtomicBool = AtomicBool::new(false);
+/// Whether Ctrl-C was pressed during the current training run.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Installs the Ctrl-C handler once per process.
+static INSTALL: Once = Once::new();
+
+/// Marks a training run that stops and checkpoints on Ctrl-C, for as long as it is alive.
+#[derive(Debug)]
+pub(crate) struct InterruptGuard;
+
+impl InterruptGuard {
+    /// Installs the Ctrl-C handler if needed and starts watching for interrupts.
+    ///
+    /// Outside of a training run the handler ends the process, as Ctrl-C would without it. If the
+    /// application installed its own handler first, interrupts are left to that handler.
+    pub(crate) fn new() -> Self {
+        INSTALL.call_once(|| {
+            let _ = ctrlc::set_handler(|| {
+                if TRAINING.load(Ordering::SeqCst) {
+                    INTERRUPTED.store(true, Ordering::SeqCst);
+                } else {
+                    std::process::exit(130);
+                }
+            });
+        });
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        TRAINING.store(true, Ordering::SeqCst);
+        InterruptGuard
+    }
+
+    /// Returns whether Ctrl-C was pressed since the guard was created.
+    pub(crate) fn interrupted(&self) -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        TRAINING.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+/// Tests for the checkpoint format.
+///
+/// The tests cover:
+/// - Restoring the random number generator mid-stream
+/// - Round trips through both formats, and telling checkpoints and networks apart
+mod tests {
+    use super::*;
+    use crate::history::EpochRecord;
+    use crate::serialization::{SavedMatrix, FORMAT_VERSION};
+    use rand::Rng;
+
+    #[test]
+    fn test_restore_rng() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let _: [u64; 5] = rng.gen();
+        let mut restored = RngState::of(&rng).restore();
+        let expected: [u32; 8] = rng.gen();
+        assert_eq!(restored.gen::<[u32; 8]>(), expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let _: u32 = rng.gen();
+        let checkpoint = Checkpoint {
+            format_version: FORMAT_VERSION,
+            network: SavedNetwork {
+                format_version: FORMAT_VERSION,
+                layers: vec![1, 1],
+                activations: vec![crate::activations::Activation::Tanh],
+                learning_rate: 0.1,
+                weights: vec![SavedMatrix { rows: 1, cols: 1, data: vec![0.3] }],
+                biases: vec![SavedMatrix { rows: 1, cols: 1, data: vec![-0.2] }],
+                normalizations: Default::default(),
+            },
+            optimizer: OptimizerState {
+                kind: "adam".to_string(),
+                step: 4,
+                buffers: vec![[(0, SavedMatrix { rows: 1, cols: 1, data: vec![0.01] })].into()],
+            },
+            schedule: ScheduleState::new("reduce_on_plateau", vec![0.5, 1.0]),
+            epoch: 7,
+            order: vec![2, 0, 1],
+            rng: RngState::of(&rng),
+            mask_rng: RngState::of(&ChaCha8Rng::seed_from_u64(11)),
+            history: TrainingHistory {
+                epochs: vec![EpochRecord { epoch: 1, loss: 0.4, ..EpochRecord::default() }],
+            },
+        };
+        for format in [Format::Json, Format::Binary] {
+            let mut bytes = vec![];
+            checkpoint.write_to(&mut bytes, format).unwrap();
+            assert_eq!(Checkpoint::read_from(bytes.as_slice()).unwrap(), checkpoint);
+        }
+
+        let mut bytes = vec![];
+        checkpoint.network.write_to(&mut bytes, Format::Binary).unwrap();
+        assert!(Checkpoint::read_from(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Dropout, which randomly silences neurons of the hidden layers during training to reduce overfitting.
+//!
+//! Each layer transition has a dropout rate, the probability that an output of that layer is set
+//! to zero in a training step. The dropout is inverted: the outputs that are kept are scaled by
+//! `1 / (1 - rate)`, so their expected value is unchanged and inference needs no rescaling.
+//!
+//! Whether dropout applies depends on the `Mode` of the network. `Network::train` trains in
+//! `Mode::Train` and evaluates the validation set in `Mode::Eval`; outside of training, the
+//! network is in `Mode::Eval` unless `Network::set_mode` changes it, e.g. to compute gradients of
+//! a thinned network with `Network::compute_gradients`. The masks are drawn from a generator of
+//! their own, seeded with the seed of the network, so seeded runs stay reproducible.
+//!
+//! # Example
+//! ```
+//! use neural_network::activations::Activation;
+//! use neural_network::dropout::Mode;
+//! use neural_network::network::Network;
+//!
+//! let mut network: Network = Network::builder()
+//!     .layers(vec![2, 16, 16, 1])
+//!     .activation(Activation::Relu)
+//!     .layer_activation(2, Activation::Sigmoid)
+//!     .dropout(0.2)
+//!     .layer_dropout(0, 0.5)
+//!     .seed(42)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(network.mode(), Mode::Eval);
+//! let inputs = vec![vec![0.0, 1.0]];
+//! assert_eq!(network.predict(&inputs), network.predict(&inputs));
+//! ```
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use rand::Rng;
+
+/// Whether a `Network` is being trained or used for inference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Training: the outputs of the hidden layers are dropped out at random, and batch
+    /// normalization uses the statistics of each batch.
+    Train,
+    /// Inference: every neuron is used, batch normalization uses its running statistics and the
+    /// outputs are deterministic.
+    #[default]
+    Eval,
+}
+
+/// Returns `true` if `rate` is a valid dropout rate, in `[0, 1)`.
+pub(crate) fn is_valid_rate(rate: f64) -> bool {
+    (0.0..1.0).contains(&rate)
+}
+
+/// Draws a new dropout mask, with each entry `0` with probability `rate` and `1 / (1 - rate)` otherwise.
+///
+/// # Arguments
+/// * `rng` - The generator the mask is drawn from.
+/// * `rate` - The probability of dropping each output, in `[0, 1)`.
+/// * `mask` - The mask to overwrite, with the shape of the outputs it applies to.
+pub fn sample_mask<T: Float>(rng: &mut impl Rng, rate: f64, mask: &mut Matrix<T>) {
+    let scale = T::from_f64(1.0 / (1.0 - rate));
+    for value in mask.data.iter_mut() {
+        *value = if rng.gen::<f64>() < rate { T::zero() } else { scale };
+    }
+}
+
+#[cfg(test)]
+/// Tests for the dropout masks.
+///
+/// The tests cover:
+/// - The fraction of dropped entries and the scale of the kept ones
+/// - Reproducing a mask from the same seed
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_sample_mask() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut mask = Matrix::<f64>::zeros(100, 100);
+        sample_mask(&mut rng, 0.25, &mut mask);
+        let dropped = mask.data.iter().filter(|&&value| value == 0.0).count();
+        assert!((2300..2700).contains(&dropped), "{} of 10000 entries dropped", dropped);
+        assert!(mask.data.iter().all(|&value| value == 0.0 || value == 1.0 / 0.75));
+
+        sample_mask(&mut rng, 0.0, &mut mask);
+        assert!(mask.data.iter().all(|&value| value == 1.0));
+        assert!(!is_valid_rate(1.0) && !is_valid_rate(-0.1) && is_valid_rate(0.5));
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_mask() {
+        let mut first = Matrix::<f32>::zeros(4, 8);
+        let mut second = Matrix::<f32>::zeros(4, 8);
+        sample_mask(&mut ChaCha8Rng::seed_from_u64(7), 0.5, &mut first);
+        sample_mask(&mut ChaCha8Rng::seed_from_u64(7), 0.5, &mut second);
+        assert_eq!(first, second);
+    }
+}
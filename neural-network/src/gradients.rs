@@ -0,0 +1,187 @@
+//! The gradients of the loss with respect to the parameters of a `Network`.
+//!
+//! `Network::compute_gradients` returns a `Gradients` without modifying the network, so the
+//! gradients can be inspected, accumulated over several batches, averaged across threads or
+//! clipped before `Network::apply_gradients` hands them to the optimizer.
+//!
+//! `Network::check_gradients` compares these gradients with finite differences of the loss and
+//! reports a `GradientCheck`, to verify the derivatives of the activations and losses.
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+
+/// The gradient of the loss with respect to every weight matrix and bias vector of a network,
+/// and to the scale and shift of its normalized layers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradients<T: Float = f64> {
+    /// The gradient of each weight matrix, with the same shape as the weights.
+    pub weights: Vec<Matrix<T>>,
+    /// The gradient of each bias vector, with the same shape as the biases.
+    pub biases: Vec<Matrix<T>>,
+    /// The gradient of the `gamma` of each layer, empty for the layers without normalization.
+    pub gammas: Vec<Matrix<T>>,
+    /// The gradient of the `beta` of each layer, empty for the layers without normalization.
+    pub betas: Vec<Matrix<T>>,
+}
+
+impl<T: Float> Default for Gradients<T> {
+    /// Creates gradients for a network without layers.
+    fn default() -> Self {
+        Gradients {
+            weights: vec![],
+            biases: vec![],
+            gammas: vec![],
+            betas: vec![],
+        }
+    }
+}
+
+impl<T: Float> Gradients<T> {
+    /// Creates zero gradients for a network with the given layer sizes and no normalization.
+    ///
+    /// # Arguments
+    /// * `layers` - The number of neurons in each layer of the network.
+    pub fn zeros(layers: &[usize]) -> Self {
+        Gradients::with_normalized(layers, &vec![false; layers.len() - 1])
+    }
+
+    /// Creates zero gradients for a network with the given layer sizes and normalized layers.
+    ///
+    /// # Arguments
+    /// * `layers` - The number of neurons in each layer of the network.
+    /// * `normalized` - Whether each layer after the input layer is normalized.
+    pub fn with_normalized(layers: &[usize], normalized: &[bool]) -> Self {
+        let normalization = || {
+            layers[1..]
+                .iter()
+                .zip(normalized)
+                .map(|(&size, &normalized)| Matrix::zeros(if normalized { size } else { 0 }, 1))
+                .collect()
+        };
+        Gradients {
+            weights: layers.windows(2).map(|pair| Matrix::zeros(pair[1], pair[0])).collect(),
+            biases: layers[1..].iter().map(|&size| Matrix::zeros(size, 1)).collect(),
+            gammas: normalization(),
+            betas: normalization(),
+        }
+    }
+
+    /// Returns every gradient matrix, the weights of each layer followed by its biases, then the
+    /// `gamma` of each layer followed by its `beta`.
+    ///
+    /// The position of a matrix is the parameter index the network passes to the optimizer.
+    pub fn iter(&self) -> impl Iterator<Item = &Matrix<T>> {
+        let parameters = self.weights.iter().zip(&self.biases).flat_map(|(weights, biases)| [weights, biases]);
+        parameters.chain(self.gammas.iter().zip(&self.betas).flat_map(|(gamma, beta)| [gamma, beta]))
+    }
+
+    /// Returns every gradient matrix mutably, in the order of `iter`.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Matrix<T>> {
+        let parameters = self
+            .weights
+            .iter_mut()
+            .zip(self.biases.iter_mut())
+            .flat_map(|(weights, biases)| [weights, biases]);
+        parameters.chain(self.gammas.iter_mut().zip(self.betas.iter_mut()).flat_map(|(gamma, beta)| [gamma, beta]))
+    }
+
+    /// Adds `other` to these gradients, e.g. to accumulate the gradients of several batches.
+    ///
+    /// # Panics
+    /// Panics if the gradients belong to networks with different layer sizes.
+    pub fn add_assign(&mut self, other: &Gradients<T>) {
+        assert!(self.weights.len() == other.weights.len(), "Gradients of different networks");
+        for (gradient, other) in self.iter_mut().zip(other.iter()) {
+            gradient.add_assign(other);
+        }
+    }
+
+    /// Multiplies every gradient by `factor`, e.g. to average accumulated gradients.
+    pub fn scale_inplace(&mut self, factor: T) {
+        self.iter_mut().for_each(|gradient| gradient.scale_inplace(factor));
+    }
+
+    /// Returns the Euclidean norm of all the gradients taken together.
+    pub fn global_norm(&self) -> T {
+        self.iter()
+            .flat_map(|gradient| gradient.data.iter())
+            .map(|&value| value * value)
+            .sum::<T>()
+            .sqrt()
+    }
+
+    /// Scales the gradients down so that their global norm is at most `max_norm`.
+    ///
+    /// # Arguments
+    /// * `max_norm` - The largest global norm allowed.
+    ///
+    /// # Returns
+    /// The global norm before clipping.
+    pub fn clip_by_norm(&mut self, max_norm: T) -> T {
+        let norm = self.global_norm();
+        if norm > max_norm {
+            self.scale_inplace(max_norm / norm);
+        }
+        norm
+    }
+}
+
+/// The result of `Network::check_gradients`: the largest relative error between the analytic
+/// and the numerical gradients, for the weights, the biases, and the scale and shift of each layer.
+///
+/// With `f64` parameters, errors below about `1e-6` indicate correct gradients; errors above
+/// `1e-2` almost always point to a bug in a derivative. Kinks such as that of ReLU at zero can
+/// produce isolated large errors when a pre-activation lies within `epsilon` of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GradientCheck<T: Float = f64> {
+    /// The largest relative error among the weights of each layer.
+    pub weights: Vec<T>,
+    /// The largest relative error among the biases of each layer.
+    pub biases: Vec<T>,
+    /// The largest relative error among the `gamma` of each layer, zero without normalization.
+    pub gammas: Vec<T>,
+    /// The largest relative error among the `beta` of each layer, zero without normalization.
+    pub betas: Vec<T>,
+}
+
+impl<T: Float> GradientCheck<T> {
+    /// Returns the largest relative error over every layer.
+    pub fn max_error(&self) -> T {
+        let errors = self.weights.iter().chain(&self.biases).chain(&self.gammas).chain(&self.betas);
+        errors.fold(T::zero(), |max, &error| max.max(error))
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `Gradients` helpers.
+///
+/// The tests cover:
+/// - Accumulating and averaging
+/// - The global norm and clipping by norm
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulate_and_average() {
+        let mut total: Gradients = Gradients::zeros(&[2, 1]);
+        let mut gradients = Gradients::zeros(&[2, 1]);
+        gradients.weights[0] = Matrix::new(1, 2, vec![1.0, 2.0]);
+        gradients.biases[0] = Matrix::from(vec![3.0]);
+        total.add_assign(&gradients);
+        total.add_assign(&gradients);
+        total.scale_inplace(0.5);
+        assert_eq!(total, gradients);
+    }
+
+    #[test]
+    fn test_clip_by_norm() {
+        let mut gradients: Gradients = Gradients::zeros(&[2, 1]);
+        gradients.weights[0] = Matrix::new(1, 2, vec![3.0, 0.0]);
+        gradients.biases[0] = Matrix::from(vec![4.0]);
+        assert_eq!(gradients.global_norm(), 5.0);
+        assert_eq!(gradients.clip_by_norm(10.0), 5.0);
+        assert_eq!(gradients.weights[0].data, vec![3.0, 0.0]);
+        assert_eq!(gradients.clip_by_norm(1.0), 5.0);
+        assert!((gradients.global_norm() - 1.0).abs() < 1e-12);
+        assert_eq!(gradients.biases[0].data, vec![0.8]);
+    }
+}
@@ -0,0 +1,189 @@
+//! The record of a training run, returned by `Network::train`.
+//!
+//! A `TrainingHistory` holds one `EpochRecord` per epoch, with the training loss, the validation
+//! loss and metrics when a validation set is used, the learning rate and the wall time of the
+//! epoch. It can be exported as CSV, e.g. for plotting in a spreadsheet, or as JSON.
+use crate::serialization::SerializationError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// What happened during one epoch of training.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EpochRecord {
+    /// The number of the epoch, starting at one.
+    pub epoch: usize,
+    /// The mean training loss over the samples of the epoch.
+    pub loss: f64,
+    /// The loss on the validation set at the end of the epoch, if one is used.
+    pub validation_loss: Option<f64>,
+    /// The metrics computed at the end of the epoch, by name.
+    pub metrics: BTreeMap<String, f64>,
+    /// The learning rate of the last step of the epoch.
+    pub learning_rate: f64,
+    /// The wall time of the epoch, in seconds.
+    pub seconds: f64,
+}
+
+/// The record of every epoch of a training run.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrainingHistory {
+    /// The record of each epoch, in order.
+    pub epochs: Vec<EpochRecord>,
+}
+
+impl TrainingHistory {
+    /// Creates an empty history.
+    pub fn new() -> Self {
+        TrainingHistory::default()
+    }
+
+    /// Returns the number of recorded epochs.
+    pub fn len(&self) -> usize {
+        self.epochs.len()
+    }
+
+    /// Returns whether no epoch was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.epochs.is_empty()
+    }
+
+    /// Returns the record of the last epoch.
+    pub fn last(&self) -> Option<&EpochRecord> {
+        self.epochs.last()
+    }
+
+    /// Returns the training loss of every epoch.
+    pub fn losses(&self) -> Vec<f64> {
+        self.epochs.iter().map(|record| record.loss).collect()
+    }
+
+    /// Returns the validation loss of every epoch that has one.
+    pub fn validation_losses(&self) -> Vec<f64> {
+        self.epochs.iter().filter_map(|record| record.validation_loss).collect()
+    }
+
+    /// Returns the total wall time of the recorded epochs, in seconds.
+    pub fn total_seconds(&self) -> f64 {
+        self.epochs.iter().map(|record| record.seconds).sum()
+    }
+
+    /// Writes the history as CSV, one row per epoch.
+    ///
+    /// The columns are `epoch`, `loss`, `validation_loss`, `learning_rate`, `seconds`, then one
+    /// column per metric in alphabetical order. Missing values are left empty.
+    ///
+    /// # Arguments
+    /// * `writer` - Where to write the CSV.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        let metrics: BTreeSet<&String> = self.epochs.iter().flat_map(|record| record.metrics.keys()).collect();
+        let metrics: Vec<&str> = metrics.into_iter().map(String::as_str).collect();
+        write_csv_header(&mut writer, &metrics)?;
+        for record in &self.epochs {
+            write_csv_row(&mut writer, record, &metrics)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the history as JSON.
+    ///
+    /// # Arguments
+    /// * `writer` - Where to write the JSON.
+    pub fn write_json(&self, mut writer: impl Write) -> Result<(), SerializationError> {
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the history to a file, as CSV if the path ends in `.csv` and as JSON otherwise.
+    ///
+    /// # Arguments
+    /// * `path` - The file to create or overwrite.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SerializationError> {
+        let path = path.as_ref();
+        let writer = io::BufWriter::new(std::fs::File::create(path)?);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => Ok(self.write_csv(writer)?),
+            _ => self.write_json(writer),
+        }
+    }
+}
+
+/// Writes the header of the CSV form of a history with the given metric columns.
+pub(crate) fn write_csv_header(writer: &mut impl Write, metrics: &[&str]) -> io::Result<()> {
+    write!(writer, "epoch,loss,validation_loss,learning_rate,seconds")?;
+    for name in metrics {
+        write!(writer, ",{}", name)?;
+    }
+    writeln!(writer)
+}
+
+/// Writes one epoch as a row of the CSV form of a history, leaving missing values empty.
+pub(crate) fn write_csv_row(writer: &mut impl Write, record: &EpochRecord, metrics: &[&str]) -> io::Result<()> {
+    let optional = |value: Option<&f64>| value.map(f64::to_string).unwrap_or_default();
+    write!(
+        writer,
+        "{},{},{},{},{}",
+        record.epoch,
+        record.loss,
+        optional(record.validation_loss.as_ref()),
+        record.learning_rate,
+        record.seconds
+    )?;
+    for name in metrics {
+        write!(writer, ",{}", optional(record.metrics.get(*name)))?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+/// Tests for the `TrainingHistory`.
+///
+/// The tests cover:
+/// - Exporting to CSV with optional values and metrics
+/// - Round trips through JSON
+mod tests {
+    use super::*;
+
+    fn history() -> TrainingHistory {
+        let mut second = EpochRecord {
+            epoch: 2,
+            loss: 0.25,
+            validation_loss: Some(0.5),
+            learning_rate: 0.05,
+            seconds: 0.5,
+            ..EpochRecord::default()
+        };
+        second.metrics.insert("accuracy".to_string(), 0.75);
+        let first = EpochRecord {
+            epoch: 1,
+            loss: 0.5,
+            learning_rate: 0.1,
+            seconds: 1.0,
+            ..EpochRecord::default()
+        };
+        TrainingHistory { epochs: vec![first, second] }
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut csv = vec![];
+        history().write_csv(&mut csv).unwrap();
+        let expected = "epoch,loss,validation_loss,learning_rate,seconds,accuracy\n\
+                        1,0.5,,0.1,1,\n\
+                        2,0.25,0.5,0.05,0.5,0.75\n";
+        assert_eq!(String::from_utf8(csv).unwrap(), expected);
+        assert_eq!(history().losses(), vec![0.5, 0.25]);
+        assert_eq!(history().validation_losses(), vec![0.5]);
+        assert_eq!(history().total_seconds(), 1.5);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut json = vec![];
+        history().write_json(&mut json).unwrap();
+        let parsed: TrainingHistory = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed, history());
+    }
+}
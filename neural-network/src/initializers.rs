@@ -0,0 +1,266 @@
+//! Strategies for the initial values of the weights and biases of a `Network`.
+//!
+//! The variance scaling initializers (Xavier/Glorot, He/Kaiming and LeCun) pick the spread of the
+//! initial values from the number of inputs (`fan_in`) and outputs (`fan_out`) of a layer, so the
+//! magnitude of the signal stays roughly constant from one layer to the next.
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use rand::Rng;
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A strategy for initializing a weight or bias matrix.
+pub enum Initializer {
+    /// Draws every value uniformly from `[low, high)`.
+    Uniform { low: f64, high: f64 },
+    /// Draws every value from a normal distribution.
+    Normal { mean: f64, std_dev: f64 },
+    /// Draws every value from a normal distribution, redrawing values more than two standard
+    /// deviations away from the mean.
+    TruncatedNormal { mean: f64, std_dev: f64 },
+    /// Xavier/Glorot uniform: `U(-l, l)` with `l = sqrt(6 / (fan_in + fan_out))`.
+    XavierUniform,
+    /// Xavier/Glorot normal: `N(0, 2 / (fan_in + fan_out))`.
+    XavierNormal,
+    /// He/Kaiming uniform, suited to ReLU layers: `U(-l, l)` with `l = sqrt(6 / fan_in)`.
+    HeUniform,
+    /// He/Kaiming normal, suited to ReLU layers: `N(0, 2 / fan_in)`.
+    HeNormal,
+    /// LeCun uniform, suited to SELU layers: `U(-l, l)` with `l = sqrt(3 / fan_in)`.
+    LeCunUniform,
+    /// LeCun normal, suited to SELU layers: `N(0, 1 / fan_in)`.
+    LeCunNormal,
+    /// A (semi-)orthogonal matrix scaled by `gain`, obtained by orthonormalizing a matrix of
+    /// standard normal values.
+    Orthogonal { gain: f64 },
+    /// Sets every value to zero.
+    Zeros,
+    /// Sets every value to the given constant.
+    Constant(f64),
+}
+
+impl Default for Initializer {
+    /// Uniform in `[0, 1)`, the historical behaviour of `Network::new`.
+    fn default() -> Self {
+        Initializer::Uniform { low: 0.0, high: 1.0 }
+    }
+}
+
+impl Initializer {
+    /// Creates a matrix initialized with this strategy.
+    ///
+    /// # Arguments
+    /// * `rng` - The random number generator to draw the values from.
+    /// * `rows` - The number of rows in the matrix.
+    /// * `cols` - The number of columns in the matrix.
+    /// * `fan_in` - The number of inputs of the layer the matrix belongs to.
+    /// * `fan_out` - The number of outputs of the layer the matrix belongs to.
+    ///
+    /// # Returns
+    /// A new `rows x cols` matrix.
+    pub fn initialize<T: Float>(
+        &self,
+        rng: &mut impl Rng,
+        rows: usize,
+        cols: usize,
+        fan_in: usize,
+        fan_out: usize,
+    ) -> Matrix<T> {
+        let len = rows * cols;
+        let (fan_in, fan_out) = (fan_in as f64, fan_out as f64);
+        let data = match *self {
+            Initializer::Uniform { low, high } => uniform(rng, len, T::from_f64(low), T::from_f64(high)),
+            Initializer::Normal { mean, std_dev } => normal(rng, len, mean, std_dev, false),
+            Initializer::TruncatedNormal { mean, std_dev } => normal(rng, len, mean, std_dev, true),
+            Initializer::XavierUniform => symmetric_uniform(rng, len, (6.0 / (fan_in + fan_out)).sqrt()),
+            Initializer::XavierNormal => normal(rng, len, 0.0, (2.0 / (fan_in + fan_out)).sqrt(), false),
+            Initializer::HeUniform => symmetric_uniform(rng, len, (6.0 / fan_in).sqrt()),
+            Initializer::HeNormal => normal(rng, len, 0.0, (2.0 / fan_in).sqrt(), false),
+            Initializer::LeCunUniform => symmetric_uniform(rng, len, (3.0 / fan_in).sqrt()),
+            Initializer::LeCunNormal => normal(rng, len, 0.0, (1.0 / fan_in).sqrt(), false),
+            Initializer::Orthogonal { gain } => orthogonal(rng, rows, cols, gain),
+            Initializer::Zeros => vec![T::zero(); len],
+            Initializer::Constant(value) => vec![T::from_f64(value); len],
+        };
+        Matrix { rows, cols, data }
+    }
+
+    /// Returns `true` if a parameter is not finite, the uniform range is empty or a standard
+    /// deviation is negative.
+    pub(crate) fn is_invalid(&self) -> bool {
+        match *self {
+            Initializer::Uniform { low, high } => !(low.is_finite() && high.is_finite() && low < high),
+            Initializer::Normal { mean, std_dev } | Initializer::TruncatedNormal { mean, std_dev } => {
+                !(mean.is_finite() && std_dev.is_finite() && std_dev >= 0.0)
+            }
+            Initializer::Orthogonal { gain: value } | Initializer::Constant(value) => !value.is_finite(),
+            _ => false,
+        }
+    }
+}
+
+fn uniform<T: Float>(rng: &mut impl Rng, len: usize, low: T, high: T) -> Vec<T> {
+    (0..len).map(|_| rng.gen_range(low..high)).collect()
+}
+
+fn symmetric_uniform<T: Float>(rng: &mut impl Rng, len: usize, limit: f64) -> Vec<T> {
+    uniform(rng, len, T::from_f64(-limit), T::from_f64(limit))
+}
+
+/// Draws a standard normal value with the Box-Muller transform.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    // `1 - u` is in `(0, 1]`, so the logarithm is finite.
+    let radius = (-2.0 * (1.0 - rng.gen::<f64>()).ln()).sqrt();
+    radius * (2.0 * PI * rng.gen::<f64>()).cos()
+}
+
+fn normal<T: Float>(rng: &mut impl Rng, len: usize, mean: f64, std_dev: f64, truncated: bool) -> Vec<T> {
+    (0..len)
+        .map(|_| {
+            let mut value = standard_normal(rng);
+            while truncated && value.abs() > 2.0 {
+                value = standard_normal(rng);
+            }
+            T::from_f64(mean + std_dev * value)
+        })
+        .collect()
+}
+
+/// Creates a `rows x cols` matrix whose rows (if `rows <= cols`) or columns are orthonormal.
+fn orthogonal<T: Float>(rng: &mut impl Rng, rows: usize, cols: usize, gain: f64) -> Vec<T> {
+    // Orthonormalize the longer vectors of a random normal matrix with modified Gram-Schmidt.
+    let (count, len) = if rows <= cols { (rows, cols) } else { (cols, rows) };
+    let mut vectors: Vec<Vec<f64>> = (0..count)
+        .map(|_| (0..len).map(|_| standard_normal(rng)).collect())
+        .collect();
+    for i in 0..count {
+        for j in 0..i {
+            let projection: f64 = vectors[i].iter().zip(&vectors[j]).map(|(a, b)| a * b).sum();
+            let (done, rest) = vectors.split_at_mut(i);
+            for (value, basis) in rest[0].iter_mut().zip(&done[j]) {
+                *value -= projection * basis;
+            }
+        }
+        let norm = vectors[i].iter().map(|value| value * value).sum::<f64>().sqrt();
+        vectors[i].iter_mut().for_each(|value| *value /= norm);
+    }
+    let mut data = vec![T::zero(); rows * cols];
+    for (i, vector) in vectors.iter().enumerate() {
+        for (j, &value) in vector.iter().enumerate() {
+            let index = if rows <= cols { i * cols + j } else { j * cols + i };
+            data[index] = T::from_f64(gain * value);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+/// Tests for the `Initializer` strategies.
+///
+/// The tests cover:
+/// - The default matching `Matrix::random_with`
+/// - The bounds of the uniform strategies
+/// - The mean and spread of the normal strategies
+/// - The orthonormality of the orthogonal strategy
+/// - The constant strategies
+/// - Rejecting invalid parameters
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn sample(initializer: Initializer, rows: usize, cols: usize) -> Matrix<f64> {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        initializer.initialize(&mut rng, rows, cols, cols, rows)
+    }
+
+    fn mean_and_std_dev(data: &[f64]) -> (f64, f64) {
+        let mean = data.iter().sum::<f64>() / data.len() as f64;
+        let variance = data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / data.len() as f64;
+        (mean, variance.sqrt())
+    }
+
+    #[test]
+    fn test_default_matches_random_with() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let expected: Matrix<f64> = Matrix::random_with(&mut rng, 4, 5);
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        assert_eq!(Initializer::default().initialize(&mut rng, 4, 5, 5, 4), expected);
+    }
+
+    #[test]
+    fn test_uniform_bounds() {
+        let limit = (6.0f64 / (200.0 + 100.0)).sqrt();
+        let matrix = sample(Initializer::XavierUniform, 100, 200);
+        assert!(matrix.data.iter().all(|x| x.abs() < limit));
+        assert!(matrix.data.iter().any(|&x| x < 0.0));
+
+        let limit = (6.0f64 / 200.0).sqrt();
+        let matrix = sample(Initializer::HeUniform, 100, 200);
+        assert!(matrix.data.iter().all(|x| x.abs() < limit));
+
+        let limit = (3.0f64 / 200.0).sqrt();
+        let matrix = sample(Initializer::LeCunUniform, 100, 200);
+        assert!(matrix.data.iter().all(|x| x.abs() < limit));
+    }
+
+    #[test]
+    fn test_normal_statistics() {
+        let cases = [
+            (Initializer::Normal { mean: 1.0, std_dev: 0.5 }, 1.0, 0.5),
+            (Initializer::XavierNormal, 0.0, (2.0f64 / 300.0).sqrt()),
+            (Initializer::HeNormal, 0.0, (2.0f64 / 200.0).sqrt()),
+            (Initializer::LeCunNormal, 0.0, (1.0f64 / 200.0).sqrt()),
+        ];
+        for (initializer, expected_mean, expected_std_dev) in cases {
+            let matrix = sample(initializer, 100, 200);
+            let (mean, std_dev) = mean_and_std_dev(&matrix.data);
+            assert!((mean - expected_mean).abs() < 0.05 * expected_std_dev.max(1.0), "{:?}", initializer);
+            assert!((std_dev / expected_std_dev - 1.0).abs() < 0.05, "{:?}", initializer);
+        }
+    }
+
+    #[test]
+    fn test_truncated_normal_stays_within_two_std_devs() {
+        let matrix = sample(Initializer::TruncatedNormal { mean: 3.0, std_dev: 0.1 }, 100, 200);
+        assert!(matrix.data.iter().all(|x| (x - 3.0).abs() <= 0.2 + 1e-12));
+    }
+
+    #[test]
+    fn test_orthogonal() {
+        for (rows, cols) in [(4, 4), (3, 7), (7, 3), (5, 1)] {
+            let matrix = sample(Initializer::Orthogonal { gain: 2.0 }, rows, cols);
+            // The shorter dimension gives the orthonormal vectors, so check `W Wᵀ` or `Wᵀ W`.
+            let product = if rows <= cols {
+                matrix.dot_multiply(&matrix.transpose())
+            } else {
+                matrix.transpose().dot_multiply(&matrix)
+            };
+            for i in 0..product.rows {
+                for j in 0..product.cols {
+                    let expected = if i == j { 4.0 } else { 0.0 };
+                    assert!((product.data[i * product.cols + j] - expected).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant_initializers() {
+        assert!(sample(Initializer::Zeros, 3, 2).data.iter().all(|&x| x == 0.0));
+        let matrix: Matrix<f32> = Initializer::Constant(0.25).initialize(&mut ChaCha8Rng::seed_from_u64(0), 3, 2, 2, 3);
+        assert_eq!(matrix, Matrix::new(3, 2, vec![0.25; 6]));
+    }
+
+    #[test]
+    fn test_is_invalid() {
+        assert!(!Initializer::default().is_invalid());
+        assert!(!Initializer::HeNormal.is_invalid());
+        assert!(!Initializer::Normal { mean: 0.0, std_dev: 0.0 }.is_invalid());
+        assert!(Initializer::Uniform { low: 1.0, high: 1.0 }.is_invalid());
+        assert!(Initializer::Uniform { low: 0.0, high: f64::INFINITY }.is_invalid());
+        assert!(Initializer::Normal { mean: 0.0, std_dev: -1.0 }.is_invalid());
+        assert!(Initializer::TruncatedNormal { mean: 0.0, std_dev: f64::NAN }.is_invalid());
+        assert!(Initializer::Constant(f64::NAN).is_invalid());
+    }
+}
@@ -2,8 +2,24 @@
 extern crate derive_builder;
 pub mod network;
 pub mod activations;
+pub mod builder;
+pub mod callbacks;
+pub mod checkpoint;
+pub mod dropout;
+pub mod gradients;
+pub mod history;
+pub mod initializers;
+pub mod losses;
+pub mod metrics;
+pub mod normalization;
+pub mod optimizers;
+pub mod regularizers;
+pub mod schedules;
+pub mod serialization;
 
 pub mod matrix { 
 
+    pub use matrix::error::MatrixError;
     pub use matrix::matrix::Matrix;
+    pub use matrix::scalar::{Float, Scalar};
 }
\ No newline at end of file
@@ -0,0 +1,343 @@
+//! Loss functions measuring how far the outputs of a `Network` are from the targets.
+//!
+//! Each column of the `outputs` and `targets` matrices is one sample. The regression losses
+//! average the error over the output neurons of a sample, like the metrics of the same name;
+//! the classification losses sum it over the classes. The loss of a batch is the mean over its
+//! samples, so `gradient` divides by the number of samples.
+use crate::activations::Activation;
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use std::fmt::Debug;
+
+/// A loss function with its gradient.
+pub trait Loss<T: Float>: Debug + Send + Sync {
+    /// Returns the mean loss over the samples in `outputs`.
+    ///
+    /// # Arguments
+    /// * `outputs` - The outputs of the network, one column per sample.
+    /// * `targets` - The expected outputs, with the same shape as `outputs`.
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T;
+
+    /// Computes the gradient of `value` with respect to the outputs.
+    ///
+    /// # Arguments
+    /// * `outputs` - The outputs of the network, one column per sample.
+    /// * `targets` - The expected outputs, with the same shape as `outputs`.
+    /// * `gradient` - The matrix to write the gradient to, with the same shape as `outputs`.
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>);
+
+    /// Computes the gradient of `value` with respect to the inputs of the output activation.
+    ///
+    /// The default backpropagates `gradient` through `activation`. Losses override it when
+    /// combining both steps is simpler and more stable, as for cross-entropy after softmax.
+    ///
+    /// # Arguments
+    /// * `activation` - The activation function of the output layer.
+    /// * `inputs` - The inputs of the output activation.
+    /// * `outputs` - The outputs of the network, one column per sample.
+    /// * `targets` - The expected outputs, with the same shape as `outputs`.
+    /// * `gradient` - The matrix to write the gradient to, with the same shape as `outputs`.
+    fn output_gradient(
+        &self,
+        activation: Activation,
+        inputs: &Matrix<T>,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+        gradient: &mut Matrix<T>,
+    ) {
+        self.gradient(outputs, targets, gradient);
+        activation.backward(inputs, outputs, gradient);
+    }
+}
+
+/// The mean squared error over the output neurons, `1/n sum (y - t)^2` for `n` outputs, as
+/// computed by `Metric::MeanSquaredError`.
+///
+/// Its gradient is `2 (y - t) / n`. This is the default loss of a `Network`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeanSquaredError;
+
+/// The mean absolute error over the output neurons, `1/n sum |y - t|` for `n` outputs, as
+/// computed by `Metric::MeanAbsoluteError`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeanAbsoluteError;
+
+/// Squared error for errors up to `delta`, and absolute error beyond it, which makes the loss
+/// less sensitive to outliers than `MeanSquaredError`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Huber {
+    /// The size of the error at which the loss becomes linear.
+    pub delta: f64,
+}
+
+impl Default for Huber {
+    fn default() -> Self {
+        Huber { delta: 1.0 }
+    }
+}
+
+/// Binary cross-entropy, `-sum t ln(y) + (1 - t) ln(1 - y)`, for outputs in `(0, 1)`.
+///
+/// After a `Sigmoid` output the gradient is computed directly as `y - t`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BinaryCrossEntropy;
+
+/// Categorical cross-entropy, `-sum t ln(y)`, for one-hot or probability targets.
+///
+/// After a `Softmax` output the gradient is computed directly as `y - t`, which avoids dividing
+/// by probabilities close to zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CategoricalCrossEntropy;
+
+/// Hinge loss, `sum max(0, 1 - t y)`, for targets of `-1` or `1`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Hinge;
+
+/// Sums `loss(y, t)` over every element and divides by the number of samples.
+fn mean_value<T: Float>(outputs: &Matrix<T>, targets: &Matrix<T>, loss: impl Fn(T, T) -> T) -> T {
+    assert_same_shape(outputs, targets);
+    let total: T = outputs.data.iter().zip(&targets.data).map(|(&y, &t)| loss(y, t)).sum();
+    total / T::from_f64(outputs.cols as f64)
+}
+
+/// Writes `derivative(y, t)` divided by the number of samples into `gradient`.
+fn mean_gradient<T: Float>(
+    outputs: &Matrix<T>,
+    targets: &Matrix<T>,
+    gradient: &mut Matrix<T>,
+    derivative: impl Fn(T, T) -> T,
+) {
+    assert_same_shape(outputs, targets);
+    assert_same_shape(outputs, gradient);
+    let samples = T::from_f64(outputs.cols as f64);
+    for ((gradient, &y), &t) in gradient.data.iter_mut().zip(&outputs.data).zip(&targets.data) {
+        *gradient = derivative(y, t) / samples;
+    }
+}
+
+fn assert_same_shape<T: Float>(outputs: &Matrix<T>, other: &Matrix<T>) {
+    assert!(
+        outputs.shape() == other.shape(),
+        "Invalid Number of Targets: expected {}x{}, got {}x{}",
+        outputs.rows,
+        outputs.cols,
+        other.rows,
+        other.cols
+    );
+}
+
+/// Keeps a probability away from 0 and 1 so that its logarithm stays finite.
+fn clamp_probability<T: Float>(y: T) -> T {
+    let epsilon = T::from_f64(1e-7);
+    y.max(epsilon).min(T::one() - epsilon)
+}
+
+impl<T: Float> Loss<T> for MeanSquaredError {
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T {
+        let neurons = T::from_f64(outputs.rows as f64);
+        mean_value(outputs, targets, |y, t| (y - t) * (y - t) / neurons)
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>) {
+        let scale = T::from_f64(2.0 / outputs.rows as f64);
+        mean_gradient(outputs, targets, gradient, |y, t| scale * (y - t))
+    }
+}
+
+impl<T: Float> Loss<T> for MeanAbsoluteError {
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T {
+        let neurons = T::from_f64(outputs.rows as f64);
+        mean_value(outputs, targets, |y, t| (y - t).abs() / neurons)
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>) {
+        let neurons = T::from_f64(outputs.rows as f64);
+        mean_gradient(outputs, targets, gradient, |y, t| {
+            if y > t {
+                T::one() / neurons
+            } else if y < t {
+                -T::one() / neurons
+            } else {
+                T::zero()
+            }
+        })
+    }
+}
+
+impl<T: Float> Loss<T> for Huber {
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T {
+        let delta = T::from_f64(self.delta);
+        let half = T::from_f64(0.5);
+        mean_value(outputs, targets, |y, t| {
+            let error = (y - t).abs();
+            if error <= delta {
+                half * error * error
+            } else {
+                delta * (error - half * delta)
+            }
+        })
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>) {
+        let delta = T::from_f64(self.delta);
+        mean_gradient(outputs, targets, gradient, |y, t| (y - t).max(-delta).min(delta))
+    }
+}
+
+impl<T: Float> Loss<T> for BinaryCrossEntropy {
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T {
+        mean_value(outputs, targets, |y, t| {
+            let y = clamp_probability(y);
+            -(t * y.ln() + (T::one() - t) * (T::one() - y).ln())
+        })
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>) {
+        mean_gradient(outputs, targets, gradient, |y, t| {
+            let y = clamp_probability(y);
+            (y - t) / (y * (T::one() - y))
+        })
+    }
+
+    fn output_gradient(
+        &self,
+        activation: Activation,
+        inputs: &Matrix<T>,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+        gradient: &mut Matrix<T>,
+    ) {
+        if activation == Activation::Sigmoid {
+            mean_gradient(outputs, targets, gradient, |y, t| y - t);
+        } else {
+            self.gradient(outputs, targets, gradient);
+            activation.backward(inputs, outputs, gradient);
+        }
+    }
+}
+
+impl<T: Float> Loss<T> for CategoricalCrossEntropy {
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T {
+        mean_value(outputs, targets, |y, t| -t * clamp_probability(y).ln())
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>) {
+        mean_gradient(outputs, targets, gradient, |y, t| -t / clamp_probability(y))
+    }
+
+    fn output_gradient(
+        &self,
+        activation: Activation,
+        inputs: &Matrix<T>,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+        gradient: &mut Matrix<T>,
+    ) {
+        // With one-hot targets, `d/dz -ln(softmax(z)_k)` is `softmax(z) - t`.
+        if activation == Activation::Softmax {
+            mean_gradient(outputs, targets, gradient, |y, t| y - t);
+        } else {
+            self.gradient(outputs, targets, gradient);
+            activation.backward(inputs, outputs, gradient);
+        }
+    }
+}
+
+impl<T: Float> Loss<T> for Hinge {
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> T {
+        mean_value(outputs, targets, |y, t| (T::one() - t * y).max(T::zero()))
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>, gradient: &mut Matrix<T>) {
+        mean_gradient(outputs, targets, gradient, |y, t| if t * y < T::one() { -t } else { T::zero() })
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `Loss` implementations.
+///
+/// The tests cover:
+/// - Known loss values, with the mean squared and absolute errors matching their metrics
+/// - Every gradient against central finite differences
+/// - The fused softmax and sigmoid gradients against the unfused ones
+mod tests {
+    use super::*;
+    use crate::metrics::Metric;
+
+    fn matrix(rows: usize, cols: usize, data: &[f64]) -> Matrix<f64> {
+        Matrix::new(rows, cols, data.to_vec())
+    }
+
+    #[test]
+    fn test_values() {
+        let outputs = matrix(2, 2, &[0.5, 1.0, 2.0, -1.0]);
+        let targets = matrix(2, 2, &[1.0, 1.0, 0.0, 1.0]);
+        // Squared errors 0.25, 0, 4, 4, averaged over two outputs and two samples.
+        assert_eq!(MeanSquaredError.value(&outputs, &targets), 8.25 / 4.0);
+        // Absolute errors 0.5, 0, 2, 2, averaged over two outputs and two samples.
+        assert_eq!(MeanAbsoluteError.value(&outputs, &targets), 4.5 / 4.0);
+        let (samples, expected) = ([vec![0.5, 2.0], vec![1.0, -1.0]], [vec![1.0, 0.0], vec![1.0, 1.0]]);
+        let metric = Metric::MeanAbsoluteError.compute(&samples, &expected, 0.5);
+        assert_eq!(MeanAbsoluteError.value(&outputs, &targets), metric);
+        let metric = Metric::MeanSquaredError.compute(&samples, &expected, 0.5);
+        assert_eq!(MeanSquaredError.value(&outputs, &targets), metric);
+        assert_eq!(Huber { delta: 1.0 }.value(&outputs, &targets), (0.125 + 1.5 + 1.5) / 2.0);
+        let outputs = matrix(1, 2, &[0.5, -2.0]);
+        let targets = matrix(1, 2, &[1.0, -1.0]);
+        assert_eq!(Hinge.value(&outputs, &targets), 0.5 / 2.0);
+        let outputs = matrix(2, 1, &[0.25, 0.75]);
+        let targets = matrix(2, 1, &[0.0, 1.0]);
+        assert!((CategoricalCrossEntropy.value(&outputs, &targets) + 0.75f64.ln()).abs() < 1e-12);
+        assert!((BinaryCrossEntropy.value(&outputs, &targets) + 2.0 * 0.75f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_gradients_match_finite_differences() {
+        let losses: [&dyn Loss<f64>; 6] = [
+            &MeanSquaredError,
+            &MeanAbsoluteError,
+            &Huber { delta: 0.5 },
+            &BinaryCrossEntropy,
+            &CategoricalCrossEntropy,
+            &Hinge,
+        ];
+        // Outputs in (0, 1), away from the kinks of the absolute, Huber and hinge losses.
+        let outputs = matrix(3, 2, &[0.2, 0.7, 0.9, 0.35, 0.6, 0.1]);
+        let targets = matrix(3, 2, &[0.0, 1.0, 1.0, 0.0, 0.3, -1.0]);
+        let epsilon = 1e-6;
+        for loss in losses {
+            let mut gradient = Matrix::zeros(3, 2);
+            loss.gradient(&outputs, &targets, &mut gradient);
+            for i in 0..outputs.data.len() {
+                let mut plus = outputs.clone();
+                plus.data[i] += epsilon;
+                let mut minus = outputs.clone();
+                minus.data[i] -= epsilon;
+                let numeric = (loss.value(&plus, &targets) - loss.value(&minus, &targets)) / (2.0 * epsilon);
+                assert!((numeric - gradient.data[i]).abs() < 1e-5, "{:?}: {} vs {}", loss, gradient.data[i], numeric);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fused_gradients_match_unfused() {
+        let cases: [(&dyn Loss<f64>, Activation, &[f64]); 2] = [
+            (&CategoricalCrossEntropy, Activation::Softmax, &[0.0, 1.0, 1.0, 0.0, 0.0, 0.0]),
+            (&BinaryCrossEntropy, Activation::Sigmoid, &[0.0, 1.0, 1.0, 0.0, 1.0, 1.0]),
+        ];
+        for (loss, activation, targets) in cases {
+            let inputs = matrix(3, 2, &[0.5, -1.0, 2.0, 0.3, -0.7, 1.1]);
+            let targets = matrix(3, 2, targets);
+            let mut outputs = Matrix::zeros(3, 2);
+            activation.forward(&inputs, &mut outputs);
+            let mut fused = Matrix::zeros(3, 2);
+            loss.output_gradient(activation, &inputs, &outputs, &targets, &mut fused);
+            let mut unfused = Matrix::zeros(3, 2);
+            loss.gradient(&outputs, &targets, &mut unfused);
+            activation.backward(&inputs, &outputs, &mut unfused);
+            for (fused, unfused) in fused.data.iter().zip(&unfused.data) {
+                assert!((fused - unfused).abs() < 1e-9, "{:?}: {} vs {}", loss, fused, unfused);
+            }
+        }
+    }
+}
@@ -0,0 +1,501 @@
+//! Metrics measuring how well the outputs of a network match the targets.
+//!
+//! Classification metrics first turn every output and target into a class: a single output is
+//! the positive class `1` when it reaches a threshold and class `0` otherwise, several outputs
+//! are the class of the largest one. The counts of actual against predicted classes form a
+//! `ConfusionMatrix`, from which accuracy, precision, recall and F1 follow. `Curve` computes ROC
+//! and precision-recall curves from the raw outputs, and their area under the curve.
+//! Regression metrics compare the raw values.
+//!
+//! `Network::evaluate` computes a list of `Metric`s on a data set; the metrics selected with
+//! `NetworkBuilder::metric` are also computed on the validation set at the end of every epoch.
+//!
+//! # Example
+//! ```
+//! use neural_network::metrics::{ConfusionMatrix, Curve};
+//!
+//! let outputs = vec![vec![0.1], vec![0.4], vec![0.35], vec![0.8]];
+//! let targets = vec![vec![0.0], vec![0.0], vec![1.0], vec![1.0]];
+//! let confusion = ConfusionMatrix::from_outputs(&outputs, &targets, 0.5);
+//! assert_eq!(confusion.accuracy(), 0.75);
+//! assert_eq!(confusion.recall(), 0.5);
+//!
+//! let scores: Vec<f64> = outputs.iter().map(|output| output[0]).collect();
+//! let labels: Vec<bool> = targets.iter().map(|target| target[0] == 1.0).collect();
+//! assert_eq!(Curve::roc(&scores, &labels).auc(), 0.75);
+//! ```
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The output above which a single-output classifier predicts the positive class by default.
+pub const DEFAULT_THRESHOLD: f64 = 0.5;
+
+/// A metric computed by `Network::evaluate`.
+///
+/// For a single output, precision, recall and F1 are those of the positive class; for several
+/// outputs they are averaged over the classes, as are the areas under the curves, each class
+/// being compared against all the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Metric {
+    /// The fraction of samples whose predicted class is the actual class.
+    Accuracy,
+    /// The fraction of the samples predicted in a class that actually are in it.
+    Precision,
+    /// The fraction of the samples in a class that are predicted in it.
+    Recall,
+    /// The harmonic mean of precision and recall.
+    F1,
+    /// The area under the ROC curve.
+    RocAuc,
+    /// The area under the precision-recall curve.
+    PrAuc,
+    /// The mean of the squared differences between outputs and targets.
+    MeanSquaredError,
+    /// The mean of the absolute differences between outputs and targets.
+    MeanAbsoluteError,
+    /// The coefficient of determination, averaged over the outputs.
+    R2,
+}
+
+impl Metric {
+    /// Returns the name of the metric, used as its key in the training history.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Accuracy => "accuracy",
+            Metric::Precision => "precision",
+            Metric::Recall => "recall",
+            Metric::F1 => "f1",
+            Metric::RocAuc => "roc_auc",
+            Metric::PrAuc => "pr_auc",
+            Metric::MeanSquaredError => "mse",
+            Metric::MeanAbsoluteError => "mae",
+            Metric::R2 => "r2",
+        }
+    }
+
+    /// Computes the metric.
+    ///
+    /// # Arguments
+    /// * `outputs` - The outputs of the network, one vector per sample.
+    /// * `targets` - The targets, one vector per sample.
+    /// * `threshold` - The output from which a single-output classifier predicts the positive class.
+    ///
+    /// # Returns
+    /// The value of the metric, `NaN` when it is undefined, e.g. ROC-AUC without any positive sample.
+    pub fn compute(&self, outputs: &[Vec<f64>], targets: &[Vec<f64>], threshold: f64) -> f64 {
+        match self {
+            Metric::Accuracy => ConfusionMatrix::from_outputs(outputs, targets, threshold).accuracy(),
+            Metric::Precision => ConfusionMatrix::from_outputs(outputs, targets, threshold).precision(),
+            Metric::Recall => ConfusionMatrix::from_outputs(outputs, targets, threshold).recall(),
+            Metric::F1 => ConfusionMatrix::from_outputs(outputs, targets, threshold).f1(),
+            Metric::RocAuc => mean_over_classes(outputs, targets, threshold, |scores, labels| Curve::roc(scores, labels).auc()),
+            Metric::PrAuc => {
+                mean_over_classes(outputs, targets, threshold, |scores, labels| Curve::precision_recall(scores, labels).auc())
+            }
+            Metric::MeanSquaredError => mean_squared_error(outputs, targets),
+            Metric::MeanAbsoluteError => mean_absolute_error(outputs, targets),
+            Metric::R2 => r2_score(outputs, targets),
+        }
+    }
+}
+
+/// Turns outputs or targets into classes.
+///
+/// # Arguments
+/// * `values` - One vector per sample.
+/// * `threshold` - The value from which a single value is the positive class `1`.
+///
+/// # Returns
+/// The class of each sample: for a single value `1` if it reaches `threshold` and `0` otherwise,
+/// for several values the index of the largest.
+pub fn classes(values: &[Vec<f64>], threshold: f64) -> Vec<usize> {
+    values
+        .iter()
+        .map(|value| match value.as_slice() {
+            [single] => usize::from(*single >= threshold),
+            _ => (0..value.len()).fold(0, |best, i| if value[i] > value[best] { i } else { best }),
+        })
+        .collect()
+}
+
+/// Averages a curve metric over the classes, against all the other classes; a single output
+/// only measures the positive class.
+fn mean_over_classes(
+    outputs: &[Vec<f64>],
+    targets: &[Vec<f64>],
+    threshold: f64,
+    metric: impl Fn(&[f64], &[bool]) -> f64,
+) -> f64 {
+    let actual = classes(targets, threshold);
+    let width = outputs.first().map_or(1, Vec::len);
+    let measured: Vec<usize> = if width == 1 { vec![0] } else { (0..width).collect() };
+    let total: f64 = measured
+        .iter()
+        .map(|&class| {
+            let scores: Vec<f64> = outputs.iter().map(|output| output[class]).collect();
+            let positive = if width == 1 { 1 } else { class };
+            let labels: Vec<bool> = actual.iter().map(|&label| label == positive).collect();
+            metric(&scores, &labels)
+        })
+        .sum();
+    total / measured.len() as f64
+}
+
+/// The number of samples of each actual class predicted in each class.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfusionMatrix {
+    /// The number of classes.
+    pub classes: usize,
+    /// The counts, with a row per actual class and a column per predicted class.
+    pub counts: Vec<usize>,
+}
+
+impl ConfusionMatrix {
+    /// Counts actual against predicted classes.
+    ///
+    /// # Arguments
+    /// * `actual` - The actual class of each sample.
+    /// * `predicted` - The predicted class of each sample.
+    /// * `classes` - The number of classes.
+    ///
+    /// # Panics
+    /// Panics if the numbers of samples differ or a class is out of range.
+    pub fn new(actual: &[usize], predicted: &[usize], classes: usize) -> Self {
+        assert!(actual.len() == predicted.len(), "Different numbers of actual and predicted classes");
+        let mut counts = vec![0; classes * classes];
+        for (&actual, &predicted) in actual.iter().zip(predicted) {
+            assert!(actual < classes && predicted < classes, "Class out of range");
+            counts[actual * classes + predicted] += 1;
+        }
+        ConfusionMatrix { classes, counts }
+    }
+
+    /// Counts the classes of targets against those of outputs, as described in the module.
+    ///
+    /// # Arguments
+    /// * `outputs` - The outputs of the network, one vector per sample.
+    /// * `targets` - The targets, one vector per sample.
+    /// * `threshold` - The output from which a single-output classifier predicts the positive class.
+    pub fn from_outputs(outputs: &[Vec<f64>], targets: &[Vec<f64>], threshold: f64) -> Self {
+        let classes = targets.first().map_or(2, |target| target.len().max(2));
+        ConfusionMatrix::new(&self::classes(targets, threshold), &self::classes(outputs, threshold), classes)
+    }
+
+    /// Returns the number of samples of class `actual` predicted as `predicted`.
+    pub fn count(&self, actual: usize, predicted: usize) -> usize {
+        self.counts[actual * self.classes + predicted]
+    }
+
+    /// Returns the total number of samples.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the fraction of samples predicted correctly.
+    pub fn accuracy(&self) -> f64 {
+        let correct: usize = (0..self.classes).map(|class| self.count(class, class)).sum();
+        correct as f64 / self.total() as f64
+    }
+
+    /// Returns the precision of one class: the fraction of the samples predicted in it that are in it.
+    pub fn class_precision(&self, class: usize) -> f64 {
+        let predicted: usize = (0..self.classes).map(|actual| self.count(actual, class)).sum();
+        ratio(self.count(class, class), predicted)
+    }
+
+    /// Returns the recall of one class: the fraction of the samples in it that are predicted in it.
+    pub fn class_recall(&self, class: usize) -> f64 {
+        let actual: usize = (0..self.classes).map(|predicted| self.count(class, predicted)).sum();
+        ratio(self.count(class, class), actual)
+    }
+
+    /// Returns the F1 score of one class, the harmonic mean of its precision and recall.
+    pub fn class_f1(&self, class: usize) -> f64 {
+        let (precision, recall) = (self.class_precision(class), self.class_recall(class));
+        if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        }
+    }
+
+    /// Returns the precision of the positive class `1` for two classes, the mean over the classes otherwise.
+    pub fn precision(&self) -> f64 {
+        self.summarize(ConfusionMatrix::class_precision)
+    }
+
+    /// Returns the recall of the positive class `1` for two classes, the mean over the classes otherwise.
+    pub fn recall(&self) -> f64 {
+        self.summarize(ConfusionMatrix::class_recall)
+    }
+
+    /// Returns the F1 score of the positive class `1` for two classes, the mean over the classes otherwise.
+    pub fn f1(&self) -> f64 {
+        self.summarize(ConfusionMatrix::class_f1)
+    }
+
+    fn summarize(&self, metric: impl Fn(&Self, usize) -> f64) -> f64 {
+        if self.classes == 2 {
+            metric(self, 1)
+        } else {
+            (0..self.classes).map(|class| metric(self, class)).sum::<f64>() / self.classes as f64
+        }
+    }
+}
+
+/// Returns `count / total`, or zero when `total` is zero.
+fn ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    /// Writes the counts as a table, with a row per actual class and a column per predicted class.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.counts.iter().map(|count| count.to_string().len()).max().unwrap_or(1).max(self.classes.to_string().len());
+        write!(f, "actual \\ predicted")?;
+        for predicted in 0..self.classes {
+            write!(f, "  {:>width$}", predicted)?;
+        }
+        writeln!(f)?;
+        for actual in 0..self.classes {
+            write!(f, "{:>18}", actual)?;
+            for predicted in 0..self.classes {
+                write!(f, "  {:>width$}", self.count(actual, predicted))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A ROC or precision-recall curve, with one point per distinct score used as threshold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve {
+    /// The horizontal coordinate of each point: the false positive rate or the recall.
+    pub x: Vec<f64>,
+    /// The vertical coordinate of each point: the true positive rate or the precision.
+    pub y: Vec<f64>,
+    /// The score from which samples are predicted positive at each point, infinity for the first point.
+    pub thresholds: Vec<f64>,
+}
+
+impl Curve {
+    /// Computes the receiver operating characteristic: the true positive rate against the false
+    /// positive rate, from `(0, 0)` to `(1, 1)`.
+    ///
+    /// # Arguments
+    /// * `scores` - The score of each sample, higher meaning more likely positive.
+    /// * `labels` - Whether each sample is positive.
+    pub fn roc(scores: &[f64], labels: &[bool]) -> Self {
+        let (true_positives, false_positives, thresholds) = cumulative_counts(scores, labels);
+        let positives = *true_positives.last().unwrap_or(&0) as f64;
+        let negatives = *false_positives.last().unwrap_or(&0) as f64;
+        Curve {
+            x: false_positives.iter().map(|&count| count as f64 / negatives).collect(),
+            y: true_positives.iter().map(|&count| count as f64 / positives).collect(),
+            thresholds,
+        }
+    }
+
+    /// Computes the precision against the recall, starting at a recall of zero and a precision of one.
+    ///
+    /// # Arguments
+    /// * `scores` - The score of each sample, higher meaning more likely positive.
+    /// * `labels` - Whether each sample is positive.
+    pub fn precision_recall(scores: &[f64], labels: &[bool]) -> Self {
+        let (true_positives, false_positives, thresholds) = cumulative_counts(scores, labels);
+        let positives = *true_positives.last().unwrap_or(&0) as f64;
+        let precision = |(&tp, &fp): (&usize, &usize)| if tp + fp == 0 { 1.0 } else { tp as f64 / (tp + fp) as f64 };
+        Curve {
+            x: true_positives.iter().map(|&count| count as f64 / positives).collect(),
+            y: true_positives.iter().zip(&false_positives).map(precision).collect(),
+            thresholds,
+        }
+    }
+
+    /// Returns the area under the curve, by the trapezoidal rule.
+    ///
+    /// The area is `NaN` if the curve is undefined, e.g. a ROC curve without positive samples.
+    pub fn auc(&self) -> f64 {
+        self.x
+            .windows(2)
+            .zip(self.y.windows(2))
+            .map(|(x, y)| (x[1] - x[0]) * (y[0] + y[1]) / 2.0)
+            .sum()
+    }
+}
+
+/// Returns the numbers of true and false positives when predicting every sample with a score
+/// of at least each distinct score positive, from the highest score down, after a first point
+/// predicting no sample positive, together with those thresholds.
+fn cumulative_counts(scores: &[f64], labels: &[bool]) -> (Vec<usize>, Vec<usize>, Vec<f64>) {
+    assert!(scores.len() == labels.len(), "Different numbers of scores and labels");
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    let (mut true_positives, mut false_positives, mut thresholds) = (vec![0], vec![0], vec![f64::INFINITY]);
+    let (mut tp, mut fp) = (0, 0);
+    for (i, &sample) in order.iter().enumerate() {
+        if labels[sample] {
+            tp += 1;
+        } else {
+            fp += 1;
+        }
+        if order.get(i + 1).map_or(true, |&next| scores[next] != scores[sample]) {
+            true_positives.push(tp);
+            false_positives.push(fp);
+            thresholds.push(scores[sample]);
+        }
+    }
+    (true_positives, false_positives, thresholds)
+}
+
+/// Returns the mean of the squared differences between every output and its target.
+pub fn mean_squared_error(outputs: &[Vec<f64>], targets: &[Vec<f64>]) -> f64 {
+    mean_difference(outputs, targets, |difference| difference * difference)
+}
+
+/// Returns the mean of the absolute differences between every output and its target.
+pub fn mean_absolute_error(outputs: &[Vec<f64>], targets: &[Vec<f64>]) -> f64 {
+    mean_difference(outputs, targets, f64::abs)
+}
+
+fn mean_difference(outputs: &[Vec<f64>], targets: &[Vec<f64>], measure: impl Fn(f64) -> f64) -> f64 {
+    assert!(outputs.len() == targets.len(), "Different numbers of outputs and targets");
+    let (mut total, mut count) = (0.0, 0);
+    for (output, target) in outputs.iter().zip(targets) {
+        for (&output, &target) in output.iter().zip(target) {
+            total += measure(output - target);
+            count += 1;
+        }
+    }
+    total / count as f64
+}
+
+/// Returns the coefficient of determination, `1 - SS_res / SS_tot`, averaged over the outputs.
+///
+/// An output whose targets are all equal scores `1` if it matches them exactly and `0` otherwise.
+pub fn r2_score(outputs: &[Vec<f64>], targets: &[Vec<f64>]) -> f64 {
+    assert!(outputs.len() == targets.len(), "Different numbers of outputs and targets");
+    let width = targets.first().map_or(0, Vec::len);
+    let score = |column: usize| {
+        let mean = targets.iter().map(|target| target[column]).sum::<f64>() / targets.len() as f64;
+        let residual: f64 = outputs.iter().zip(targets).map(|(output, target)| (target[column] - output[column]).powi(2)).sum();
+        let total: f64 = targets.iter().map(|target| (target[column] - mean).powi(2)).sum();
+        match (residual == 0.0, total == 0.0) {
+            (true, _) => 1.0,
+            (false, true) => 0.0,
+            (false, false) => 1.0 - residual / total,
+        }
+    };
+    (0..width).map(score).sum::<f64>() / width as f64
+}
+
+#[cfg(test)]
+/// Tests for the metrics.
+///
+/// The tests cover:
+/// - Turning outputs into classes with a threshold or the largest output
+/// - Binary and multi-class confusion matrices and the metrics derived from them
+/// - Printing a confusion matrix
+/// - ROC and precision-recall curves and their areas
+/// - Regression metrics
+mod tests {
+    use super::*;
+
+    fn column(values: &[f64]) -> Vec<Vec<f64>> {
+        values.iter().map(|&value| vec![value]).collect()
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(classes(&column(&[0.2, 0.5, 0.7]), 0.5), vec![0, 1, 1]);
+        assert_eq!(classes(&column(&[0.2, 0.5, 0.7]), 0.6), vec![0, 0, 1]);
+        assert_eq!(classes(&[vec![0.1, 0.7, 0.2], vec![0.5, 0.2, 0.3]], 0.5), vec![1, 0]);
+    }
+
+    #[test]
+    fn test_binary_confusion_matrix() {
+        let outputs = column(&[0.9, 0.8, 0.3, 0.6, 0.1, 0.2]);
+        let targets = column(&[1.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+        let confusion = ConfusionMatrix::from_outputs(&outputs, &targets, 0.5);
+        assert_eq!(confusion.counts, vec![2, 1, 1, 2]);
+        assert_eq!(confusion.total(), 6);
+        assert!((confusion.accuracy() - 4.0 / 6.0).abs() < 1e-12);
+        assert!((confusion.precision() - 2.0 / 3.0).abs() < 1e-12);
+        assert!((confusion.recall() - 2.0 / 3.0).abs() < 1e-12);
+        assert!((confusion.f1() - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(Metric::Accuracy.compute(&outputs, &targets, 0.5), confusion.accuracy());
+
+        // A higher threshold trades recall for precision.
+        let strict = ConfusionMatrix::from_outputs(&outputs, &targets, 0.7);
+        assert_eq!(strict.precision(), 1.0);
+        assert!((strict.recall() - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_multi_class_confusion_matrix() {
+        let confusion = ConfusionMatrix::new(&[0, 0, 1, 1, 2, 2], &[0, 1, 1, 1, 2, 0], 3);
+        assert_eq!(confusion.count(0, 1), 1);
+        assert_eq!(confusion.count(2, 0), 1);
+        assert!((confusion.accuracy() - 4.0 / 6.0).abs() < 1e-12);
+        assert_eq!(confusion.class_precision(1), 2.0 / 3.0);
+        assert_eq!(confusion.class_recall(1), 1.0);
+        assert!((confusion.precision() - (0.5 + 2.0 / 3.0 + 1.0) / 3.0).abs() < 1e-12);
+        assert!((confusion.recall() - (0.5 + 1.0 + 0.5) / 3.0).abs() < 1e-12);
+
+        let outputs = vec![vec![0.8, 0.1, 0.1], vec![0.2, 0.3, 0.5]];
+        let targets = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        assert_eq!(ConfusionMatrix::from_outputs(&outputs, &targets, 0.5).counts, vec![1, 0, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_display_confusion_matrix() {
+        let confusion = ConfusionMatrix::new(&[0, 0, 1, 1, 1], &[0, 1, 1, 1, 1], 2);
+        let expected = "actual \\ predicted  0  1\n                 0  1  1\n                 1  0  3\n";
+        assert_eq!(confusion.to_string(), expected);
+    }
+
+    #[test]
+    fn test_curves() {
+        let scores = [0.1, 0.4, 0.35, 0.8];
+        let labels = [false, false, true, true];
+        let roc = Curve::roc(&scores, &labels);
+        assert_eq!(roc.x, vec![0.0, 0.0, 0.5, 0.5, 1.0]);
+        assert_eq!(roc.y, vec![0.0, 0.5, 0.5, 1.0, 1.0]);
+        assert_eq!(roc.thresholds[1..], [0.8, 0.4, 0.35, 0.1]);
+        assert_eq!(roc.auc(), 0.75);
+
+        let pr = Curve::precision_recall(&scores, &labels);
+        assert_eq!(pr.x, vec![0.0, 0.5, 0.5, 1.0, 1.0]);
+        assert_eq!(pr.y, vec![1.0, 1.0, 0.5, 2.0 / 3.0, 0.5]);
+        assert!((pr.auc() - (0.5 + 0.5 * (0.5 + 2.0 / 3.0) / 2.0)).abs() < 1e-12);
+
+        // Tied scores form a single point, and perfect scores give an area of one.
+        let tied = Curve::roc(&[0.5, 0.5, 0.9], &[true, false, true]);
+        assert_eq!(tied.x, vec![0.0, 0.0, 1.0]);
+        assert_eq!(Curve::roc(&[0.1, 0.9], &[false, true]).auc(), 1.0);
+        assert!(Curve::roc(&[0.1, 0.9], &[true, true]).auc().is_nan());
+
+        let outputs = column(&scores);
+        let targets = column(&[0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(Metric::RocAuc.compute(&outputs, &targets, 0.5), 0.75);
+        let outputs: Vec<Vec<f64>> = scores.iter().map(|&score| vec![1.0 - score, score]).collect();
+        let targets = vec![vec![1.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0], vec![0.0, 1.0]];
+        assert_eq!(Metric::RocAuc.compute(&outputs, &targets, 0.5), 0.75);
+    }
+
+    #[test]
+    fn test_regression_metrics() {
+        let outputs = column(&[2.5, 0.0, 2.0, 8.0]);
+        let targets = column(&[3.0, -0.5, 2.0, 7.0]);
+        assert_eq!(mean_squared_error(&outputs, &targets), 0.375);
+        assert_eq!(mean_absolute_error(&outputs, &targets), 0.5);
+        assert!((r2_score(&outputs, &targets) - 0.948_608_137_044_967_9).abs() < 1e-12);
+        assert_eq!(Metric::R2.compute(&targets, &targets, 0.5), 1.0);
+        assert_eq!(r2_score(&column(&[1.0, 2.0]), &column(&[1.0, 1.0])), 0.0);
+    }
+}
@@ -1,84 +1,476 @@
 use crate::activations::Activation;
-use avance::AvanceBar;
+use crate::builder::{NetworkBuilder, NetworkConfig};
+use crate::callbacks::{BatchRecord, Callback, Control};
+use crate::checkpoint::{Checkpoint, RngState};
+use crate::dropout::{self, Mode};
+use crate::gradients::{GradientCheck, Gradients};
+use crate::history::{EpochRecord, TrainingHistory};
+use crate::losses::Loss;
+use crate::metrics::Metric;
+use crate::normalization::{Normalization, NormalizationBuffers};
+use crate::optimizers::Optimizer;
+use crate::regularizers::Regularizer;
+use crate::schedules::{LrSchedule, Progress};
+use crate::serialization::{SavedMatrix, SavedNetwork, SavedNormalization, SerializationError, FORMAT_VERSION};
 use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
 
 /// The main neural network struct, containing the configuration and state of the network.
 ///
-/// This struct represents a neural network with a configurable number of layers, activation function, and learning rate.
-/// It stores the weights and biases for each layer, as well as the input data and activation function to be used.
-pub struct Network {
+/// This struct represents a neural network with a configurable number of layers, activation functions, and learning rate.
+/// It stores the weights and biases for each layer, as well as the input data and the activation function of each layer.
+///
+/// The element type `T` defaults to `f64`; a `Network<f32>` halves the memory used by the weights.
+pub struct Network<T: Float = f64> {
     /// The number of neurons in each layer of the network.
     layers: Vec<usize>,
     /// The weights for each connection between neurons.
-    weights: Vec<Matrix>,
+    weights: Vec<Matrix<T>>,
     /// The biases for each neuron.
-    biases: Vec<Matrix>,
-    /// The input data for the network.
-    data: Vec<Matrix>,
-    /// The activation function to use for the network.
-    activation: Activation,
-    /// The learning rate to use for the network.
-    learning_rate: f64,
+    biases: Vec<Matrix<T>>,
+    /// Preallocated buffers reused by every forward pass and training step.
+    scratch: Scratch<T>,
+    /// The preallocated gradients computed by every training step.
+    gradients: Gradients<T>,
+    /// The activation function applied to the outputs of each layer after the input layer.
+    activations: Vec<Activation>,
+    /// The loss function minimized by training.
+    loss: Arc<dyn Loss<T>>,
+    /// The penalty on the parameters of each layer transition, added to the loss.
+    regularizers: Vec<Regularizer>,
+    /// Whether the penalties apply to the biases as well as the weights.
+    regularize_biases: bool,
+    /// The dropout rate of the outputs of each layer after the input layer.
+    dropout: Vec<f64>,
+    /// The normalization of the pre-activations of each layer after the input layer.
+    normalizations: Vec<Normalization>,
+    /// The learnable scale of each neuron of the normalized layers; empty for the other layers.
+    gammas: Vec<Matrix<T>>,
+    /// The learnable shift of each neuron of the normalized layers; empty for the other layers.
+    betas: Vec<Matrix<T>>,
+    /// The running mean of the pre-activations of each layer with batch normalization; empty
+    /// for the other layers.
+    running_means: Vec<Matrix<T>>,
+    /// The running variance of the pre-activations of each layer with batch normalization;
+    /// empty for the other layers.
+    running_variances: Vec<Matrix<T>>,
+    /// Whether the network is being trained, with dropout, or used for inference.
+    mode: Mode,
+    /// The random number generator drawing the dropout masks, behind a lock so that
+    /// `compute_gradients` can draw masks without mutable access.
+    mask_rng: Mutex<ChaCha8Rng>,
+    /// The update rule applied to the weights and biases after each step.
+    optimizer: Box<dyn Optimizer<T>>,
+    /// The learning rate to use for the network, before the schedule is applied.
+    learning_rate: T,
+    /// Adjusts the learning rate as training progresses.
+    schedule: Box<dyn LrSchedule>,
+    /// The learning rate of the current training step, as given by the schedule.
+    current_learning_rate: T,
+    /// The number of samples in each training step.
+    batch_size: usize,
+    /// Whether the samples are reshuffled at the start of every epoch.
+    shuffle: bool,
+    /// The fraction of the samples passed to `train` held out as a validation set.
+    validation_split: f64,
+    /// The metrics computed on the validation set at the end of every epoch.
+    metrics: Vec<Metric>,
+    /// The output from which a single-output classifier predicts the positive class.
+    threshold: f64,
+    /// The random number generator that initialized the weights, then shuffles the samples.
+    rng: ChaCha8Rng,
+    /// The hooks called by `train`, in order.
+    callbacks: Vec<Box<dyn Callback<T>>>,
+    /// The order of the samples in the last epoch, which the next shuffle starts from.
+    order: Vec<usize>,
+    /// Whether `resume_from` restored a checkpoint that the next call to `train` continues.
+    resumed: bool,
+    /// The record of every epoch of the current training run.
+    history: TrainingHistory,
+}
+
+/// Preallocated buffers used by the forward and backward passes, so that a training step does not allocate.
+///
+/// There is one matrix per layer, with a row per neuron in that layer and a column per sample
+/// in the current batch.
+struct Scratch<T: Float> {
+    /// The input data for the network, followed by the output of each layer from the last forward pass.
+    data: Vec<Matrix<T>>,
+    /// The input of the activation function of each layer from the last forward pass; the
+    /// entry for the input layer is unused.
+    pre_activations: Vec<Matrix<T>>,
+    /// The gradient of the loss with respect to the outputs of each layer, propagated backwards from the output.
+    errors: Vec<Matrix<T>>,
+    /// The gradient of the loss with respect to the inputs of the activation function of each layer.
+    gradients: Vec<Matrix<T>>,
+    /// The dropout mask of each layer from the last forward pass in training mode; empty for
+    /// the layers without dropout.
+    masks: Vec<Matrix<T>>,
+    /// The outputs of each layer with dropout before the mask was applied; empty for the layers
+    /// without dropout.
+    undropped: Vec<Matrix<T>>,
+    /// The normalized values and statistics of each layer from the last forward pass; empty for
+    /// the layers without normalization.
+    normalization: Vec<NormalizationBuffers<T>>,
+    /// The targets of the current batch.
+    targets: Matrix<T>,
+}
+
+impl<T: Float> Scratch<T> {
+    /// Creates buffers for the given layer sizes, sized for a single sample.
+    ///
+    /// # Arguments
+    /// * `layers` - The number of neurons in each layer.
+    /// * `dropout` - The dropout rate of each layer transition; the dropout buffers are only
+    ///   allocated for the layers with a positive rate.
+    /// * `normalizations` - The normalization of each layer transition.
+    fn new(layers: &[usize], dropout: &[f64], normalizations: &[Normalization]) -> Self {
+        let columns = || layers.iter().map(|&size| Matrix::zeros(size, 1)).collect();
+        let dropout_columns = || {
+            let rows = std::iter::once(0).chain(dropout.iter().zip(&layers[1..]).map(|(&rate, &size)| {
+                if rate > 0.0 {
+                    size
+                } else {
+                    0
+                }
+            }));
+            rows.map(|rows| Matrix::zeros(rows, 1)).collect()
+        };
+        Scratch {
+            data: columns(),
+            pre_activations: columns(),
+            errors: columns(),
+            gradients: columns(),
+            masks: dropout_columns(),
+            undropped: dropout_columns(),
+            normalization: std::iter::once(Normalization::None)
+                .chain(normalizations.iter().copied())
+                .zip(layers)
+                .map(|(normalization, &size)| NormalizationBuffers::new(normalization, size))
+                .collect(),
+            targets: Matrix::zeros(layers[layers.len() - 1], 1),
+        }
+    }
+
+    /// Changes the number of samples, i.e. columns, held by the buffers.
+    ///
+    /// The buffers keep their allocation, so alternating between a few batch sizes does not
+    /// allocate once each size has been seen.
+    ///
+    /// # Arguments
+    /// * `batch` - The number of samples in the next batch.
+    fn resize_batch(&mut self, batch: usize) {
+        let layers = [
+            &mut self.data,
+            &mut self.pre_activations,
+            &mut self.errors,
+            &mut self.gradients,
+            &mut self.masks,
+            &mut self.undropped,
+        ];
+        for buffer in layers.into_iter().flatten() {
+            buffer.resize_cols(batch);
+        }
+        for buffers in &mut self.normalization {
+            buffers.normalized.resize_cols(batch);
+        }
+        self.targets.resize_cols(batch);
+    }
 }
 
-impl Network {
+impl<T: Float> Default for Scratch<T> {
+    /// Creates empty buffers, used as a placeholder while the real buffers are borrowed.
+    fn default() -> Self {
+        Scratch {
+            data: vec![],
+            pre_activations: vec![],
+            errors: vec![],
+            gradients: vec![],
+            masks: vec![],
+            undropped: vec![],
+            normalization: vec![],
+            targets: Matrix { rows: 0, cols: 0, data: vec![] },
+        }
+    }
+}
+
+impl<T: Float> Network<T> {
 
     /// Creates a new neural network with the specified layer sizes, activation function, and learning rate.
     ///
+    /// The weights and biases are drawn uniformly from `[0, 1)`; use `Network::builder` to pick
+    /// another `Initializer`.
+    ///
     /// # Arguments
     /// * `layers` - A vector of usize values representing the number of neurons in each layer of the network.
-    /// * `activation` - The activation function to use for the network.
+    /// * `activation` - The activation function to use for every layer; use `Network::builder`
+    ///   to pick a different activation function per layer.
     /// * `desired_learning_rate` - The learning rate to use for the network.
+    /// * `seed` - The seed for the random number generator that initializes the weights and biases.
+    ///   Two networks created with the same seed and trained on the same data end up with
+    ///   bit-identical weights. `None` seeds the generator from the operating system.
     ///
     /// # Returns
     /// A new `Network` instance with the specified configuration.
-    pub fn new(layers: Vec<usize>, activation: Activation, desired_learning_rate: f64) -> Self {
-        let mut weights: Vec<Matrix> = vec![];
-        let mut biases: Vec<Matrix> = vec![];
-        for i in 0..layers.len() - 1 {
-            weights.push(Matrix::random(layers[i + 1], layers[i]));
-            biases.push(Matrix::random(layers[i + 1], 1));
-        }
-        let default_learning_rate: f64 = 0.5;
-        let learning_rate: f64 = if default_learning_rate != desired_learning_rate {
+    pub fn new(layers: Vec<usize>, activation: Activation, desired_learning_rate: T, seed: Option<u64>) -> Self {
+        let default_learning_rate: T = T::from_f64(0.5);
+        let learning_rate: T = if default_learning_rate != desired_learning_rate {
             desired_learning_rate
         } else {
             default_learning_rate
         };
+        let mut builder = Network::builder()
+            .layers(layers)
+            .activation(activation)
+            .learning_rate(learning_rate); /*map_with_learning_rate*/
+        if let Some(seed) = seed {
+            builder = builder.seed(seed);
+        }
+        builder.build().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Returns a builder for configuring every option of a new network.
+    ///
+    /// See the `builder` module for an example.
+    pub fn builder() -> NetworkBuilder<T> {
+        NetworkBuilder::default()
+    }
+
+    /// Creates a new neural network from a complete configuration.
+    ///
+    /// The weights and biases of each layer are drawn in order from a single generator seeded
+    /// with `config.seed`, using the initializers selected for that layer. The dropout masks are
+    /// drawn from a separate stream of a generator with the same seed.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the network.
+    ///
+    /// # Returns
+    /// A new `Network` instance with the specified configuration.
+    pub fn from_config(config: NetworkConfig<T>) -> Self {
+        let mut rng = match config.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        let mut mask_rng = match config.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        mask_rng.set_stream(1);
+        let layers = config.layers.clone();
+        let mut weights: Vec<Matrix<T>> = vec![];
+        let mut biases: Vec<Matrix<T>> = vec![];
+        for i in 0..layers.len() - 1 {
+            let (fan_in, fan_out) = (layers[i], layers[i + 1]);
+            let weight_initializer = config.weight_initializer_for(i);
+            weights.push(weight_initializer.initialize(&mut rng, fan_out, fan_in, fan_in, fan_out));
+            let bias_initializer = config.bias_initializer_for(i);
+            biases.push(bias_initializer.initialize(&mut rng, fan_out, 1, fan_in, fan_out));
+        }
+        let activations = (0..layers.len() - 1).map(|i| config.activation_for(i)).collect();
+        let regularizers = (0..layers.len() - 1).map(|i| config.regularizer_for(i)).collect();
+        let dropout: Vec<f64> = (0..layers.len() - 1).map(|i| config.dropout_for(i)).collect();
+        let normalizations: Vec<Normalization> = (0..layers.len() - 1).map(|i| config.normalization_for(i)).collect();
+        let normalization_parameters = |normalized: fn(&Normalization) -> bool, value: f64| -> Vec<Matrix<T>> {
+            normalizations
+                .iter()
+                .zip(&layers[1..])
+                .map(|(normalization, &size)| {
+                    let rows = if normalized(normalization) { size } else { 0 };
+                    Matrix { rows, cols: 1, data: vec![T::from_f64(value); rows] }
+                })
+                .collect()
+        };
+        let gammas = normalization_parameters(|normalization| !normalization.is_none(), 1.0);
+        let betas = normalization_parameters(|normalization| !normalization.is_none(), 0.0);
+        let running_means = normalization_parameters(Normalization::has_running_statistics, 0.0);
+        let running_variances = normalization_parameters(Normalization::has_running_statistics, 1.0);
+        let scratch = Scratch::new(&layers, &dropout, &normalizations);
+        let normalized: Vec<bool> = normalizations.iter().map(|normalization| !normalization.is_none()).collect();
+        let gradients = Gradients::with_normalized(&layers, &normalized);
+        let callbacks = config.all_callbacks();
         Network {
             layers,
             weights,
             biases,
-            data: vec![],
-            activation,
-            learning_rate, /*map_with_learning_rate*/
+            scratch,
+            gradients,
+            activations,
+            loss: config.loss,
+            regularizers,
+            regularize_biases: config.regularize_biases,
+            dropout,
+            normalizations,
+            gammas,
+            betas,
+            running_means,
+            running_variances,
+            mode: Mode::Eval,
+            mask_rng: Mutex::new(mask_rng),
+            optimizer: config.optimizer,
+            learning_rate: config.learning_rate,
+            schedule: config.schedule,
+            current_learning_rate: config.learning_rate,
+            batch_size: config.batch_size,
+            shuffle: config.shuffle,
+            validation_split: config.validation_split,
+            metrics: config.metrics,
+            threshold: config.threshold,
+            rng,
+            callbacks,
+            order: vec![],
+            resumed: false,
+            history: TrainingHistory::new(),
         }
     }
 
     /// Performs a forward pass through the neural network.
     ///
+    /// In `Mode::Train`, new dropout masks are drawn and kept for the next `back_propogate`.
+    ///
     /// # Arguments
     /// * `inputs` - A `Matrix` containing the input data for the network.
     ///
     /// # Returns
     /// A `Matrix` containing the output of the neural network after the forward pass.
-    pub fn feed_forward(&mut self, inputs: Matrix) -> Matrix {
+    pub fn feed_forward(&mut self, inputs: Matrix<T>) -> Matrix<T> {
         assert!(
             self.layers[0] == inputs.data.len(),
             "Invalid Number of Inputs"
         );
         //   println!("{:?} {:?}",self.weights[0],inputs);
         //   println!("{:?}",self.weights[0].dot_multiply(&inputs).add(&self.biases[0]));
-        let mut current = inputs;
-        self.data = vec![current.clone()];
+        self.scratch.resize_batch(1);
+        self.scratch.data[0].copy_from_slice(&inputs.data);
+        self.forward();
+        self.scratch.data[self.layers.len() - 1].clone()
+    }
+
+    /// Copies a batch of samples into the input buffer and the target buffer, one column per sample.
+    ///
+    /// # Arguments
+    /// * `inputs` - The input values of every sample.
+    /// * `targets` - The target output values of every sample.
+    /// * `indices` - The indices of the samples in the batch.
+    fn load_batch(&mut self, inputs: &[Vec<T>], targets: &[Vec<T>], indices: &[usize]) {
+        self.load_inputs(inputs, indices);
+        let batch = indices.len();
+        let last = self.layers.len() - 1;
+        for (col, &sample) in indices.iter().enumerate() {
+            assert!(targets[sample].len() == self.layers[last], "Invalid Number of Targets");
+            for (row, &value) in targets[sample].iter().enumerate() {
+                self.scratch.targets.data[row * batch + col] = value;
+            }
+        }
+    }
+
+    /// Copies the inputs of the given samples into the scratch buffers, one column per sample,
+    /// resizing the buffers to the number of samples.
+    fn load_inputs(&mut self, inputs: &[Vec<T>], indices: &[usize]) {
+        let batch = indices.len();
+        self.scratch.resize_batch(batch);
+        for (col, &sample) in indices.iter().enumerate() {
+            assert!(inputs[sample].len() == self.layers[0], "Invalid Number of Inputs");
+            for (row, &value) in inputs[sample].iter().enumerate() {
+                self.scratch.data[0].data[row * batch + col] = value;
+            }
+        }
+    }
+
+    /// Performs a forward pass of the batch loaded into the scratch buffers without allocating.
+    ///
+    /// In `Mode::Train`, the running statistics of batch normalization are updated with those of
+    /// the batch, unless the batch holds a single sample.
+    fn forward(&mut self) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        self.forward_with(&mut scratch, true);
+        if self.mode == Mode::Train {
+            for (i, normalization) in self.normalizations.iter().enumerate() {
+                normalization.update_running_statistics(
+                    &scratch.normalization[i + 1],
+                    &mut self.running_means[i],
+                    &mut self.running_variances[i],
+                );
+            }
+        }
+        self.scratch = scratch;
+    }
+
+    /// Performs a forward pass of the batch in `scratch.data[0]`, writing every intermediate result into `scratch`.
+    ///
+    /// The pre-activations of the normalized layers are normalized, scaled and shifted before the
+    /// activation function; the running statistics of batch normalization are left unchanged.
+    /// In `Mode::Train`, batch normalization uses the statistics of the batch, the outputs of the
+    /// layers with dropout are multiplied by their mask, and the outputs before dropout are kept
+    /// in `scratch.undropped` for the backward pass.
+    ///
+    /// # Arguments
+    /// * `scratch` - The buffers holding the inputs and receiving the outputs of each layer.
+    /// * `draw_masks` - Whether to draw new dropout masks, or to reuse the masks in `scratch`.
+    fn forward_with(&self, scratch: &mut Scratch<T>, draw_masks: bool) {
+        let Scratch { data, pre_activations, masks, undropped, normalization, .. } = scratch;
+        let training = self.mode == Mode::Train;
         for i in 0..self.layers.len() - 1 {
-            current = self.weights[i]
-                .dot_multiply(&current)
-                .add(&self.biases[i])
-                .map(self.activation.function);
-            self.data.push(current.clone());
+            let pre_activation = &mut pre_activations[i + 1];
+            Matrix::gemm_into(pre_activation, &self.weights[i], &data[i], T::one(), T::zero());
+            if self.normalizations[i].uses_bias() {
+                pre_activation.add_column_assign(&self.biases[i]);
+            }
+            if !self.normalizations[i].is_none() {
+                self.normalizations[i].forward(
+                    &self.gammas[i],
+                    &self.betas[i],
+                    &self.running_means[i],
+                    &self.running_variances[i],
+                    training,
+                    pre_activation,
+                    &mut normalization[i + 1],
+                );
+            }
+            self.activations[i].forward(pre_activation, &mut data[i + 1]);
+            if self.drops_out(i) {
+                if draw_masks {
+                    dropout::sample_mask(&mut *self.mask_rng(), self.dropout[i], &mut masks[i + 1]);
+                }
+                undropped[i + 1].copy_from_slice(&data[i + 1].data);
+                data[i + 1].elementwise_multiply_assign(&masks[i + 1]);
+            }
         }
-        current
+    }
+
+    /// Returns `true` if the outputs of layer `layer + 1` are dropped out in the current mode.
+    fn drops_out(&self, layer: usize) -> bool {
+        self.mode == Mode::Train && self.dropout[layer] > 0.0
+    }
+
+    /// Locks the generator of the dropout masks.
+    fn mask_rng(&self) -> MutexGuard<'_, ChaCha8Rng> {
+        self.mask_rng.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns whether the network is being trained or used for inference.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switches the network between training and inference.
+    ///
+    /// In `Mode::Train`, every forward pass, e.g. from `feed_forward`, `predict` or
+    /// `compute_gradients`, drops out the outputs of the hidden layers at the configured rates;
+    /// in `Mode::Eval` every neuron is used. `train` switches to `Mode::Train` itself and restores
+    /// the mode when it returns.
+    ///
+    /// # Arguments
+    /// * `mode` - The new mode.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
     }
 
     /// Performs backpropagation to update the weights and biases of the neural network.
@@ -87,26 +479,506 @@ impl Network {
     /// * `inputs` - A `Matrix` containing the input data for the network.
     /// * `targets` - A `Matrix` containing the target output data for the network.
     ///
-    /// This function calculates the errors between the network's outputs and the target outputs,
-    /// then uses those errors to update the weights and biases of the network through backpropagation.
+    /// This function calculates the gradient of the loss between the network's outputs and the target outputs,
+    /// backpropagates it to the weights and biases of every layer, then lets the optimizer update them.
     /// The learning rate is applied to the weight and bias updates.
-    pub fn back_propogate(&mut self, inputs: Matrix, targets: Matrix) {
-        let mut errors = targets.subtract(&inputs);
-        let mut gradients = inputs.clone().map(self.activation.derivative);
-        let map_with_learning_rate = |x: &f64| {
-            let _lr = self.learning_rate;
-            x * self.learning_rate
+    ///
+    /// The activations of the hidden layers, the inputs of every activation function and the
+    /// dropout masks are taken from the last call to `feed_forward`.
+    pub fn back_propogate(&mut self, inputs: Matrix<T>, targets: Matrix<T>) {
+        let last = self.layers.len() - 1;
+        self.scratch.data[last].copy_from_slice(&inputs.data);
+        self.scratch.targets.copy_from_slice(&targets.data);
+        self.backward();
+        self.step();
+    }
+
+    /// Computes the gradients of the batch loaded into the scratch buffers into the preallocated
+    /// gradients without allocating.
+    fn backward(&mut self) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let mut gradients = std::mem::take(&mut self.gradients);
+        self.backward_with(&mut scratch, &mut gradients);
+        self.scratch = scratch;
+        self.gradients = gradients;
+    }
+
+    /// Computes the gradients of the weights and biases for the batch in `scratch`.
+    ///
+    /// The outputs of the network are read from the buffers filled by the last `forward_with`,
+    /// and the targets from `scratch.targets`. The gradients are averaged over the samples of
+    /// the batch. The weights and biases are left unchanged.
+    ///
+    /// # Arguments
+    /// * `scratch` - The buffers filled by the forward pass, also used for the backward pass.
+    /// * `out` - The gradients to overwrite.
+    fn backward_with(&self, scratch: &mut Scratch<T>, out: &mut Gradients<T>) {
+        let last = self.layers.len() - 1;
+        let Scratch { data, pre_activations, errors, gradients, masks, undropped, normalization, targets } = scratch;
+        let training = self.mode == Mode::Train;
+        self.loss.output_gradient(
+            self.activations[last - 1],
+            &pre_activations[last],
+            &data[last],
+            targets,
+            &mut gradients[last],
+        );
+        for i in (0..last).rev() {
+            let (previous, next) = gradients.split_at_mut(i + 1);
+            if !self.normalizations[i].is_none() {
+                self.normalizations[i].backward(
+                    &self.gammas[i],
+                    &self.running_variances[i],
+                    training,
+                    &normalization[i + 1],
+                    &mut next[0],
+                    &mut out.gammas[i],
+                    &mut out.betas[i],
+                );
+            }
+            let gradient = &next[0];
+            Matrix::gemm_nt_into(&mut out.weights[i], gradient, &data[i], T::one(), T::zero());
+            if self.normalizations[i].uses_bias() {
+                gradient.sum_columns_into(&mut out.biases[i]);
+            } else {
+                out.biases[i].scale_inplace(T::zero());
+            }
+            self.regularizers[i].add_gradient(&self.weights[i], &mut out.weights[i]);
+            if self.regularize_biases {
+                self.regularizers[i].add_gradient(&self.biases[i], &mut out.biases[i]);
+            }
+            if i > 0 {
+                Matrix::gemm_tn_into(&mut errors[i], &self.weights[i], gradient, T::one(), T::zero());
+                previous[i].copy_from_slice(&errors[i].data);
+                let outputs = if self.drops_out(i - 1) {
+                    previous[i].elementwise_multiply_assign(&masks[i]);
+                    &undropped[i]
+                } else {
+                    &data[i]
+                };
+                self.activations[i - 1].backward(&pre_activations[i], outputs, &mut previous[i]);
+            }
+        }
+    }
+
+    /// Updates the weights and biases with the gradients computed by the last `backward`.
+    fn step(&mut self) {
+        let gradients = std::mem::take(&mut self.gradients);
+        self.apply_gradients(&gradients);
+        self.gradients = gradients;
+    }
+
+    /// Computes the gradients of the loss with respect to every weight and bias, without
+    /// modifying the network.
+    ///
+    /// In `Mode::Train`, new dropout masks are drawn for the batch.
+    ///
+    /// # Arguments
+    /// * `inputs` - The input data, one column per sample.
+    /// * `targets` - The target output data, one column per sample.
+    ///
+    /// # Returns
+    /// The gradients averaged over the samples, ready for `apply_gradients`.
+    ///
+    /// # Panics
+    /// Panics if the number of rows of `inputs` or `targets` does not match the input or
+    /// output layer, or if they do not have the same number of columns.
+    pub fn compute_gradients(&self, inputs: &Matrix<T>, targets: &Matrix<T>) -> Gradients<T> {
+        let mut scratch = self.scratch_for(inputs, targets);
+        let mut gradients = self.zero_gradients();
+        self.forward_with(&mut scratch, true);
+        self.backward_with(&mut scratch, &mut gradients);
+        gradients
+    }
+
+    /// Allocates buffers holding a batch given as matrices with one column per sample.
+    ///
+    /// # Panics
+    /// Panics if the number of rows of `inputs` or `targets` does not match the input or
+    /// output layer, or if they do not have the same number of columns.
+    fn scratch_for(&self, inputs: &Matrix<T>, targets: &Matrix<T>) -> Scratch<T> {
+        let last = self.layers.len() - 1;
+        assert!(inputs.rows == self.layers[0], "Invalid Number of Inputs");
+        assert!(
+            targets.rows == self.layers[last] && targets.cols == inputs.cols,
+            "Invalid Number of Targets"
+        );
+        let mut scratch = Scratch::new(&self.layers, &self.dropout, &self.normalizations);
+        scratch.resize_batch(inputs.cols);
+        scratch.data[0].copy_from_slice(&inputs.data);
+        scratch.targets.copy_from_slice(&targets.data);
+        scratch
+    }
+
+    /// Checks the gradients computed by backpropagation against finite differences of the loss.
+    ///
+    /// Every weight and bias is in turn moved by `+epsilon` and `-epsilon`, and the central
+    /// difference of the loss is compared with the analytic gradient from `compute_gradients`.
+    /// The relative error of a parameter is `|analytic - numeric| / max(|analytic|, |numeric|, epsilon)`,
+    /// so gradients smaller than `epsilon` are compared absolutely. This runs two forward passes
+    /// per parameter, so it is meant for small networks and batches, e.g. in tests.
+    ///
+    /// In `Mode::Train`, the dropout masks are drawn once and kept for every forward pass, so the
+    /// check applies to a single thinned network.
+    ///
+    /// # Arguments
+    /// * `inputs` - The input data, one column per sample.
+    /// * `targets` - The target output data, one column per sample.
+    /// * `epsilon` - The perturbation applied to each parameter, e.g. `1e-5` for `f64`.
+    ///
+    /// # Returns
+    /// The largest relative error among the weights and among the biases of each layer.
+    ///
+    /// # Panics
+    /// Panics if the number of rows of `inputs` or `targets` does not match the input or
+    /// output layer, or if they do not have the same number of columns.
+    pub fn check_gradients(&mut self, inputs: &Matrix<T>, targets: &Matrix<T>, epsilon: T) -> GradientCheck<T> {
+        let mut scratch = self.scratch_for(inputs, targets);
+        let mut analytic = self.zero_gradients();
+        self.forward_with(&mut scratch, true);
+        self.backward_with(&mut scratch, &mut analytic);
+        let mut errors = Vec::with_capacity(4 * self.weights.len());
+        for (index, gradient) in analytic.iter().enumerate() {
+            let mut max_error = T::zero();
+            for (k, &analytic) in gradient.data.iter().enumerate() {
+                let original = self.parameter(index).data[k];
+                self.parameter(index).data[k] = original + epsilon;
+                let plus = self.loss_with(&mut scratch);
+                self.parameter(index).data[k] = original - epsilon;
+                let minus = self.loss_with(&mut scratch);
+                self.parameter(index).data[k] = original;
+
+                let numeric = (plus - minus) / (epsilon + epsilon);
+                let scale = analytic.abs().max(numeric.abs()).max(epsilon);
+                max_error = max_error.max((analytic - numeric).abs() / scale);
+            }
+            errors.push(max_error);
+        }
+        let (parameters, normalization) = errors.split_at(2 * self.weights.len());
+        GradientCheck {
+            weights: parameters.iter().step_by(2).copied().collect(),
+            biases: parameters.iter().skip(1).step_by(2).copied().collect(),
+            gammas: normalization.iter().step_by(2).copied().collect(),
+            betas: normalization.iter().skip(1).step_by(2).copied().collect(),
+        }
+    }
+
+    /// Returns zero gradients with the shapes of the parameters of the network.
+    fn zero_gradients(&self) -> Gradients<T> {
+        let normalized: Vec<bool> = self.normalizations.iter().map(|normalization| !normalization.is_none()).collect();
+        Gradients::with_normalized(&self.layers, &normalized)
+    }
+
+    /// Returns the parameter with the given index passed to the optimizer, following the order
+    /// of `Gradients::iter`: the weights of layer `index / 2` if `index` is even and its biases
+    /// otherwise, then the `gamma` and `beta` of each layer after the weights and biases.
+    fn parameter(&mut self, index: usize) -> &mut Matrix<T> {
+        let transitions = self.weights.len();
+        match (index < 2 * transitions, index % 2 == 0) {
+            (true, true) => &mut self.weights[index / 2],
+            (true, false) => &mut self.biases[index / 2],
+            (false, true) => &mut self.gammas[index / 2 - transitions],
+            (false, false) => &mut self.betas[index / 2 - transitions],
+        }
+    }
+
+    /// Runs a forward pass of the batch in `scratch`, reusing its dropout masks, and returns its
+    /// mean loss, including the penalty.
+    fn loss_with(&self, scratch: &mut Scratch<T>) -> T {
+        self.forward_with(scratch, false);
+        self.loss.value(&scratch.data[self.layers.len() - 1], &scratch.targets) + self.penalty()
+    }
+
+    /// Lets the optimizer update the weights and biases, and the scale and shift of the
+    /// normalized layers, with the given gradients, at the learning rate of the current step.
+    ///
+    /// # Arguments
+    /// * `gradients` - The gradients, e.g. from `compute_gradients`.
+    ///
+    /// # Panics
+    /// Panics if the gradients do not have the same shapes as the weights and biases.
+    pub fn apply_gradients(&mut self, gradients: &Gradients<T>) {
+        let matches = |parameters: &[Matrix<T>], gradients: &[Matrix<T>]| {
+            parameters.len() == gradients.len()
+                && parameters.iter().zip(gradients).all(|(p, g)| p.shape() == g.shape())
         };
-        for i in (0..self.layers.len() - 1).rev() {
-            gradients = gradients
-                .elementwise_multiply(&errors)
-                .map(map_with_learning_rate);
-            self.weights[i] =
-                self.weights[i].add(&gradients.dot_multiply(&self.data[i].transpose()));
-            self.biases[i] = self.biases[i].add(&gradients);
-            errors = self.weights[i].transpose().dot_multiply(&errors);
-            gradients = self.data[i].map(self.activation.derivative);
+        assert!(
+            matches(&self.weights, &gradients.weights)
+                && matches(&self.biases, &gradients.biases)
+                && matches(&self.gammas, &gradients.gammas)
+                && matches(&self.betas, &gradients.betas),
+            "Gradients do not match the shape of the network"
+        );
+        self.optimizer.begin_step();
+        for i in 0..self.weights.len() {
+            self.optimizer.update(2 * i, &mut self.weights[i], &gradients.weights[i], self.current_learning_rate);
+            self.optimizer.update(2 * i + 1, &mut self.biases[i], &gradients.biases[i], self.current_learning_rate);
+        }
+        let offset = 2 * self.weights.len();
+        for i in (0..self.weights.len()).filter(|&i| !self.normalizations[i].is_none()) {
+            let learning_rate = self.current_learning_rate;
+            self.optimizer.update(offset + 2 * i, &mut self.gammas[i], &gradients.gammas[i], learning_rate);
+            self.optimizer.update(offset + 2 * i + 1, &mut self.betas[i], &gradients.betas[i], learning_rate);
+        }
+    }
+
+    /// Returns the penalty of the regularizers on the current parameters, part of the loss.
+    pub fn penalty(&self) -> T {
+        let mut penalty = T::zero();
+        for (i, regularizer) in self.regularizers.iter().enumerate().filter(|(_, regularizer)| !regularizer.is_none()) {
+            penalty += regularizer.penalty(&self.weights[i]);
+            if self.regularize_biases {
+                penalty += regularizer.penalty(&self.biases[i]);
+            }
+        }
+        penalty
+    }
+
+    /// Returns the mean loss of the network over a dataset.
+    ///
+    /// The samples are pushed through the network in batches of the configured batch size.
+    ///
+    /// # Arguments
+    /// * `inputs` - The input values of each sample.
+    /// * `targets` - The target output values of each sample.
+    ///
+    /// # Returns
+    /// The mean of the loss of each sample, plus the penalty of the regularizers.
+    pub fn loss(&mut self, inputs: &[Vec<T>], targets: &[Vec<T>]) -> T {
+        let last = self.layers.len() - 1;
+        let indices: Vec<usize> = (0..inputs.len()).collect();
+        let mut total = T::zero();
+        for batch in indices.chunks(self.batch_size) {
+            self.load_batch(inputs, targets, batch);
+            self.forward();
+            let batch_loss = self.loss.value(&self.scratch.data[last], &self.scratch.targets);
+            total += batch_loss * T::from_f64(batch.len() as f64);
+        }
+        total / T::from_f64(inputs.len() as f64) + self.penalty()
+    }
+
+    /// Returns the weight matrix of each layer transition.
+    pub fn weights(&self) -> &[Matrix<T>] {
+        &self.weights
+    }
+
+    /// Returns the bias vector of each layer after the input layer.
+    pub fn biases(&self) -> &[Matrix<T>] {
+        &self.biases
+    }
+
+    /// Replaces the weights and biases, e.g. with ones returned by `weights` and `biases` earlier.
+    ///
+    /// # Arguments
+    /// * `weights` - The weight matrix of each layer transition.
+    /// * `biases` - The bias vector of each layer after the input layer.
+    ///
+    /// # Panics
+    /// Panics if the matrices do not have the shapes of the parameters of the network.
+    pub fn set_parameters(&mut self, weights: Vec<Matrix<T>>, biases: Vec<Matrix<T>>) {
+        let shape = |matrix: &Matrix<T>| (matrix.rows, matrix.cols);
+        assert!(
+            weights.iter().map(shape).eq(self.weights.iter().map(shape))
+                && biases.iter().map(shape).eq(self.biases.iter().map(shape)),
+            "Parameters do not match the shape of the network"
+        );
+        self.weights = weights;
+        self.biases = biases;
+    }
+
+    /// Computes the outputs of the network for many samples, in batches of the configured batch size.
+    ///
+    /// # Arguments
+    /// * `inputs` - The input of each sample.
+    ///
+    /// # Returns
+    /// The output of each sample.
+    pub fn predict(&mut self, inputs: &[Vec<T>]) -> Vec<Vec<T>> {
+        let last = self.layers.len() - 1;
+        let indices: Vec<usize> = (0..inputs.len()).collect();
+        let mut outputs = Vec::with_capacity(inputs.len());
+        for batch in indices.chunks(self.batch_size) {
+            self.load_inputs(inputs, batch);
+            self.forward();
+            let result = &self.scratch.data[last];
+            for col in 0..batch.len() {
+                outputs.push((0..result.rows).map(|row| result.data[row * result.cols + col]).collect());
+            }
+        }
+        outputs
+    }
+
+    /// Measures how well the outputs of the network match the targets.
+    ///
+    /// Classification metrics use the configured `threshold` for single-output networks, see the
+    /// `metrics` module.
+    ///
+    /// # Arguments
+    /// * `inputs` - The input of each sample.
+    /// * `targets` - The target of each sample.
+    /// * `metrics` - The metrics to compute.
+    ///
+    /// # Returns
+    /// The value of each metric, keyed by its name.
+    pub fn evaluate(&mut self, inputs: &[Vec<T>], targets: &[Vec<T>], metrics: &[Metric]) -> BTreeMap<String, f64> {
+        assert!(inputs.len() == targets.len(), "Different numbers of inputs and targets");
+        let to_f64 = |values: Vec<Vec<T>>| -> Vec<Vec<f64>> {
+            values.into_iter().map(|value| value.into_iter().map(T::to_f64).collect()).collect()
+        };
+        let outputs = to_f64(self.predict(inputs));
+        let targets = to_f64(targets.to_vec());
+        metrics
+            .iter()
+            .map(|metric| (metric.name().to_string(), metric.compute(&outputs, &targets, self.threshold)))
+            .collect()
+    }
+
+    /// Returns the learning rate of the last training step, or the configured learning rate
+    /// before training.
+    pub fn current_learning_rate(&self) -> T {
+        self.current_learning_rate
+    }
+
+    /// Replaces the learning-rate schedule used by `train`.
+    ///
+    /// # Arguments
+    /// * `schedule` - The new schedule, starting from its own state.
+    pub fn set_schedule(&mut self, schedule: impl LrSchedule + 'static) {
+        self.schedule = Box::new(schedule);
+    }
+
+    /// Replaces the update rule applied after each training step.
+    ///
+    /// # Arguments
+    /// * `optimizer` - The new optimizer, starting from its own state.
+    pub fn set_optimizer(&mut self, optimizer: impl Optimizer<T> + 'static) {
+        self.optimizer = Box::new(optimizer);
+    }
+
+    /// Replaces the loss function minimized by training.
+    ///
+    /// # Arguments
+    /// * `loss` - The new loss function.
+    pub fn set_loss(&mut self, loss: impl Loss<T> + 'static) {
+        self.loss = Arc::new(loss);
+    }
+
+    /// Returns the layer sizes, activation functions, base learning rate, weights and biases of
+    /// the network, ready to be written to disk.
+    pub fn to_saved(&self) -> SavedNetwork {
+        SavedNetwork {
+            format_version: FORMAT_VERSION,
+            layers: self.layers.clone(),
+            activations: self.activations.clone(),
+            learning_rate: self.learning_rate.to_f64(),
+            weights: self.weights.iter().map(SavedMatrix::from).collect(),
+            biases: self.biases.iter().map(SavedMatrix::from).collect(),
+            normalizations: (0..self.weights.len())
+                .filter(|&i| !self.normalizations[i].is_none())
+                .map(|i| {
+                    let saved = SavedNormalization {
+                        normalization: self.normalizations[i],
+                        gamma: SavedMatrix::from(&self.gammas[i]),
+                        beta: SavedMatrix::from(&self.betas[i]),
+                        running_mean: SavedMatrix::from(&self.running_means[i]),
+                        running_variance: SavedMatrix::from(&self.running_variances[i]),
+                    };
+                    (i, saved)
+                })
+                .collect(),
+        }
+    }
+
+    /// Restores a network from its saved form.
+    ///
+    /// The loss, optimizer, schedule and training options are the defaults of `Network::builder`,
+    /// except for the batch size of a network with batch normalization, which is 2.
+    ///
+    /// # Arguments
+    /// * `saved` - The saved network, e.g. from `to_saved` or `SavedNetwork::load`.
+    ///
+    /// # Returns
+    /// The network, or `SerializationError::InvalidNetwork` if the layer sizes are invalid or
+    /// do not match the activations, weights, biases and normalizations.
+    pub fn from_saved(saved: &SavedNetwork) -> Result<Self, SerializationError> {
+        let invalid = |reason: String| SerializationError::InvalidNetwork(reason);
+        let transitions = saved.layers.len().saturating_sub(1);
+        if saved.activations.len() != transitions {
+            return Err(invalid(format!(
+                "{} activations for {} layers",
+                saved.activations.len(),
+                saved.layers.len()
+            )));
+        }
+        let mut builder = Network::builder()
+            .layers(saved.layers.clone())
+            .activations(saved.activations.clone())
+            .learning_rate(T::from_f64(saved.learning_rate));
+        for (&layer, normalization) in &saved.normalizations {
+            builder = builder.layer_normalization(layer, normalization.normalization);
+        }
+        // Batch normalization rejects the default batch size of 1.
+        if saved.normalizations.values().any(|saved| saved.normalization.has_running_statistics()) {
+            builder = builder.batch_size(2);
+        }
+        let mut network = builder.build().map_err(|error| invalid(error.to_string()))?;
+        if saved.weights.len() != transitions || saved.biases.len() != transitions {
+            return Err(invalid(format!(
+                "{} weight and {} bias matrices for {} layers",
+                saved.weights.len(),
+                saved.biases.len(),
+                saved.layers.len()
+            )));
+        }
+        let mut parameters: Vec<(&mut Matrix<T>, &SavedMatrix)> = network.weights.iter_mut().zip(&saved.weights).collect();
+        parameters.extend(network.biases.iter_mut().zip(&saved.biases));
+        let normalized = network
+            .gammas
+            .iter_mut()
+            .zip(&mut network.betas)
+            .zip(&mut network.running_means)
+            .zip(&mut network.running_variances);
+        for (layer, (((gamma, beta), mean), variance)) in normalized.enumerate() {
+            if let Some(saved) = saved.normalizations.get(&layer) {
+                parameters.extend([(gamma, &saved.gamma), (beta, &saved.beta)]);
+                if saved.normalization.has_running_statistics() {
+                    parameters.extend([(mean, &saved.running_mean), (variance, &saved.running_variance)]);
+                }
+            }
+        }
+        for (parameter, saved) in parameters {
+            let matrix = saved.to_matrix()?;
+            if matrix.shape() != parameter.shape() {
+                return Err(invalid(format!(
+                    "expected a {}x{} matrix, found {}x{}",
+                    parameter.rows, parameter.cols, matrix.rows, matrix.cols
+                )));
+            }
+            *parameter = matrix;
         }
+        Ok(network)
+    }
+
+    /// Saves the network to a file, as JSON if the path ends in `.json` and in the compact
+    /// binary format otherwise.
+    ///
+    /// See the `serialization` module for what is saved.
+    ///
+    /// # Arguments
+    /// * `path` - The file to create or overwrite.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SerializationError> {
+        self.to_saved().save(path)
+    }
+
+    /// Loads a network saved by `save`, in either format.
+    ///
+    /// # Arguments
+    /// * `path` - The file to read.
+    ///
+    /// # Returns
+    /// The network, or an error if the file cannot be read or does not hold a valid network.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SerializationError> {
+        Network::from_saved(&SavedNetwork::load(path)?)
     }
 
     /// Trains the neural network by iterating through the provided input and target data for the specified number of epochs.
@@ -118,21 +990,1101 @@ impl Network {
     ///
     /// This function performs the following steps:
     /// 1. Iterates through the specified number of training epochs.
-    /// 2. For each epoch, iterates through the input and target data.
-    /// 3. For each input-target pair, performs a forward pass through the network using `feed_forward()` and then updates the weights and biases using `back_propogate()`.
-    /// 4. Displays a progress bar to indicate the training progress.
-    pub fn train(&mut self, inputs: Vec<Vec<f64>>, targets: Vec<Vec<f64>>, epochs: u32) {
-        let bar = AvanceBar::new(epochs as u64);
-        bar.set_desc("Progress");
-        for i in 1..=epochs {
-            if epochs < 100 || i % (epochs / 100) == 0 {
-                // println!("Epoch {} of {}", i, epochs);
-            }
-            for j in 0..inputs.len() {
-                let outputs = self.feed_forward(Matrix::from(inputs[j].clone()));
-                self.back_propogate(outputs, Matrix::from(targets[j].clone()));
-            }
-            bar.inc();
+    /// 2. For each epoch, reshuffles the samples (unless shuffling is disabled) and splits them into batches of the configured batch size.
+    /// 3. For each batch, asks the learning-rate schedule for the learning rate of the step, performs a forward pass of the whole batch
+    ///    through the network, computes the gradients averaged over the batch by backpropagation and lets the optimizer update the
+    ///    weights and biases, reusing preallocated buffers so that no allocation happens inside the loop.
+    /// 4. Reports the mean training loss of each epoch to the learning-rate schedule, and records it with the
+    ///    learning rate and wall time of the epoch in the training history.
+    /// 5. Calls the hooks of every callback, e.g. to show a progress bar or write checkpoints, and stops
+    ///    early when one of them asks to.
+    ///
+    /// The batches are trained in `Mode::Train`, with dropout, and the validation set is evaluated in
+    /// `Mode::Eval`; the mode set with `set_mode` is restored when training ends.
+    ///
+    /// With a `validation_split`, that fraction of the samples is taken from the end of `inputs` and
+    /// `targets` and held out as a validation set, as described for `train_with_validation`.
+    ///
+    /// After `resume_from`, training continues from the epoch of the checkpoint instead of starting
+    /// over; `epochs` is then the total number of epochs of the resumed run.
+    ///
+    /// # Returns
+    /// The history of the training run, also available from `history` until the next run.
+    ///
+    /// # Panics
+    /// Panics if a checkpoint was restored with `resume_from` for a different number of samples.
+    pub fn train(&mut self, mut inputs: Vec<Vec<T>>, mut targets: Vec<Vec<T>>, epochs: u32) -> TrainingHistory {
+        let held_out = (inputs.len() as f64 * self.validation_split).round() as usize;
+        let held_out = held_out.min(inputs.len().saturating_sub(1));
+        let validation_inputs = inputs.split_off(inputs.len() - held_out);
+        let validation_targets = targets.split_off(targets.len() - held_out);
+        self.fit(&inputs, &targets, &validation_inputs, &validation_targets, epochs)
+    }
+
+    /// Trains the neural network like `train`, evaluating it on a separate validation set at the
+    /// end of every epoch.
+    ///
+    /// The validation loss is recorded in the training history, reported to the learning-rate
+    /// schedule instead of the training loss, and monitored by `EarlyStopping`.
+    ///
+    /// # Arguments
+    /// * `inputs` - A vector of input data matrices.
+    /// * `targets` - A vector of target output data matrices.
+    /// * `validation_inputs` - The inputs of the validation set, never trained on.
+    /// * `validation_targets` - The targets of the validation set.
+    /// * `epochs` - The number of training epochs to perform.
+    ///
+    /// # Returns
+    /// The history of the training run, also available from `history` until the next run.
+    ///
+    /// # Panics
+    /// Panics if a checkpoint was restored with `resume_from` for a different number of samples.
+    pub fn train_with_validation(
+        &mut self,
+        inputs: Vec<Vec<T>>,
+        targets: Vec<Vec<T>>,
+        validation_inputs: Vec<Vec<T>>,
+        validation_targets: Vec<Vec<T>>,
+        epochs: u32,
+    ) -> TrainingHistory {
+        self.fit(&inputs, &targets, &validation_inputs, &validation_targets, epochs)
+    }
+
+    /// Runs the training loop shared by `train` and `train_with_validation`; an empty validation
+    /// set is not evaluated.
+    fn fit(
+        &mut self,
+        inputs: &[Vec<T>],
+        targets: &[Vec<T>],
+        validation_inputs: &[Vec<T>],
+        validation_targets: &[Vec<T>],
+        epochs: u32,
+    ) -> TrainingHistory {
+        if std::mem::take(&mut self.resumed) {
+            assert!(self.order.len() == inputs.len(), "The checkpoint does not match the number of samples");
+        } else {
+            self.history = TrainingHistory::new();
+            self.order = (0..inputs.len()).collect();
+        }
+        let mut callbacks = std::mem::take(&mut self.callbacks);
+        let metric_kinds = std::mem::take(&mut self.metrics);
+        let mode = self.mode;
+        self.mode = Mode::Eval;
+        for callback in &mut callbacks {
+            callback.on_train_begin(self, epochs as usize);
+        }
+        let last = self.layers.len() - 1;
+        let batch_size = self.batch_size;
+        let steps_per_epoch = inputs.len().div_ceil(batch_size);
+        let mut order = self.order.clone();
+        for i in self.history.len() + 1..=epochs as usize {
+            let mut stop = false;
+            for callback in &mut callbacks {
+                stop |= callback.on_epoch_begin(self, i) == Control::Stop;
+            }
+            if stop {
+                break;
+            }
+            let started = Instant::now();
+            let epoch = i - 1;
+            let mut epoch_loss = T::zero();
+            if self.shuffle {
+                order.shuffle(&mut self.rng);
+            }
+            self.mode = Mode::Train;
+            for (j, batch) in order.chunks(batch_size).enumerate() {
+                let progress = Progress {
+                    epoch,
+                    step: epoch * steps_per_epoch + j,
+                    steps_per_epoch,
+                    epochs: epochs as usize,
+                };
+                let learning_rate = self.schedule.learning_rate(self.learning_rate.to_f64(), progress);
+                self.current_learning_rate = T::from_f64(learning_rate);
+                self.load_batch(inputs, targets, batch);
+                self.forward();
+                self.backward();
+                let batch_loss = self.loss.value(&self.scratch.data[last], &self.scratch.targets) + self.penalty();
+                epoch_loss += batch_loss * T::from_f64(batch.len() as f64);
+                self.step();
+                let record = BatchRecord {
+                    epoch: i,
+                    batch: j,
+                    samples: batch.len(),
+                    loss: batch_loss.to_f64(),
+                };
+                for callback in &mut callbacks {
+                    stop |= callback.on_batch_end(self, &record) == Control::Stop;
+                }
+            }
+            self.mode = Mode::Eval;
+            let mean_loss = epoch_loss / T::from_f64(inputs.len().max(1) as f64);
+            let validation_loss =
+                (!validation_inputs.is_empty()).then(|| self.loss(validation_inputs, validation_targets).to_f64());
+            let metrics = match validation_loss {
+                Some(_) => self.evaluate(validation_inputs, validation_targets, &metric_kinds),
+                None => BTreeMap::new(),
+            };
+            self.schedule.observe(validation_loss.unwrap_or(mean_loss.to_f64()));
+            let record = EpochRecord {
+                epoch: i,
+                loss: mean_loss.to_f64(),
+                validation_loss,
+                metrics,
+                learning_rate: self.current_learning_rate.to_f64(),
+                seconds: started.elapsed().as_secs_f64(),
+            };
+            self.history.epochs.push(record.clone());
+            // The checkpoints written by the callbacks start the next shuffle from this order.
+            self.order.clone_from(&order);
+            for callback in &mut callbacks {
+                stop |= callback.on_epoch_end(self, &record) == Control::Stop;
+            }
+            if stop {
+                break;
+            }
+        }
+        for callback in &mut callbacks {
+            callback.on_train_end(self);
+        }
+        self.callbacks = callbacks;
+        self.metrics = metric_kinds;
+        self.mode = mode;
+        self.history.clone()
+    }
+
+    /// Returns the history of the last training run, including the epochs before the
+    /// checkpoint of a resumed run.
+    pub fn history(&self) -> &TrainingHistory {
+        &self.history
+    }
+
+    /// Adds a callback, called by `train` after the callbacks added before it.
+    ///
+    /// # Arguments
+    /// * `callback` - The callback to add.
+    pub fn add_callback(&mut self, callback: impl Callback<T> + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Captures the complete state of the training run at the end of the last epoch.
+    ///
+    /// Resuming from the checkpoint with `resume_from` continues the run exactly where it stopped;
+    /// it is typically written by a `Checkpointer` callback.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            format_version: FORMAT_VERSION,
+            network: self.to_saved(),
+            optimizer: self.optimizer.save_state(),
+            schedule: self.schedule.save_state(),
+            epoch: self.history.len(),
+            order: self.order.clone(),
+            rng: RngState::of(&self.rng),
+            mask_rng: RngState::of(&self.mask_rng()),
+            history: self.history.clone(),
+        }
+    }
+
+    /// Restores the state of an interrupted training run, so that the next call to `train`
+    /// continues it.
+    ///
+    /// The network must have been configured like the one that wrote the checkpoint, with the
+    /// same layers, activations, normalizations, loss, kind of optimizer, schedule, batch size,
+    /// shuffling and dropout; calling `train` with the same data and number of epochs as the
+    /// interrupted run then gives exactly the weights an uninterrupted run would have.
+    ///
+    /// # Arguments
+    /// * `checkpoint` - The checkpoint, e.g. from `Checkpoint::load`.
+    ///
+    /// # Returns
+    /// An error if the checkpoint does not match the layers and activations of the network, the
+    /// kind of its optimizer or schedule, or is malformed. The network is left unchanged in that
+    /// case.
+    pub fn resume_from(&mut self, checkpoint: &Checkpoint) -> Result<(), SerializationError> {
+        let saved = &checkpoint.network;
+        if let Some(mismatch) = self.architecture_mismatch(saved) {
+            return Err(SerializationError::InvalidCheckpoint(format!("the checkpoint {}", mismatch)));
+        }
+        let mut sorted = checkpoint.order.clone();
+        sorted.sort_unstable();
+        if sorted.iter().enumerate().any(|(i, &index)| i != index) {
+            return Err(SerializationError::InvalidCheckpoint("the sample order is not a permutation".to_string()));
+        }
+        if checkpoint.history.len() != checkpoint.epoch {
+            return Err(SerializationError::InvalidCheckpoint(format!(
+                "the checkpoint is at epoch {} with a history of {} epochs",
+                checkpoint.epoch,
+                checkpoint.history.len()
+            )));
+        }
+        let restored = Network::<T>::from_saved(saved)?;
+        let mut optimizer = self.optimizer.clone();
+        optimizer.load_state(&checkpoint.optimizer)?;
+        let mut schedule = self.schedule.clone();
+        schedule.load_state(&checkpoint.schedule)?;
+
+        self.learning_rate = restored.learning_rate;
+        self.take_parameters(restored);
+        self.optimizer = optimizer;
+        self.schedule = schedule;
+        self.rng = checkpoint.rng.restore();
+        self.mask_rng = Mutex::new(checkpoint.mask_rng.restore());
+        self.history = checkpoint.history.clone();
+        self.order = checkpoint.order.clone();
+        self.resumed = true;
+        Ok(())
+    }
+
+    /// Replaces the weights, biases and normalization state with those of a saved network, e.g.
+    /// one returned by `to_saved` earlier.
+    ///
+    /// Unlike `from_saved`, the configuration of the network is kept.
+    ///
+    /// # Arguments
+    /// * `saved` - A saved network with the same layers, activations and normalizations.
+    ///
+    /// # Returns
+    /// `SerializationError::InvalidNetwork` if the saved network has a different architecture or
+    /// is malformed. The network is left unchanged in that case.
+    pub fn load_parameters(&mut self, saved: &SavedNetwork) -> Result<(), SerializationError> {
+        if let Some(mismatch) = self.architecture_mismatch(saved) {
+            return Err(SerializationError::InvalidNetwork(format!("the saved network {}", mismatch)));
+        }
+        let restored = Network::<T>::from_saved(saved)?;
+        self.take_parameters(restored);
+        Ok(())
+    }
+
+    /// Describes how the layers, activations or normalizations of a saved network differ from
+    /// those of the network, or returns `None` if they match.
+    fn architecture_mismatch(&self, saved: &SavedNetwork) -> Option<String> {
+        if saved.layers != self.layers || saved.activations != self.activations {
+            return Some(format!(
+                "has layers {:?} with activations {:?}, the network {:?} with {:?}",
+                saved.layers, saved.activations, self.layers, self.activations
+            ));
+        }
+        let normalizations: Vec<Normalization> = (0..self.normalizations.len())
+            .map(|i| saved.normalizations.get(&i).map_or(Normalization::None, |saved| saved.normalization))
+            .collect();
+        if normalizations != self.normalizations {
+            return Some(format!(
+                "has normalizations {:?}, the network {:?}",
+                normalizations, self.normalizations
+            ));
+        }
+        None
+    }
+
+    /// Moves the weights, biases and normalization state of `restored` into the network.
+    fn take_parameters(&mut self, restored: Network<T>) {
+        self.weights = restored.weights;
+        self.biases = restored.biases;
+        self.gammas = restored.gammas;
+        self.betas = restored.betas;
+        self.running_means = restored.running_means;
+        self.running_variances = restored.running_variances;
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `Network` struct.
+///
+/// The tests cover:
+/// - Training a network with `f32` elements
+/// - Reproducing the same trained weights from the same seed
+/// - Building a network with per-layer initializers
+/// - Using a different activation function per layer
+/// - Training with cross-entropy losses
+/// - Training with the optimizers
+/// - Following a learning-rate schedule
+/// - Recording the training history
+/// - Holding out a validation set
+/// - Predicting and evaluating metrics
+/// - Averaging the gradients of a mini-batch
+/// - Computing gradients separately from applying them
+/// - Checking the gradients against finite differences
+/// - Shrinking the weights with L1 and L2 penalties
+/// - Dropping out hidden outputs in training mode only, reproducibly, with correct gradients
+/// - Checking the gradients of batch and layer normalization
+/// - Normalizing with running statistics for inference, and saving the normalization state
+/// - Leaving the running statistics unchanged on single-sample batches
+/// - Saving and loading networks in both formats
+/// - Resuming training from a checkpoint
+/// - Shuffling the samples reproducibly
+mod tests {
+    use super::*;
+    use crate::initializers::Initializer;
+    use crate::losses::{BinaryCrossEntropy, CategoricalCrossEntropy, Huber, MeanSquaredError};
+    use crate::optimizers::{AdaGrad, Adam, RmsProp, Sgd};
+    use crate::schedules::{ReduceOnPlateau, StepDecay};
+    use rand::Rng;
+
+    fn xor() -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+        let inputs = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0]];
+        let targets = vec![vec![0.0], vec![1.0], vec![1.0], vec![0.0]];
+        (inputs, targets)
+    }
+
+    #[test]
+    fn test_same_seed_gives_identical_weights() {
+        let (inputs, targets) = xor();
+        let mut first = Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, Some(7));
+        let mut second = Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, Some(7));
+        assert_eq!(first.weights, second.weights);
+        assert_eq!(first.biases, second.biases);
+        first.train(inputs.clone(), targets.clone(), 50);
+        second.train(inputs, targets, 50);
+        assert_eq!(first.weights, second.weights);
+        assert_eq!(first.biases, second.biases);
+
+        let other = Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, Some(8));
+        assert_ne!(other.weights, Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, Some(7)).weights);
+    }
+
+    #[test]
+    fn test_train_f32_network() {
+        let mut network: Network<f32> = Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, None);
+        let inputs = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0]];
+        let targets = vec![vec![0.0], vec![1.0], vec![1.0], vec![0.0]];
+        network.train(inputs.clone(), targets.clone(), 10);
+        for input in inputs {
+            let output = network.feed_forward(Matrix::from(input));
+            assert_eq!(output.shape(), (1, 1));
+            assert!(output.data[0].is_finite());
+        }
+    }
+
+    #[test]
+    fn test_builder_initializers() {
+        let network: Network = Network::builder()
+            .layers(vec![2, 4, 1])
+            .seed(1)
+            .weight_initializer(Initializer::HeNormal)
+            .bias_initializer(Initializer::Zeros)
+            .layer_weight_initializer(1, Initializer::Constant(0.5))
+            .build()
+            .unwrap();
+        assert_eq!(network.weights[0].shape(), (4, 2));
+        assert!(network.weights[0].data.iter().any(|&x| x < 0.0));
+        assert_eq!(network.weights[1], Matrix::new(1, 4, vec![0.5; 4]));
+        assert!(network.biases.iter().all(|bias| bias.data.iter().all(|&x| x == 0.0)));
+
+        let default: Network = Network::builder().layers(vec![2, 3, 1]).seed(7).build().unwrap();
+        let new = Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, Some(7));
+        assert_eq!(default.weights, new.weights);
+        assert_eq!(default.biases, new.biases);
+    }
+
+    #[test]
+    fn test_per_layer_activations() {
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 2, 1])
+            .weight_initializer(Initializer::Constant(1.0))
+            .bias_initializer(Initializer::Zeros)
+            .activations(vec![Activation::Relu, Activation::Linear])
+            .build()
+            .unwrap();
+        assert_eq!(network.feed_forward(Matrix::from(vec![1.0, -3.0])).data, vec![0.0]);
+        assert_eq!(network.feed_forward(Matrix::from(vec![3.0, 1.0])).data, vec![8.0]);
+    }
+
+    #[test]
+    fn test_train_relu_hidden_linear_output() {
+        let inputs: Vec<Vec<f64>> = (0..16).map(|i| vec![(i % 4) as f64 / 3.0, (i / 4) as f64 / 3.0]).collect();
+        let targets: Vec<Vec<f64>> = inputs.iter().map(|x| vec![2.0 * x[0] - x[1] + 0.5]).collect();
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 8, 1])
+            .seed(5)
+            .learning_rate(0.01)
+            .weight_initializer(Initializer::HeNormal)
+            .bias_initializer(Initializer::Zeros)
+            .activation(Activation::Relu)
+            .layer_activation(1, Activation::Linear)
+            .build()
+            .unwrap();
+        let squared_error = |network: &mut Network| {
+            let mut total = 0.0;
+            for (input, target) in inputs.iter().zip(&targets) {
+                let output = network.feed_forward(Matrix::from(input.clone()));
+                total += (output.data[0] - target[0]).powi(2);
+            }
+            total
+        };
+        let before = squared_error(&mut network);
+        network.train(inputs.clone(), targets.clone(), 300);
+        let after = squared_error(&mut network);
+        assert!(after < 0.01 * before, "{} -> {}", before, after);
+    }
+
+    #[test]
+    fn test_train_softmax_categorical_cross_entropy() {
+        let inputs = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0], vec![0.0, 0.0]];
+        let targets = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0], vec![0.0, 0.0, 1.0], vec![0.0, 0.0, 1.0]];
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 6, 3])
+            .seed(2)
+            .learning_rate(0.1)
+            .weight_initializer(Initializer::XavierUniform)
+            .bias_initializer(Initializer::Zeros)
+            .activation(Activation::Tanh)
+            .layer_activation(1, Activation::Softmax)
+            .loss(CategoricalCrossEntropy)
+            .build()
+            .unwrap();
+        let before = network.loss(&inputs, &targets);
+        network.train(inputs.clone(), targets.clone(), 500);
+        let after = network.loss(&inputs, &targets);
+        assert!(after < 0.1 * before, "{} -> {}", before, after);
+        for (input, target) in inputs.iter().zip(&targets) {
+            let output = network.feed_forward(Matrix::from(input.clone()));
+            assert!((output.data.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+            let predicted = (0..3).max_by(|&a, &b| output.data[a].total_cmp(&output.data[b])).unwrap();
+            assert_eq!(target[predicted], 1.0);
+        }
+    }
+
+    #[test]
+    fn test_set_loss() {
+        let (inputs, targets) = xor();
+        let mut network = Network::new(vec![2, 3, 1], Activation::Sigmoid, 0.5, Some(4));
+        network.set_loss(BinaryCrossEntropy);
+        let before = network.loss(&inputs, &targets);
+        network.train(inputs.clone(), targets.clone(), 2000);
+        assert!(network.loss(&inputs, &targets) < before);
+    }
+
+    #[test]
+    fn test_train_with_optimizers() {
+        let (inputs, targets) = xor();
+        let build = |learning_rate: f64| {
+            Network::builder()
+                .layers(vec![2, 4, 1])
+                .seed(9)
+                .learning_rate(learning_rate)
+                .weight_initializer(Initializer::XavierUniform)
+                .bias_initializer(Initializer::Zeros)
+                .activation(Activation::Tanh)
+                .layer_activation(1, Activation::Sigmoid)
+        };
+        let mut adam: Network = build(0.05).optimizer(Adam::new()).build().unwrap();
+        adam.train(inputs.clone(), targets.clone(), 500);
+        assert!(adam.loss(&inputs, &targets) < 0.01);
+
+        let mut momentum: Network = build(0.1).build().unwrap();
+        momentum.set_optimizer(Sgd::nesterov(0.9));
+        momentum.train(inputs.clone(), targets.clone(), 500);
+        assert!(momentum.loss(&inputs, &targets) < 0.01);
+    }
+
+    #[test]
+    fn test_learning_rate_schedules() {
+        let (inputs, targets) = xor();
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(1)
+            .learning_rate(0.8)
+            .schedule(StepDecay { step_size: 2, gamma: 0.5 })
+            .build()
+            .unwrap();
+        assert_eq!(network.current_learning_rate(), 0.8);
+        let history = network.train(inputs.clone(), targets.clone(), 5);
+        assert_eq!(network.current_learning_rate(), 0.2);
+        let rates: Vec<f64> = history.epochs.iter().map(|record| record.learning_rate).collect();
+        assert_eq!(rates, vec![0.8, 0.8, 0.4, 0.4, 0.2]);
+
+        // With an unreachable `min_delta` every epoch after the first is a plateau, so the
+        // learning rate is halved after the second epoch and used for the third.
+        let mut plateau = ReduceOnPlateau::new(0.5, 0);
+        plateau.min_delta = 1e9;
+        network.set_schedule(plateau);
+        network.train(inputs, targets, 3);
+        assert_eq!(network.current_learning_rate(), 0.4);
+    }
+
+    #[test]
+    fn test_train_returns_history() {
+        let (inputs, targets) = xor();
+        let mut network: Network = Network::builder().layers(vec![2, 3, 1]).seed(2).build().unwrap();
+        let history = network.train(inputs.clone(), targets.clone(), 30);
+        assert_eq!(history.len(), 30);
+        assert_eq!(&history, network.history());
+        assert!(history.epochs.iter().enumerate().all(|(i, record)| record.epoch == i + 1));
+        assert!(history.epochs.iter().all(|record| record.seconds >= 0.0 && record.validation_loss.is_none()));
+        let losses = history.losses();
+        assert!(losses[29] < losses[0]);
+
+        // The loss of an epoch is the mean over the samples, measured while the weights change.
+        let mut single: Network = Network::builder().layers(vec![2, 3, 1]).seed(2).batch_size(4).build().unwrap();
+        let before = single.loss(&inputs, &targets);
+        assert_eq!(single.train(inputs, targets, 1).losses(), vec![before]);
+    }
+
+    #[test]
+    fn test_validation_split() {
+        let (inputs, targets) = xor();
+        let build = |split: f64| -> Network {
+            Network::builder().layers(vec![2, 3, 1]).seed(9).validation_split(split).build().unwrap()
+        };
+        // A split of a quarter holds out the last sample.
+        let mut split = build(0.25);
+        let history = split.train(inputs.clone(), targets.clone(), 10);
+        let mut explicit = build(0.0);
+        let expected =
+            explicit.train_with_validation(inputs[..3].to_vec(), targets[..3].to_vec(), inputs[3..].to_vec(), targets[3..].to_vec(), 10);
+        assert_eq!(split.weights, explicit.weights);
+        assert_eq!(history.validation_losses(), expected.validation_losses());
+        assert_eq!(history.validation_losses().len(), 10);
+        assert_eq!(history.last().unwrap().validation_loss, Some(split.loss(&inputs[3..], &targets[3..])));
+
+        let history = build(0.0).train(inputs, targets, 10);
+        assert!(history.validation_losses().is_empty());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let (inputs, targets) = xor();
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(42)
+            .batch_size(3)
+            .metric(Metric::Accuracy)
+            .metric(Metric::MeanSquaredError)
+            .build()
+            .unwrap();
+        let history = network.train_with_validation(inputs.clone(), targets.clone(), inputs.clone(), targets.clone(), 3000);
+        let last = history.last().unwrap();
+        assert_eq!(last.metrics.keys().collect::<Vec<_>>(), ["accuracy", "mse"]);
+
+        let outputs = network.predict(&inputs);
+        for (input, output) in inputs.iter().zip(&outputs) {
+            assert_eq!(network.feed_forward(Matrix::from(input.clone())).data, *output);
+        }
+        let metrics = network.evaluate(&inputs, &targets, &[Metric::Accuracy, Metric::MeanSquaredError, Metric::RocAuc]);
+        assert_eq!(metrics["accuracy"], 1.0);
+        assert_eq!(metrics["roc_auc"], 1.0);
+        assert_eq!(metrics["mse"], last.metrics["mse"]);
+        assert!((metrics["mse"] - network.loss(&inputs, &targets)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_batch_gradients_are_averaged() {
+        let (inputs, targets) = xor();
+        let build = |batch_size: usize| -> Network {
+            Network::builder()
+                .layers(vec![2, 3, 1])
+                .seed(6)
+                .batch_size(batch_size)
+                .shuffle(false)
+                .build()
+                .unwrap()
+        };
+        let mut single = build(1);
+        let mut expected: Vec<Matrix<f64>> = Gradients::zeros(&[2, 3, 1]).weights;
+        for sample in 0..4 {
+            single.load_batch(&inputs, &targets, &[sample]);
+            single.forward();
+            single.backward();
+            for (total, gradient) in expected.iter_mut().zip(&single.gradients.weights) {
+                total.add_assign(gradient);
+            }
+        }
+        expected.iter_mut().for_each(|gradient| gradient.scale_inplace(0.25));
+
+        let mut full = build(4);
+        full.load_batch(&inputs, &targets, &[0, 1, 2, 3]);
+        full.forward();
+        full.backward();
+        for (batch, expected) in full.gradients.weights.iter().zip(&expected) {
+            for (x, y) in batch.data.iter().zip(&expected.data) {
+                assert!((x - y).abs() < 1e-12);
+            }
+        }
+
+        // A partial last batch is handled, and the outputs match one-sample forward passes.
+        let mut partial = build(3);
+        partial.train(inputs.clone(), targets.clone(), 2);
+        let batched = partial.loss(&inputs, &targets);
+        let mut total = 0.0;
+        for (input, target) in inputs.iter().zip(&targets) {
+            let output = partial.feed_forward(Matrix::from(input.clone()));
+            total += (output.data[0] - target[0]).powi(2);
+        }
+        assert!((batched - total / 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_compute_and_apply_gradients() {
+        let (inputs, targets) = xor();
+        let build = || -> Network { Network::builder().layers(vec![2, 3, 1]).seed(8).build().unwrap() };
+        let network = build();
+        let batch_inputs = Matrix::new(2, 4, vec![0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+        let batch_targets = Matrix::new(1, 4, vec![0.0, 1.0, 1.0, 0.0]);
+        let batch = network.compute_gradients(&batch_inputs, &batch_targets);
+        assert_eq!(network.weights, build().weights);
+
+        // The gradients of a batch are the average of the gradients of its samples.
+        let mut total = Gradients::zeros(&[2, 3, 1]);
+        for (input, target) in inputs.iter().zip(&targets) {
+            total.add_assign(&network.compute_gradients(&Matrix::from(input.clone()), &Matrix::from(target.clone())));
+        }
+        total.scale_inplace(0.25);
+        for (x, y) in batch.iter().zip(total.iter()) {
+            for (x, y) in x.data.iter().zip(&y.data) {
+                assert!((x - y).abs() < 1e-12);
+            }
+        }
+
+        // Applying the gradients of a sample takes the same step as `back_propogate`.
+        let (mut separate, mut fused) = (build(), build());
+        let input = Matrix::from(inputs[1].clone());
+        let target = Matrix::from(targets[1].clone());
+        let gradients = separate.compute_gradients(&input, &target);
+        separate.apply_gradients(&gradients);
+        let output = fused.feed_forward(input);
+        fused.back_propogate(output, target);
+        assert_eq!(separate.weights, fused.weights);
+        assert_eq!(separate.biases, fused.biases);
+    }
+
+    #[test]
+    #[should_panic(expected = "Gradients do not match the shape of the network")]
+    fn test_apply_gradients_of_other_network() {
+        let mut network: Network = Network::builder().layers(vec![2, 3, 1]).build().unwrap();
+        network.apply_gradients(&Gradients::zeros(&[2, 4, 1]));
+    }
+
+    /// Returns `samples` random inputs and one-hot targets for a network with the given layers.
+    fn random_batch(layers: &[usize], samples: usize, seed: u64) -> (Matrix<f64>, Matrix<f64>) {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let inputs = Matrix::random_with(&mut rng, layers[0], samples);
+        let classes = layers[layers.len() - 1];
+        let mut targets = Matrix::zeros(classes, samples);
+        for sample in 0..samples {
+            targets.data[(sample % classes) * samples + sample] = 1.0;
+        }
+        (inputs, targets)
+    }
+
+    #[test]
+    fn test_check_gradients() {
+        let case = |layers: Vec<usize>, activations: Vec<Activation>| -> NetworkBuilder<f64> {
+            Network::builder()
+                .layers(layers)
+                .activations(activations)
+                .weight_initializer(Initializer::XavierNormal)
+                .bias_initializer(Initializer::Normal { mean: 0.0, std_dev: 0.1 })
+        };
+        let cases = vec![
+            case(vec![3, 5, 4, 2], vec![Activation::Tanh, Activation::Sigmoid, Activation::Sigmoid]),
+            case(vec![3, 4, 4, 3], vec![Activation::Elu { alpha: 1.0 }, Activation::Gelu, Activation::Softmax])
+                .loss(CategoricalCrossEntropy),
+            case(vec![2, 4, 1], vec![Activation::Softplus, Activation::Sigmoid]).loss(BinaryCrossEntropy),
+            case(vec![3, 4, 3, 2], vec![Activation::Selu, Activation::Swish, Activation::Linear]).loss(Huber::default()),
+            case(vec![2, 3, 2], vec![Activation::LeakyRelu { alpha: 0.1 }, Activation::Tanh]),
+        ];
+        for (seed, builder) in cases.into_iter().enumerate() {
+            let mut network: Network = builder.seed(seed as u64).build().unwrap();
+            let layers = network.layers.clone();
+            let (inputs, targets) = random_batch(&layers, 3, seed as u64);
+            let weights = network.weights.clone();
+            let check = network.check_gradients(&inputs, &targets, 1e-5);
+            assert_eq!(check.weights.len(), layers.len() - 1);
+            assert!(check.max_error() < 1e-6, "{:?}: {:?}", layers, check);
+            assert_eq!(network.weights, weights);
         }
     }
+
+    /// The mean squared error with a gradient twice too large.
+    #[derive(Debug)]
+    struct DoubledGradient;
+
+    impl Loss<f64> for DoubledGradient {
+        fn value(&self, outputs: &Matrix<f64>, targets: &Matrix<f64>) -> f64 {
+            MeanSquaredError.value(outputs, targets)
+        }
+
+        fn gradient(&self, outputs: &Matrix<f64>, targets: &Matrix<f64>, gradient: &mut Matrix<f64>) {
+            MeanSquaredError.gradient(outputs, targets, gradient);
+            gradient.scale_inplace(2.0);
+        }
+    }
+
+    #[test]
+    fn test_check_gradients_detects_wrong_derivative() {
+        let mut network: Network = Network::builder().layers(vec![2, 3, 1]).seed(4).build().unwrap();
+        network.set_loss(DoubledGradient);
+        let (inputs, targets) = random_batch(&[2, 3, 1], 2, 4);
+        let check = network.check_gradients(&inputs, &targets, 1e-5);
+        assert!(check.max_error() > 0.1, "{:?}", check);
+    }
+
+    #[test]
+    fn test_regularization_shrinks_weights() {
+        let (inputs, targets) = xor();
+        let train = |regularizer: Regularizer, biases: bool| -> Network {
+            let mut network: Network = Network::builder()
+                .layers(vec![2, 4, 1])
+                .seed(21)
+                .regularizer(regularizer)
+                .regularize_biases(biases)
+                .build()
+                .unwrap();
+            network.train(inputs.clone(), targets.clone(), 500);
+            network
+        };
+        let norm = |matrices: &[Matrix<f64>], power: i32| -> f64 {
+            matrices.iter().flat_map(|matrix| &matrix.data).map(|value| value.abs().powi(power)).sum()
+        };
+        let plain = train(Regularizer::None, false);
+        let l2 = train(Regularizer::L2(0.01), false);
+        let l1 = train(Regularizer::L1(0.01), false);
+        assert!(norm(&l2.weights, 2) < norm(&plain.weights, 2));
+        assert!(norm(&l1.weights, 1) < norm(&plain.weights, 1));
+        let mut stronger = train(Regularizer::L2(0.05), false);
+        assert!(norm(&stronger.weights, 2) < norm(&l2.weights, 2));
+        let with_biases = train(Regularizer::L2(0.05), true);
+        assert!(norm(&with_biases.biases, 2) < norm(&stronger.biases, 2));
+
+        // The penalty is part of the loss.
+        let mut unregularized: Network = Network::builder().layers(vec![2, 4, 1]).seed(21).build().unwrap();
+        unregularized.set_parameters(stronger.weights.clone(), stronger.biases.clone());
+        let expected = 0.025 * norm(&stronger.weights, 2);
+        assert!((stronger.penalty() - expected).abs() < 1e-12);
+        let difference = stronger.loss(&inputs, &targets) - unregularized.loss(&inputs, &targets);
+        assert!((difference - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_check_gradients_with_regularization() {
+        let (inputs, targets) = random_batch(&[3, 4, 2], 5, 17);
+        let mut network: Network = Network::builder()
+            .layers(vec![3, 4, 2])
+            .seed(17)
+            .activation(Activation::Tanh)
+            .weight_initializer(Initializer::XavierNormal)
+            .bias_initializer(Initializer::Normal { mean: 0.0, std_dev: 0.5 })
+            .regularizer(Regularizer::ElasticNet { l1: 0.01, l2: 0.1 })
+            .layer_regularizer(1, Regularizer::L2(0.3))
+            .regularize_biases(true)
+            .build()
+            .unwrap();
+        let check = network.check_gradients(&inputs, &targets, 1e-6);
+        assert!(check.max_error() < 1e-6, "{:?}", check);
+    }
+
+    fn dropout_network(dropout: f64) -> Network {
+        Network::builder()
+            .layers(vec![4, 64, 32, 2])
+            .seed(19)
+            .weight_initializer(Initializer::XavierNormal)
+            .dropout(dropout)
+            .batch_size(8)
+            .progress_bar(false)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dropout_modes() {
+        let inputs: Vec<Vec<f64>> = (0..8).map(|i| vec![i as f64 / 8.0, 0.5, -0.25, 1.0]).collect();
+        let mut network = dropout_network(0.5);
+        assert_eq!(network.mode(), Mode::Eval);
+        let outputs = network.predict(&inputs);
+        assert_eq!(network.predict(&inputs), outputs);
+        assert_eq!(dropout_network(0.0).predict(&inputs), outputs);
+
+        network.set_mode(Mode::Train);
+        let thinned = network.predict(&inputs);
+        assert_ne!(thinned, outputs);
+        assert_ne!(network.predict(&inputs), thinned);
+        let Scratch { data, masks, undropped, .. } = &network.scratch;
+        let dropped = data[1].data.iter().filter(|&&value| value == 0.0).count();
+        assert!((200..312).contains(&dropped), "{} of 512 outputs dropped", dropped);
+        let mut expected = undropped[1].clone();
+        expected.elementwise_multiply_assign(&masks[1]);
+        assert_eq!(data[1], expected);
+        assert!(masks[1].data.iter().all(|&value| value == 0.0 || value == 2.0));
+
+        // The masks come from their own seeded generator.
+        let mut other = dropout_network(0.5);
+        other.set_mode(Mode::Train);
+        assert_eq!(other.predict(&inputs), thinned);
+
+        // Training leaves the mode as it was.
+        let targets = vec![vec![1.0, 0.0]; inputs.len()];
+        network.train(inputs.clone(), targets.clone(), 2);
+        assert_eq!(network.mode(), Mode::Train);
+        network.set_mode(Mode::Eval);
+        network.train(inputs, targets, 2);
+        assert_eq!(network.mode(), Mode::Eval);
+    }
+
+    #[test]
+    fn test_check_gradients_with_dropout() {
+        let (inputs, targets) = random_batch(&[3, 6, 5, 2], 4, 23);
+        let mut network: Network = Network::builder()
+            .layers(vec![3, 6, 5, 2])
+            .seed(23)
+            .activations(vec![Activation::Tanh, Activation::Sigmoid, Activation::Sigmoid])
+            .weight_initializer(Initializer::XavierNormal)
+            .dropout(0.3)
+            .layer_dropout(0, 0.5)
+            .build()
+            .unwrap();
+        let evaluated = network.compute_gradients(&inputs, &targets);
+        network.set_mode(Mode::Train);
+        assert_ne!(network.compute_gradients(&inputs, &targets).weights, evaluated.weights);
+        let check = network.check_gradients(&inputs, &targets, 1e-6);
+        assert!(check.max_error() < 1e-6, "{:?}", check);
+    }
+
+    #[test]
+    fn test_check_gradients_with_normalization() {
+        let (inputs, targets) = random_batch(&[3, 5, 4, 2], 6, 29);
+        let case = |normalization: Normalization, mode: Mode, dropout: f64| {
+            let mut network: Network = Network::builder()
+                .layers(vec![3, 5, 4, 2])
+                .seed(29)
+                .activations(vec![Activation::Tanh, Activation::Elu { alpha: 1.0 }, Activation::Sigmoid])
+                .weight_initializer(Initializer::XavierNormal)
+                .bias_initializer(Initializer::Normal { mean: 0.0, std_dev: 0.5 })
+                .normalization(normalization)
+                .layer_normalization(1, Normalization::layer_norm())
+                .dropout(dropout)
+                .batch_size(6)
+                .build()
+                .unwrap();
+            // Move the scale, shift and running statistics away from their initial values.
+            let mut rng = ChaCha8Rng::seed_from_u64(31);
+            let state = [&mut network.gammas, &mut network.betas, &mut network.running_means];
+            for parameter in state.into_iter().flatten() {
+                parameter.data.iter_mut().for_each(|value| *value += rng.gen_range(-0.5..0.5));
+            }
+            network.running_variances.iter_mut().for_each(|variance| variance.scale_inplace(1.5));
+            network.set_mode(mode);
+            let check = network.check_gradients(&inputs, &targets, 1e-6);
+            assert_eq!(check.gammas.len(), 3);
+            assert!(check.max_error() < 1e-6, "{:?} in {:?}: {:?}", normalization, mode, check);
+        };
+        case(Normalization::batch_norm(), Mode::Train, 0.0);
+        case(Normalization::batch_norm(), Mode::Eval, 0.0);
+        case(Normalization::layer_norm(), Mode::Train, 0.0);
+        case(Normalization::batch_norm(), Mode::Train, 0.2);
+    }
+
+    #[test]
+    fn test_normalization_inference_and_saving() {
+        let (inputs, targets) = xor();
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 8, 8, 1])
+            .seed(37)
+            .activation(Activation::Tanh)
+            .layer_activation(2, Activation::Sigmoid)
+            .normalization(Normalization::batch_norm())
+            .layer_normalization(1, Normalization::layer_norm())
+            .optimizer(Adam::new())
+            .learning_rate(0.05)
+            .batch_size(4)
+            .progress_bar(false)
+            .build()
+            .unwrap();
+        network.train(inputs.clone(), targets.clone(), 200);
+        assert!(network.running_means[0].data.iter().any(|&mean| mean != 0.0));
+        assert!(network.gammas[1].data.iter().any(|&gamma| gamma != 1.0));
+        assert!(network.loss(&inputs, &targets) < 0.05);
+
+        // With running statistics, the output of a sample does not depend on its batch.
+        let outputs = network.predict(&inputs);
+        for (input, output) in inputs.iter().zip(&outputs) {
+            let alone = network.predict(std::slice::from_ref(input));
+            assert!((alone[0][0] - output[0]).abs() < 1e-12);
+        }
+
+        for name in ["neural_network_test_normalization.json", "neural_network_test_normalization.bin"] {
+            let path = std::env::temp_dir().join(name);
+            network.save(&path).unwrap();
+            let mut loaded: Network = Network::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.to_saved(), network.to_saved());
+            for (loaded, output) in loaded.predict(&inputs).iter().zip(&outputs) {
+                assert!((loaded[0] - output[0]).abs() < 1e-12);
+            }
+        }
+
+        let mut plain: Network = Network::builder().layers(vec![2, 8, 8, 1]).build().unwrap();
+        assert!(plain.load_parameters(&network.to_saved()).is_err());
+    }
+
+    #[test]
+    fn test_batch_norm_single_sample_batch() {
+        let build = || -> Network {
+            Network::builder()
+                .layers(vec![2, 4, 1])
+                .seed(5)
+                .normalization(Normalization::batch_norm())
+                .learning_rate(0.0)
+                .batch_size(4)
+                .shuffle(false)
+                .progress_bar(false)
+                .build()
+                .unwrap()
+        };
+        let (mut inputs, mut targets) = xor();
+        inputs.extend(inputs.clone());
+        targets.extend(targets.clone());
+        let mut even = build();
+        even.train(inputs.clone(), targets.clone(), 1);
+
+        // Nine samples leave a trailing batch of one, which must not touch the running statistics.
+        inputs.push(vec![0.5, 0.5]);
+        targets.push(vec![0.5]);
+        let mut odd = build();
+        odd.train(inputs, targets, 1);
+        assert_eq!(odd.running_means, even.running_means);
+        assert_eq!(odd.running_variances, even.running_variances);
+        assert!(odd.running_variances[0].data.iter().all(|&variance| variance > 0.0));
+
+        // A single sample in training mode is normalized with the running statistics.
+        odd.set_mode(Mode::Train);
+        let output = odd.feed_forward(Matrix::from(vec![1.0, 0.0]));
+        assert_eq!(odd.running_means, even.running_means);
+        odd.set_mode(Mode::Eval);
+        assert_eq!(odd.feed_forward(Matrix::from(vec![1.0, 0.0])), output);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let (inputs, targets) = xor();
+        let mut network: Network = Network::builder()
+            .layers(vec![2, 4, 1])
+            .activations(vec![Activation::LeakyRelu { alpha: 0.1 }, Activation::Sigmoid])
+            .learning_rate(0.3)
+            .seed(12)
+            .build()
+            .unwrap();
+        network.train(inputs.clone(), targets, 50);
+        let directory = std::env::temp_dir();
+        for name in ["neural_network_test_save.json", "neural_network_test_save.bin"] {
+            let path = directory.join(name);
+            network.save(&path).unwrap();
+            let mut loaded: Network = Network::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.to_saved(), network.to_saved());
+            for input in &inputs {
+                let expected = network.feed_forward(Matrix::from(input.clone()));
+                assert_eq!(loaded.feed_forward(Matrix::from(input.clone())), expected);
+            }
+        }
+
+        // An `f64` network can be loaded with `f32` elements.
+        let single: Network<f32> = Network::from_saved(&network.to_saved()).unwrap();
+        assert_eq!(single.weights[0].data[0], network.weights[0].data[0] as f32);
+    }
+
+    #[test]
+    fn test_from_saved_rejects_mismatched_parameters() {
+        let network: Network = Network::builder().layers(vec![2, 3, 1]).build().unwrap();
+        let mut saved = network.to_saved();
+        saved.weights.swap(0, 1);
+        assert!(matches!(Network::<f64>::from_saved(&saved), Err(SerializationError::InvalidNetwork(_))));
+
+        let mut saved = network.to_saved();
+        saved.activations.pop();
+        assert!(matches!(Network::<f64>::from_saved(&saved), Err(SerializationError::InvalidNetwork(_))));
+
+        let mut saved = network.to_saved();
+        saved.layers = vec![2];
+        saved.activations.clear();
+        assert!(matches!(Network::<f64>::from_saved(&saved), Err(SerializationError::InvalidNetwork(_))));
+
+        let missing = std::env::temp_dir().join("neural_network_test_missing.bin");
+        assert!(matches!(Network::<f64>::load(missing), Err(SerializationError::Io(_))));
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_run() {
+        let (inputs, targets) = xor();
+        let path = std::env::temp_dir().join("neural_network_test_resume.checkpoint");
+        let build = || -> Network {
+            Network::builder()
+                .layers(vec![2, 3, 1])
+                .seed(13)
+                .batch_size(2)
+                .optimizer(Adam::new())
+                .learning_rate(0.05)
+                .schedule(ReduceOnPlateau::new(0.5, 1))
+                .dropout(0.25)
+                .normalization(Normalization::batch_norm())
+                .checkpoint_path(path.clone())
+                .checkpoint_every(7)
+                .build()
+                .unwrap()
+        };
+        // The last checkpoint of the full run is written after epoch 14 of 20.
+        let mut full = build();
+        full.train(inputs.clone(), targets.clone(), 20);
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.epoch, 14);
+        assert_eq!(checkpoint.history.epochs[..], full.history().epochs[..14]);
+
+        let mut resumed = build();
+        resumed.callbacks.clear();
+        resumed.resume_from(&checkpoint).unwrap();
+        resumed.train(inputs, targets, 20);
+        assert_eq!(resumed.weights, full.weights);
+        assert_eq!(resumed.biases, full.biases);
+        assert_eq!(resumed.history().losses(), full.history().losses());
+    }
+
+    #[test]
+    fn test_resume_from_rejects_mismatched_checkpoint() {
+        let network: Network = Network::builder().layers(vec![2, 3, 1]).build().unwrap();
+        let checkpoint = network.checkpoint();
+
+        let mut other: Network = Network::builder().layers(vec![2, 4, 1]).build().unwrap();
+        assert!(matches!(other.resume_from(&checkpoint), Err(SerializationError::InvalidCheckpoint(_))));
+        let mut adam: Network = Network::builder().layers(vec![2, 3, 1]).optimizer(Adam::new()).build().unwrap();
+        assert!(matches!(adam.resume_from(&checkpoint), Err(SerializationError::InvalidCheckpoint(_))));
+        let rms_prop: Network = Network::builder().layers(vec![2, 3, 1]).optimizer(RmsProp::new()).build().unwrap();
+        let mut ada_grad: Network = Network::builder().layers(vec![2, 3, 1]).optimizer(AdaGrad::new()).build().unwrap();
+        let result = ada_grad.resume_from(&rms_prop.checkpoint());
+        assert!(matches!(result, Err(SerializationError::InvalidCheckpoint(_))));
+        let mut plateau: Network =
+            Network::builder().layers(vec![2, 3, 1]).schedule(ReduceOnPlateau::new(0.5, 1)).build().unwrap();
+        assert!(matches!(plateau.resume_from(&checkpoint), Err(SerializationError::InvalidCheckpoint(_))));
+        let mut broken = checkpoint.clone();
+        broken.order = vec![1, 1];
+        let mut same: Network = Network::builder().layers(vec![2, 3, 1]).build().unwrap();
+        assert!(matches!(same.resume_from(&broken), Err(SerializationError::InvalidCheckpoint(_))));
+        let mut broken = checkpoint.clone();
+        broken.epoch = 3;
+        assert!(matches!(same.resume_from(&broken), Err(SerializationError::InvalidCheckpoint(_))));
+        same.resume_from(&checkpoint).unwrap();
+        assert_eq!(same.weights, network.weights);
+    }
+
+    #[test]
+    fn test_shuffle_is_seeded() {
+        let (inputs, targets) = xor();
+        let build = |seed: u64| -> Network {
+            Network::builder().layers(vec![2, 3, 1]).seed(seed).batch_size(2).build().unwrap()
+        };
+        let (mut first, mut second) = (build(11), build(11));
+        first.train(inputs.clone(), targets.clone(), 20);
+        second.train(inputs.clone(), targets.clone(), 20);
+        assert_eq!(first.weights, second.weights);
+
+        // Reshuffling changes the batches, so the result differs from a fixed order.
+        let mut fixed: Network = Network::builder()
+            .layers(vec![2, 3, 1])
+            .seed(11)
+            .batch_size(2)
+            .shuffle(false)
+            .build()
+            .unwrap();
+        fixed.train(inputs, targets, 20);
+        assert_ne!(first.weights, fixed.weights);
+    }
 }
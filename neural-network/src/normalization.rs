@@ -0,0 +1,386 @@
+//! Batch normalization and layer normalization of the pre-activations of a layer.
+//!
+//! A layer transition with a `Normalization` computes `W x + b`, normalizes every value to zero
+//! mean and unit variance, then scales and shifts it by the learnable `gamma` and `beta` of its
+//! neuron before applying the activation function:
+//!
+//! - `BatchNorm` normalizes each neuron over the samples of the batch. During training the mean
+//!   and variance of the batch are used, and running averages of them are kept for inference, so
+//!   the output of a sample in `Mode::Eval` does not depend on the rest of its batch. Since the
+//!   mean is subtracted, the bias of the layer would have no effect: `beta` takes its place and
+//!   the bias is not used. Batch normalization needs a batch size of at least 2; a batch of a
+//!   single sample, such as the last batch of an epoch or a `Network::feed_forward` in
+//!   `Mode::Train`, is normalized with the running statistics and does not update them.
+//! - `LayerNorm` normalizes each sample over the neurons of the layer, the same way in training
+//!   and inference.
+//!
+//! `gamma` starts at one and `beta` at zero, so a new layer passes on normalized values. Both are
+//! updated by the optimizer like the weights and biases, and saved with the network along with
+//! the running statistics.
+//!
+//! # Example
+//! ```
+//! use neural_network::activations::Activation;
+//! use neural_network::normalization::Normalization;
+//! use neural_network::network::Network;
+//!
+//! let network: Network = Network::builder()
+//!     .layers(vec![2, 16, 16, 1])
+//!     .activation(Activation::Relu)
+//!     .layer_activation(2, Activation::Sigmoid)
+//!     .normalization(Normalization::batch_norm())
+//!     .layer_normalization(1, Normalization::layer_norm())
+//!     .batch_size(8)
+//!     .build()
+//!     .unwrap();
+//! ```
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use serde::{Deserialize, Serialize};
+
+/// The momentum of the running statistics of `Normalization::batch_norm`.
+pub const DEFAULT_MOMENTUM: f64 = 0.9;
+
+/// The value added to the variance of `Normalization::batch_norm` and `Normalization::layer_norm`
+/// before taking its square root.
+pub const DEFAULT_EPSILON: f64 = 1e-5;
+
+/// The normalization applied to the pre-activations of a layer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// No normalization.
+    #[default]
+    None,
+    /// Normalizes each neuron over the samples of the batch.
+    BatchNorm {
+        /// The weight of the previous running statistics when a batch updates them, in `[0, 1)`.
+        momentum: f64,
+        /// Added to the variance for numerical stability.
+        epsilon: f64,
+    },
+    /// Normalizes each sample over the neurons of the layer.
+    LayerNorm {
+        /// Added to the variance for numerical stability.
+        epsilon: f64,
+    },
+}
+
+/// Preallocated buffers holding the intermediate results of the normalization of a layer, from
+/// the forward pass to the backward pass.
+pub(crate) struct NormalizationBuffers<T: Float> {
+    /// The normalized values, before `gamma` and `beta` are applied, one column per sample.
+    pub(crate) normalized: Matrix<T>,
+    /// The mean of each group of values: of each neuron for `BatchNorm`, of each sample for `LayerNorm`.
+    pub(crate) means: Matrix<T>,
+    /// The variance of each group of values.
+    pub(crate) variances: Matrix<T>,
+}
+
+impl<T: Float> NormalizationBuffers<T> {
+    /// Creates buffers for a layer of `neurons` neurons, sized for a single sample; they are empty
+    /// without normalization.
+    pub(crate) fn new(normalization: Normalization, neurons: usize) -> Self {
+        let (rows, statistics) = match normalization {
+            Normalization::None => (0, 0),
+            Normalization::BatchNorm { .. } => (neurons, neurons),
+            Normalization::LayerNorm { .. } => (neurons, 1),
+        };
+        NormalizationBuffers {
+            normalized: Matrix::zeros(rows, 1),
+            means: Matrix::zeros(statistics, 1),
+            variances: Matrix::zeros(statistics, 1),
+        }
+    }
+}
+
+impl Normalization {
+    /// Returns batch normalization with the default momentum and epsilon.
+    pub fn batch_norm() -> Self {
+        Normalization::BatchNorm {
+            momentum: DEFAULT_MOMENTUM,
+            epsilon: DEFAULT_EPSILON,
+        }
+    }
+
+    /// Returns layer normalization with the default epsilon.
+    pub fn layer_norm() -> Self {
+        Normalization::LayerNorm { epsilon: DEFAULT_EPSILON }
+    }
+
+    /// Returns `true` if the layer is not normalized.
+    pub fn is_none(&self) -> bool {
+        *self == Normalization::None
+    }
+
+    /// Returns `true` if the normalization keeps running statistics for inference.
+    pub fn has_running_statistics(&self) -> bool {
+        matches!(self, Normalization::BatchNorm { .. })
+    }
+
+    /// Returns `false` if the normalization makes the bias of the layer redundant, so that it is not used.
+    pub fn uses_bias(&self) -> bool {
+        !self.has_running_statistics()
+    }
+
+    /// Returns `true` if the momentum is not in `[0, 1)` or epsilon is not finite and positive.
+    pub(crate) fn is_invalid(&self) -> bool {
+        let valid_epsilon = |epsilon: f64| epsilon.is_finite() && epsilon > 0.0;
+        match *self {
+            Normalization::None => false,
+            Normalization::BatchNorm { momentum, epsilon } => !((0.0..1.0).contains(&momentum) && valid_epsilon(epsilon)),
+            Normalization::LayerNorm { epsilon } => !valid_epsilon(epsilon),
+        }
+    }
+
+    /// Returns `true` if a batch of `batch` samples is normalized with the running statistics:
+    /// always in inference, and in training for a single sample, whose variance over the batch is zero.
+    fn uses_running_statistics(&self, training: bool, batch: usize) -> bool {
+        self.has_running_statistics() && (!training || batch < 2)
+    }
+
+    /// Returns the value added to the variance.
+    fn epsilon(&self) -> f64 {
+        match *self {
+            Normalization::None => 0.0,
+            Normalization::BatchNorm { epsilon, .. } | Normalization::LayerNorm { epsilon } => epsilon,
+        }
+    }
+
+    /// Returns the index into the data of a matrix with `cols` columns of element `k` of group
+    /// `group`, and the row of that element.
+    ///
+    /// The groups are the neurons, i.e. rows, for `BatchNorm`, and the samples, i.e. columns,
+    /// for `LayerNorm`.
+    fn element(&self, cols: usize, group: usize, k: usize) -> (usize, usize) {
+        if self.has_running_statistics() {
+            (group * cols + k, group)
+        } else {
+            (k * cols + group, k)
+        }
+    }
+
+    /// Returns the number of groups and the number of elements in each group of a matrix.
+    fn groups<T: Float>(&self, values: &Matrix<T>) -> (usize, usize) {
+        if self.has_running_statistics() {
+            (values.rows, values.cols)
+        } else {
+            (values.cols, values.rows)
+        }
+    }
+
+    /// Normalizes the pre-activations of a layer in place, then scales and shifts them.
+    ///
+    /// # Arguments
+    /// * `gamma` - The scale of each neuron.
+    /// * `beta` - The shift of each neuron.
+    /// * `running_mean` - The running mean of each neuron, used instead of the batch statistics
+    ///   when `training` is `false`; empty for `LayerNorm`.
+    /// * `running_variance` - The running variance of each neuron, like `running_mean`.
+    /// * `training` - Whether to normalize with the statistics of the batch; a batch of a single
+    ///   sample uses the running statistics of batch normalization even in training.
+    /// * `values` - The pre-activations, one column per sample, replaced by the normalized values.
+    /// * `buffers` - Receives the normalized values and the statistics for the backward pass.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn forward<T: Float>(
+        &self,
+        gamma: &Matrix<T>,
+        beta: &Matrix<T>,
+        running_mean: &Matrix<T>,
+        running_variance: &Matrix<T>,
+        training: bool,
+        values: &mut Matrix<T>,
+        buffers: &mut NormalizationBuffers<T>,
+    ) {
+        let (groups, size) = self.groups(values);
+        let use_running = self.uses_running_statistics(training, values.cols);
+        if !self.has_running_statistics() {
+            // One statistic per sample.
+            buffers.means.resize_cols(groups);
+            buffers.variances.resize_cols(groups);
+        }
+        let epsilon = T::from_f64(self.epsilon());
+        let count = T::from_f64(size as f64);
+        for group in 0..groups {
+            let (mean, variance) = if use_running {
+                (running_mean.data[group], running_variance.data[group])
+            } else {
+                let values = &*values;
+                let value = |k| values.data[self.element(values.cols, group, k).0];
+                let mean = (0..size).map(value).sum::<T>() / count;
+                let variance = (0..size).map(|k| (value(k) - mean) * (value(k) - mean)).sum::<T>() / count;
+                buffers.means.data[group] = mean;
+                buffers.variances.data[group] = variance;
+                (mean, variance)
+            };
+            let inverse_std = T::one() / (variance + epsilon).sqrt();
+            for k in 0..size {
+                let (index, row) = self.element(values.cols, group, k);
+                let normalized = (values.data[index] - mean) * inverse_std;
+                buffers.normalized.data[index] = normalized;
+                values.data[index] = gamma.data[row] * normalized + beta.data[row];
+            }
+        }
+    }
+
+    /// Computes the gradients of `gamma` and `beta`, and turns the gradient of the loss with
+    /// respect to the normalized outputs into its gradient with respect to the pre-activations.
+    ///
+    /// # Arguments
+    /// * `gamma` - The scale of each neuron.
+    /// * `running_variance` - The running variance of each neuron, used when `training` is `false`.
+    /// * `training` - Whether the forward pass used the statistics of the batch.
+    /// * `buffers` - The buffers filled by the forward pass.
+    /// * `gradient` - The gradient with respect to the outputs, replaced in place.
+    /// * `gamma_gradient` - Receives the gradient of `gamma`.
+    /// * `beta_gradient` - Receives the gradient of `beta`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn backward<T: Float>(
+        &self,
+        gamma: &Matrix<T>,
+        running_variance: &Matrix<T>,
+        training: bool,
+        buffers: &NormalizationBuffers<T>,
+        gradient: &mut Matrix<T>,
+        gamma_gradient: &mut Matrix<T>,
+        beta_gradient: &mut Matrix<T>,
+    ) {
+        let normalized = &buffers.normalized;
+        for row in 0..gradient.rows {
+            let range = row * gradient.cols..(row + 1) * gradient.cols;
+            let outputs = gradient.data[range.clone()].iter().zip(&normalized.data[range]);
+            gamma_gradient.data[row] = outputs.clone().map(|(&gradient, &normalized)| gradient * normalized).sum();
+            beta_gradient.data[row] = outputs.map(|(&gradient, _)| gradient).sum();
+        }
+        let (groups, size) = self.groups(gradient);
+        let use_running = self.uses_running_statistics(training, gradient.cols);
+        let variances = if use_running { running_variance } else { &buffers.variances };
+        let epsilon = T::from_f64(self.epsilon());
+        let count = T::from_f64(size as f64);
+        for group in 0..groups {
+            let inverse_std = T::one() / (variances.data[group] + epsilon).sqrt();
+            if use_running {
+                for k in 0..size {
+                    let (index, row) = self.element(gradient.cols, group, k);
+                    gradient.data[index] *= gamma.data[row] * inverse_std;
+                }
+                continue;
+            }
+            // The gradient with respect to the normalized values, and its sums over the group.
+            let (mut sum, mut dot) = (T::zero(), T::zero());
+            for k in 0..size {
+                let (index, row) = self.element(gradient.cols, group, k);
+                gradient.data[index] *= gamma.data[row];
+                sum += gradient.data[index];
+                dot += gradient.data[index] * normalized.data[index];
+            }
+            for k in 0..size {
+                let (index, _) = self.element(gradient.cols, group, k);
+                let centered = count * gradient.data[index] - sum - normalized.data[index] * dot;
+                gradient.data[index] = inverse_std / count * centered;
+            }
+        }
+    }
+
+    /// Moves the running statistics towards the statistics of the last batch normalized in training.
+    ///
+    /// A batch of a single sample was normalized with the running statistics and leaves them unchanged.
+    ///
+    /// # Arguments
+    /// * `buffers` - The buffers filled by the forward pass.
+    /// * `running_mean` - The running mean of each neuron, updated in place.
+    /// * `running_variance` - The running variance of each neuron, updated in place.
+    pub(crate) fn update_running_statistics<T: Float>(
+        &self,
+        buffers: &NormalizationBuffers<T>,
+        running_mean: &mut Matrix<T>,
+        running_variance: &mut Matrix<T>,
+    ) {
+        if buffers.normalized.cols < 2 {
+            return;
+        }
+        if let Normalization::BatchNorm { momentum, .. } = *self {
+            let (momentum, rate) = (T::from_f64(momentum), T::from_f64(1.0 - momentum));
+            for (running, &batch) in running_mean.data.iter_mut().zip(&buffers.means.data) {
+                *running = momentum * *running + rate * batch;
+            }
+            for (running, &batch) in running_variance.data.iter_mut().zip(&buffers.variances.data) {
+                *running = momentum * *running + rate * batch;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+/// Tests for the normalization kernels; their gradients are checked through `Network::check_gradients`.
+///
+/// The tests cover:
+/// - Normalizing over the batch with batch statistics and with running statistics
+/// - Normalizing over the neurons of each sample
+/// - Updating the running statistics
+/// - Rejecting invalid options
+mod tests {
+    use super::*;
+
+    fn values() -> Matrix<f64> {
+        Matrix::new(2, 4, vec![1.0, 2.0, 3.0, 6.0, -1.0, -1.0, 1.0, 1.0])
+    }
+
+    fn column(values: Vec<f64>) -> Matrix<f64> {
+        Matrix::new(values.len(), 1, values)
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_batch_norm() {
+        let normalization = Normalization::BatchNorm { momentum: 0.5, epsilon: 1e-8 };
+        let (gamma, beta) = (column(vec![2.0, 1.0]), column(vec![0.5, 0.0]));
+        let (mut running_mean, mut running_variance) = (column(vec![0.0, 0.0]), column(vec![1.0, 1.0]));
+        let mut buffers = NormalizationBuffers::new(normalization, 2);
+        buffers.normalized.resize_cols(4);
+        let mut normalized = values();
+        normalization.forward(&gamma, &beta, &running_mean, &running_variance, true, &mut normalized, &mut buffers);
+        // The first row has mean 3 and variance 3.5, the second mean 0 and variance 1.
+        let first = [-2.0, -1.0, 0.0, 3.0].map(|value: f64| 2.0 * value / 3.5f64.sqrt() + 0.5);
+        assert_close(&normalized.data[..4], &first);
+        assert_close(&normalized.data[4..], &[-1.0, -1.0, 1.0, 1.0]);
+        assert_close(&buffers.means.data, &[3.0, 0.0]);
+        assert_close(&buffers.variances.data, &[3.5, 1.0]);
+
+        normalization.update_running_statistics(&buffers, &mut running_mean, &mut running_variance);
+        assert_close(&running_mean.data, &[1.5, 0.0]);
+        assert_close(&running_variance.data, &[2.25, 1.0]);
+
+        let mut normalized = values();
+        normalization.forward(&gamma, &beta, &running_mean, &running_variance, false, &mut normalized, &mut buffers);
+        assert_close(&normalized.data[..4], &[-0.5 / 1.5 * 2.0 + 0.5, 0.5 + 0.5 / 1.5 * 2.0, 2.5, 6.5]);
+        assert_close(&normalized.data[4..], &[-1.0, -1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_layer_norm() {
+        let normalization = Normalization::LayerNorm { epsilon: 1e-8 };
+        let (gamma, beta) = (column(vec![1.0, 1.0]), column(vec![0.0, 1.0]));
+        let empty = Matrix::zeros(0, 1);
+        let mut buffers = NormalizationBuffers::new(normalization, 2);
+        buffers.normalized.resize_cols(4);
+        let mut normalized = values();
+        normalization.forward(&gamma, &beta, &empty, &empty, true, &mut normalized, &mut buffers);
+        assert_close(&normalized.data, &[1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_close(&buffers.means.data, &[0.0, 0.5, 2.0, 3.5]);
+        assert_eq!(buffers.means.shape(), (1, 4));
+    }
+
+    #[test]
+    fn test_is_invalid() {
+        assert!(!Normalization::None.is_invalid());
+        assert!(!Normalization::batch_norm().is_invalid());
+        assert!(Normalization::BatchNorm { momentum: 1.0, epsilon: 1e-5 }.is_invalid());
+        assert!(Normalization::LayerNorm { epsilon: f64::NAN }.is_invalid());
+        assert!(!Normalization::batch_norm().uses_bias() && Normalization::layer_norm().uses_bias());
+    }
+}
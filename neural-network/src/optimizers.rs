@@ -0,0 +1,509 @@
+//! Update rules turning the gradients computed by backpropagation into new parameter values.
+//!
+//! A `Network` computes the gradients of every weight and bias matrix first, then calls
+//! `Optimizer::begin_step` once and `Optimizer::update` for each matrix. The matrices are
+//! identified by their index, so stateful optimizers can keep one buffer per matrix; the
+//! weights of layer transition `i` have index `2 * i` and its biases index `2 * i + 1`.
+use crate::serialization::{SavedMatrix, SerializationError};
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// An update rule for the parameters of a `Network`.
+pub trait Optimizer<T: Float>: Debug + Send + Sync + OptimizerClone<T> {
+    /// Starts a new step, before the parameters are updated with the gradients of that step.
+    fn begin_step(&mut self) {}
+
+    /// Updates one parameter matrix with its gradient.
+    ///
+    /// # Arguments
+    /// * `index` - The index of the parameter matrix, used to find its optimizer state.
+    /// * `parameter` - The parameter matrix to update.
+    /// * `gradient` - The gradient of the loss with respect to `parameter`.
+    /// * `learning_rate` - The learning rate of this step.
+    fn update(&mut self, index: usize, parameter: &mut Matrix<T>, gradient: &Matrix<T>, learning_rate: T);
+
+    /// Returns the state accumulated by the optimizer, to be saved in a checkpoint.
+    ///
+    /// Stateless optimizers keep the default, which returns an empty state with an empty kind.
+    fn save_state(&self) -> OptimizerState {
+        OptimizerState::default()
+    }
+
+    /// Restores a state returned by `save_state`, so that training continues exactly where it stopped.
+    ///
+    /// # Returns
+    /// An error if the state was saved by a different kind of optimizer.
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), SerializationError> {
+        state.expect("", 0)?;
+        Ok(())
+    }
+}
+
+/// The state of an optimizer in its saved form.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OptimizerState {
+    /// The kind of optimizer that saved the state, e.g. `"adam"`.
+    pub kind: String,
+    /// The number of steps taken, for optimizers that count them.
+    pub step: i64,
+    /// The state buffers of the optimizer, each holding one matrix per parameter index.
+    pub buffers: Vec<BTreeMap<usize, SavedMatrix>>,
+}
+
+impl OptimizerState {
+    /// Creates the state holding the given buffers.
+    ///
+    /// # Arguments
+    /// * `kind` - The kind of optimizer saving the state.
+    /// * `step` - The number of steps taken.
+    /// * `buffers` - The state buffers, in an order fixed by each optimizer.
+    pub fn new<T: Float>(kind: &str, step: i64, buffers: &[&BTreeMap<usize, Matrix<T>>]) -> Self {
+        let save = |buffer: &&BTreeMap<usize, Matrix<T>>| {
+            buffer.iter().map(|(&index, matrix)| (index, SavedMatrix::from(matrix))).collect()
+        };
+        OptimizerState {
+            kind: kind.to_string(),
+            step,
+            buffers: buffers.iter().map(save).collect(),
+        }
+    }
+
+    /// Returns the buffer `index` converted back to matrices.
+    ///
+    /// # Returns
+    /// The buffer, or an error if a matrix is malformed.
+    pub fn buffer<T: Float>(&self, index: usize) -> Result<BTreeMap<usize, Matrix<T>>, SerializationError> {
+        self.buffers[index]
+            .iter()
+            .map(|(&index, matrix)| Ok((index, matrix.to_matrix()?)))
+            .collect()
+    }
+
+    /// Checks that the state was saved by an optimizer of kind `kind` and holds `count` buffers,
+    /// as saved by the optimizer restoring it.
+    pub fn expect(&self, kind: &str, count: usize) -> Result<(), SerializationError> {
+        if self.kind != kind {
+            return Err(SerializationError::InvalidCheckpoint(format!(
+                "expected the state of a {:?} optimizer, found {:?}",
+                kind, self.kind
+            )));
+        }
+        if self.buffers.len() != count {
+            return Err(SerializationError::InvalidCheckpoint(format!(
+                "expected {} optimizer state buffers, found {}",
+                count,
+                self.buffers.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Clones a boxed `Optimizer`, including its state.
+///
+/// This is implemented for every optimizer that implements `Clone`.
+pub trait OptimizerClone<T: Float> {
+    /// Returns a boxed copy of the optimizer.
+    fn clone_box(&self) -> Box<dyn Optimizer<T>>;
+}
+
+impl<T: Float, O: Optimizer<T> + Clone + 'static> OptimizerClone<T> for O {
+    fn clone_box(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<T: Float> Clone for Box<dyn Optimizer<T>> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Returns the state buffer of the parameter matrix `index`, creating it filled with zeros.
+fn state<'a, T: Float>(buffers: &'a mut BTreeMap<usize, Matrix<T>>, index: usize, like: &Matrix<T>) -> &'a mut Matrix<T> {
+    buffers.entry(index).or_insert_with(|| Matrix::zeros(like.rows, like.cols))
+}
+
+/// Stochastic gradient descent, optionally with (Nesterov) momentum.
+///
+/// Without momentum the update is `p -= lr * g`, the historical behaviour of `Network`. With
+/// momentum `mu` a velocity `v = mu * v + g` is kept per parameter and the update is
+/// `p -= lr * v`, or `p -= lr * (g + mu * v)` with Nesterov momentum.
+#[derive(Clone, Debug)]
+pub struct Sgd<T: Float = f64> {
+    /// The momentum factor; zero disables momentum.
+    pub momentum: f64,
+    /// Whether to use Nesterov momentum.
+    pub nesterov: bool,
+    velocities: BTreeMap<usize, Matrix<T>>,
+}
+
+impl<T: Float> Sgd<T> {
+    /// Creates plain stochastic gradient descent, without momentum.
+    pub fn new() -> Self {
+        Sgd::with_momentum(0.0)
+    }
+
+    /// Creates stochastic gradient descent with classical momentum.
+    ///
+    /// # Arguments
+    /// * `momentum` - The momentum factor, usually `0.9`.
+    pub fn with_momentum(momentum: f64) -> Self {
+        Sgd {
+            momentum,
+            nesterov: false,
+            velocities: BTreeMap::new(),
+        }
+    }
+
+    /// Creates stochastic gradient descent with Nesterov momentum.
+    ///
+    /// # Arguments
+    /// * `momentum` - The momentum factor, usually `0.9`.
+    pub fn nesterov(momentum: f64) -> Self {
+        Sgd {
+            nesterov: true,
+            ..Sgd::with_momentum(momentum)
+        }
+    }
+}
+
+impl<T: Float> Default for Sgd<T> {
+    fn default() -> Self {
+        Sgd::new()
+    }
+}
+
+impl<T: Float> Optimizer<T> for Sgd<T> {
+    fn update(&mut self, index: usize, parameter: &mut Matrix<T>, gradient: &Matrix<T>, learning_rate: T) {
+        if self.momentum == 0.0 {
+            for (p, &g) in parameter.data.iter_mut().zip(&gradient.data) {
+                *p -= learning_rate * g;
+            }
+            return;
+        }
+        let momentum = T::from_f64(self.momentum);
+        let velocity = state(&mut self.velocities, index, parameter);
+        for ((p, v), &g) in parameter.data.iter_mut().zip(velocity.data.iter_mut()).zip(&gradient.data) {
+            *v = momentum * *v + g;
+            let step = if self.nesterov { g + momentum * *v } else { *v };
+            *p -= learning_rate * step;
+        }
+    }
+
+    fn save_state(&self) -> OptimizerState {
+        OptimizerState::new("sgd", 0, &[&self.velocities])
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), SerializationError> {
+        state.expect("sgd", 1)?;
+        self.velocities = state.buffer(0)?;
+        Ok(())
+    }
+}
+
+/// AdaGrad: divides each update by the root of the sum of all past squared gradients, so
+/// parameters with large gradients slow down.
+#[derive(Clone, Debug)]
+pub struct AdaGrad<T: Float = f64> {
+    /// Added to the denominator to avoid dividing by zero.
+    pub epsilon: f64,
+    sums: BTreeMap<usize, Matrix<T>>,
+}
+
+impl<T: Float> AdaGrad<T> {
+    /// Creates AdaGrad with `epsilon = 1e-8`.
+    pub fn new() -> Self {
+        AdaGrad {
+            epsilon: 1e-8,
+            sums: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Float> Default for AdaGrad<T> {
+    fn default() -> Self {
+        AdaGrad::new()
+    }
+}
+
+impl<T: Float> Optimizer<T> for AdaGrad<T> {
+    fn update(&mut self, index: usize, parameter: &mut Matrix<T>, gradient: &Matrix<T>, learning_rate: T) {
+        let epsilon = T::from_f64(self.epsilon);
+        let sum = state(&mut self.sums, index, parameter);
+        for ((p, s), &g) in parameter.data.iter_mut().zip(sum.data.iter_mut()).zip(&gradient.data) {
+            *s += g * g;
+            *p -= learning_rate * g / (s.sqrt() + epsilon);
+        }
+    }
+
+    fn save_state(&self) -> OptimizerState {
+        OptimizerState::new("adagrad", 0, &[&self.sums])
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), SerializationError> {
+        state.expect("adagrad", 1)?;
+        self.sums = state.buffer(0)?;
+        Ok(())
+    }
+}
+
+/// RMSProp: divides each update by the root of a moving average of the squared gradients.
+#[derive(Clone, Debug)]
+pub struct RmsProp<T: Float = f64> {
+    /// The decay rate of the moving average, usually `0.9`.
+    pub decay: f64,
+    /// Added to the denominator to avoid dividing by zero.
+    pub epsilon: f64,
+    averages: BTreeMap<usize, Matrix<T>>,
+}
+
+impl<T: Float> RmsProp<T> {
+    /// Creates RMSProp with `decay = 0.9` and `epsilon = 1e-8`.
+    pub fn new() -> Self {
+        RmsProp {
+            decay: 0.9,
+            epsilon: 1e-8,
+            averages: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Float> Default for RmsProp<T> {
+    fn default() -> Self {
+        RmsProp::new()
+    }
+}
+
+impl<T: Float> Optimizer<T> for RmsProp<T> {
+    fn update(&mut self, index: usize, parameter: &mut Matrix<T>, gradient: &Matrix<T>, learning_rate: T) {
+        let decay = T::from_f64(self.decay);
+        let epsilon = T::from_f64(self.epsilon);
+        let average = state(&mut self.averages, index, parameter);
+        for ((p, a), &g) in parameter.data.iter_mut().zip(average.data.iter_mut()).zip(&gradient.data) {
+            *a = decay * *a + (T::one() - decay) * g * g;
+            *p -= learning_rate * g / (a.sqrt() + epsilon);
+        }
+    }
+
+    fn save_state(&self) -> OptimizerState {
+        OptimizerState::new("rmsprop", 0, &[&self.averages])
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), SerializationError> {
+        state.expect("rmsprop", 1)?;
+        self.averages = state.buffer(0)?;
+        Ok(())
+    }
+}
+
+/// Adam: keeps moving averages of the gradients (first moment) and squared gradients (second
+/// moment), corrects them for their bias towards zero in the first steps, and updates with
+/// `p -= lr * m / (sqrt(v) + epsilon)`.
+///
+/// With a non-zero `weight_decay` this is AdamW, which shrinks the parameters by
+/// `lr * weight_decay * p` each step, separately from the gradient.
+#[derive(Clone, Debug)]
+pub struct Adam<T: Float = f64> {
+    /// The decay rate of the first moment, usually `0.9`.
+    pub beta1: f64,
+    /// The decay rate of the second moment, usually `0.999`.
+    pub beta2: f64,
+    /// Added to the denominator to avoid dividing by zero.
+    pub epsilon: f64,
+    /// The decoupled weight decay factor; zero gives plain Adam.
+    pub weight_decay: f64,
+    /// The number of steps taken, used for the bias correction.
+    step: i32,
+    first_moments: BTreeMap<usize, Matrix<T>>,
+    second_moments: BTreeMap<usize, Matrix<T>>,
+}
+
+impl<T: Float> Adam<T> {
+    /// Creates Adam with `beta1 = 0.9`, `beta2 = 0.999` and `epsilon = 1e-8`.
+    pub fn new() -> Self {
+        Adam {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            weight_decay: 0.0,
+            step: 0,
+            first_moments: BTreeMap::new(),
+            second_moments: BTreeMap::new(),
+        }
+    }
+
+    /// Creates AdamW, Adam with decoupled weight decay.
+    ///
+    /// # Arguments
+    /// * `weight_decay` - The weight decay factor, usually `0.01`.
+    pub fn adamw(weight_decay: f64) -> Self {
+        Adam {
+            weight_decay,
+            ..Adam::new()
+        }
+    }
+}
+
+impl<T: Float> Default for Adam<T> {
+    fn default() -> Self {
+        Adam::new()
+    }
+}
+
+impl<T: Float> Optimizer<T> for Adam<T> {
+    fn begin_step(&mut self) {
+        self.step += 1;
+    }
+
+    fn update(&mut self, index: usize, parameter: &mut Matrix<T>, gradient: &Matrix<T>, learning_rate: T) {
+        let (beta1, beta2) = (T::from_f64(self.beta1), T::from_f64(self.beta2));
+        let epsilon = T::from_f64(self.epsilon);
+        let decay = learning_rate * T::from_f64(self.weight_decay);
+        let step = self.step.max(1);
+        let first_correction = T::one() - T::from_f64(self.beta1.powi(step));
+        let second_correction = T::one() - T::from_f64(self.beta2.powi(step));
+        let first = state(&mut self.first_moments, index, parameter);
+        let second = state(&mut self.second_moments, index, parameter);
+        let moments = first.data.iter_mut().zip(second.data.iter_mut());
+        for ((p, (m, v)), &g) in parameter.data.iter_mut().zip(moments).zip(&gradient.data) {
+            *m = beta1 * *m + (T::one() - beta1) * g;
+            *v = beta2 * *v + (T::one() - beta2) * g * g;
+            let m_hat = *m / first_correction;
+            let v_hat = *v / second_correction;
+            *p -= decay * *p + learning_rate * m_hat / (v_hat.sqrt() + epsilon);
+        }
+    }
+
+    fn save_state(&self) -> OptimizerState {
+        OptimizerState::new("adam", self.step as i64, &[&self.first_moments, &self.second_moments])
+    }
+
+    fn load_state(&mut self, state: &OptimizerState) -> Result<(), SerializationError> {
+        state.expect("adam", 2)?;
+        self.step = i32::try_from(state.step)
+            .map_err(|_| SerializationError::InvalidCheckpoint(format!("invalid step count {}", state.step)))?;
+        self.first_moments = state.buffer(0)?;
+        self.second_moments = state.buffer(1)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `Optimizer` implementations.
+///
+/// The tests cover:
+/// - The exact first steps of SGD, momentum and Adam
+/// - Every optimizer minimizing a quadratic
+/// - Decoupled weight decay
+/// - Cloning a boxed optimizer with its state
+/// - Saving and restoring the state of each optimizer
+mod tests {
+    use super::*;
+
+    /// Runs `steps` steps on `1/2 * |p - c|^2`, whose gradient is `p - c`, and returns `p`.
+    fn minimize(optimizer: &mut dyn Optimizer<f64>, learning_rate: f64, steps: usize) -> Matrix<f64> {
+        let target = Matrix::new(2, 2, vec![1.0, -2.0, 0.5, 3.0]);
+        let mut parameter = Matrix::zeros(2, 2);
+        for _ in 0..steps {
+            let gradient = parameter.subtract(&target);
+            optimizer.begin_step();
+            optimizer.update(0, &mut parameter, &gradient, learning_rate);
+        }
+        parameter.subtract(&target)
+    }
+
+    #[test]
+    fn test_first_steps() {
+        let gradient = Matrix::from(vec![2.0, -4.0]);
+        let mut parameter = Matrix::from(vec![1.0, 1.0]);
+        Sgd::new().update(0, &mut parameter, &gradient, 0.5);
+        assert_eq!(parameter.data, vec![0.0, 3.0]);
+
+        let mut sgd = Sgd::with_momentum(0.5);
+        let mut parameter = Matrix::from(vec![0.0, 0.0]);
+        sgd.update(0, &mut parameter, &gradient, 1.0);
+        sgd.update(0, &mut parameter, &gradient, 1.0);
+        // Velocities are `g` then `1.5 g`.
+        assert_eq!(parameter.data, vec![-5.0, 10.0]);
+
+        // Bias correction makes the first Adam step `lr * sign(g)`.
+        let mut adam = Adam::new();
+        let mut parameter = Matrix::from(vec![0.0, 0.0]);
+        adam.begin_step();
+        adam.update(0, &mut parameter, &gradient, 0.1);
+        assert!((parameter.data[0] + 0.1).abs() < 1e-6);
+        assert!((parameter.data[1] - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_optimizers_minimize_quadratic() {
+        let cases: Vec<(Box<dyn Optimizer<f64>>, f64)> = vec![
+            (Box::new(Sgd::new()), 0.1),
+            (Box::new(Sgd::with_momentum(0.9)), 0.05),
+            (Box::new(Sgd::nesterov(0.9)), 0.05),
+            (Box::new(AdaGrad::new()), 0.5),
+            (Box::new(RmsProp::new()), 0.01),
+            (Box::new(Adam::new()), 0.05),
+        ];
+        for (mut optimizer, learning_rate) in cases {
+            let error = minimize(optimizer.as_mut(), learning_rate, 1000);
+            assert!(error.data.iter().all(|e| e.abs() < 1e-2), "{:?}: {:?}", optimizer, error);
+        }
+    }
+
+    #[test]
+    fn test_adamw_decays_weights() {
+        let mut adamw = Adam::adamw(0.1);
+        let mut parameter = Matrix::from(vec![2.0, -2.0]);
+        let zero = Matrix::zeros(2, 1);
+        for _ in 0..10 {
+            adamw.begin_step();
+            adamw.update(0, &mut parameter, &zero, 0.5);
+        }
+        let expected = 2.0 * (1.0f64 - 0.05).powi(10);
+        assert!((parameter.data[0] - expected).abs() < 1e-12);
+        assert!((parameter.data[1] + expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_clone_box_keeps_state() {
+        let mut optimizer: Box<dyn Optimizer<f64>> = Box::new(Sgd::with_momentum(0.9));
+        minimize(optimizer.as_mut(), 0.01, 3);
+        let mut copy = optimizer.clone();
+        assert_eq!(minimize(optimizer.as_mut(), 0.01, 3), minimize(copy.as_mut(), 0.01, 3));
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let cases: Vec<Box<dyn Optimizer<f64>>> = vec![
+            Box::new(Sgd::new()),
+            Box::new(Sgd::with_momentum(0.9)),
+            Box::new(AdaGrad::new()),
+            Box::new(RmsProp::new()),
+            Box::new(Adam::new()),
+        ];
+        for fresh in cases {
+            let mut optimizer = fresh.clone();
+            minimize(optimizer.as_mut(), 0.01, 3);
+            let mut restored = fresh;
+            restored.load_state(&optimizer.save_state()).unwrap();
+            assert_eq!(restored.save_state(), optimizer.save_state());
+            assert_eq!(minimize(optimizer.as_mut(), 0.01, 3), minimize(restored.as_mut(), 0.01, 3));
+        }
+
+        let mut adam: Adam = Adam::new();
+        let state = Sgd::<f64>::with_momentum(0.9).save_state();
+        assert!(matches!(adam.load_state(&state), Err(SerializationError::InvalidCheckpoint(_))));
+
+        // Optimizers with the same number of buffers do not load each other's state.
+        let mut rms_prop: Box<dyn Optimizer<f64>> = Box::new(RmsProp::new());
+        minimize(rms_prop.as_mut(), 0.01, 3);
+        let mut ada_grad: AdaGrad = AdaGrad::new();
+        assert!(matches!(ada_grad.load_state(&rms_prop.save_state()), Err(SerializationError::InvalidCheckpoint(_))));
+        assert!(Sgd::<f64>::new().load_state(&ada_grad.save_state()).is_err());
+    }
+}
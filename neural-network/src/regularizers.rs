@@ -0,0 +1,119 @@
+//! Penalties on the size of the parameters of a `Network`, added to the loss to reduce overfitting.
+//!
+//! Each layer transition has a `Regularizer` for its weights, which also applies to its biases
+//! when `regularize_biases` is set on the builder. The penalty is part of the loss reported by
+//! `Network::loss` and recorded during training, and its gradient is added to the gradients of
+//! the parameters, so the optimizer shrinks the parameters towards zero at every step.
+//!
+//! With plain `Sgd`, `L2` is the classic weight decay; with `Adam`, the decoupled weight decay of
+//! `Adam::adamw` usually regularizes better than an `L2` penalty.
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// A penalty on the values of a parameter matrix.
+pub enum Regularizer {
+    /// No penalty.
+    #[default]
+    None,
+    /// The lasso penalty `l1 * sum |w|`, which drives parameters to exactly zero.
+    L1(f64),
+    /// The ridge penalty `l2 / 2 * sum w^2`, whose gradient `l2 * w` decays every parameter
+    /// in proportion to its value.
+    L2(f64),
+    /// The sum of an `L1` and an `L2` penalty.
+    ElasticNet { l1: f64, l2: f64 },
+}
+
+impl Regularizer {
+    /// Returns the `L1` and `L2` coefficients of the penalty.
+    fn coefficients(&self) -> (f64, f64) {
+        match *self {
+            Regularizer::None => (0.0, 0.0),
+            Regularizer::L1(l1) => (l1, 0.0),
+            Regularizer::L2(l2) => (0.0, l2),
+            Regularizer::ElasticNet { l1, l2 } => (l1, l2),
+        }
+    }
+
+    /// Returns `true` if the regularizer adds no penalty.
+    pub fn is_none(&self) -> bool {
+        self.coefficients() == (0.0, 0.0)
+    }
+
+    /// Returns `true` if a coefficient is negative or not finite.
+    pub(crate) fn is_invalid(&self) -> bool {
+        let (l1, l2) = self.coefficients();
+        !(l1.is_finite() && l1 >= 0.0 && l2.is_finite() && l2 >= 0.0)
+    }
+
+    /// Computes the penalty of a parameter matrix.
+    ///
+    /// # Arguments
+    /// * `parameter` - The weights or biases to penalize.
+    pub fn penalty<T: Float>(&self, parameter: &Matrix<T>) -> T {
+        let (l1, l2) = self.coefficients();
+        let (l1, half_l2) = (T::from_f64(l1), T::from_f64(l2 / 2.0));
+        parameter.data.iter().map(|&value| l1 * value.abs() + half_l2 * value * value).sum()
+    }
+
+    /// Adds the gradient of the penalty to the gradient of a parameter matrix.
+    ///
+    /// The penalty `L1` has no derivative at zero; zero is used there.
+    ///
+    /// # Arguments
+    /// * `parameter` - The penalized weights or biases.
+    /// * `gradient` - The gradient of the loss with respect to `parameter`, updated in place.
+    pub fn add_gradient<T: Float>(&self, parameter: &Matrix<T>, gradient: &mut Matrix<T>) {
+        if self.is_none() {
+            return;
+        }
+        let (l1, l2) = self.coefficients();
+        let (l1, l2) = (T::from_f64(l1), T::from_f64(l2));
+        for (gradient, &value) in gradient.data.iter_mut().zip(&parameter.data) {
+            let sign = if value > T::zero() {
+                T::one()
+            } else if value < T::zero() {
+                -T::one()
+            } else {
+                T::zero()
+            };
+            *gradient += l1 * sign + l2 * value;
+        }
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `Regularizer`.
+///
+/// The tests cover:
+/// - The penalty of each regularizer
+/// - The gradient of each regularizer, including at zero
+mod tests {
+    use super::*;
+
+    fn parameter() -> Matrix<f64> {
+        Matrix::new(1, 3, vec![-2.0, 0.0, 1.0])
+    }
+
+    #[test]
+    fn test_penalty() {
+        assert_eq!(Regularizer::None.penalty(&parameter()), 0.0);
+        assert_eq!(Regularizer::L1(0.5).penalty(&parameter()), 1.5);
+        assert_eq!(Regularizer::L2(0.5).penalty(&parameter()), 1.25);
+        assert_eq!(Regularizer::ElasticNet { l1: 0.5, l2: 0.5 }.penalty(&parameter()), 2.75);
+        assert!(Regularizer::L2(0.0).is_none());
+        assert!(Regularizer::L1(-0.1).is_invalid());
+    }
+
+    #[test]
+    fn test_gradient() {
+        let mut gradient = Matrix::new(1, 3, vec![1.0, 1.0, 1.0]);
+        Regularizer::L1(0.5).add_gradient(&parameter(), &mut gradient);
+        assert_eq!(gradient.data, vec![0.5, 1.0, 1.5]);
+
+        let mut gradient = Matrix::zeros(1, 3);
+        Regularizer::ElasticNet { l1: 0.5, l2: 0.25 }.add_gradient(&parameter(), &mut gradient);
+        assert_eq!(gradient.data, vec![-1.0, 0.0, 0.75]);
+    }
+}
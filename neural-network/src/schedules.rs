@@ -0,0 +1,515 @@
+//! Learning-rate schedules, adjusting the learning rate of a `Network` as training progresses.
+//!
+//! `Network::train` asks its schedule for the learning rate before every step, passing the
+//! configured learning rate as `base` and the position in training, and reports the loss of
+//! every epoch, on the validation set if there is one, through `LrSchedule::observe`.
+use crate::serialization::SerializationError;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+/// The position of a training step within a call to `Network::train`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The current epoch, starting at zero.
+    pub epoch: usize,
+    /// The current step, counted from the start of training and starting at zero.
+    pub step: usize,
+    /// The number of steps in each epoch.
+    pub steps_per_epoch: usize,
+    /// The total number of epochs of the training run.
+    pub epochs: usize,
+}
+
+impl Progress {
+    /// Returns the current epoch including the fraction already done, e.g. `2.5` halfway
+    /// through the third epoch.
+    pub fn fractional_epoch(&self) -> f64 {
+        self.step as f64 / self.steps_per_epoch.max(1) as f64
+    }
+
+    /// Returns the total number of steps of the training run.
+    pub fn total_steps(&self) -> usize {
+        self.epochs * self.steps_per_epoch
+    }
+}
+
+/// A learning-rate schedule.
+pub trait LrSchedule: Debug + Send + Sync + LrScheduleClone {
+    /// Returns the learning rate for a training step.
+    ///
+    /// # Arguments
+    /// * `base` - The learning rate configured on the network.
+    /// * `progress` - The position of the step in training.
+    fn learning_rate(&mut self, base: f64, progress: Progress) -> f64;
+
+    /// Returns the kind of the schedule, saved with its state so that a checkpoint only restores
+    /// into a schedule of the same kind.
+    fn kind(&self) -> String;
+
+    /// Reports the loss at the end of an epoch, for schedules that react to it.
+    ///
+    /// # Arguments
+    /// * `loss` - The validation loss of the epoch, or its training loss without a validation set.
+    fn observe(&mut self, loss: f64) {
+        let _ = loss;
+    }
+
+    /// Returns the state accumulated from the observed losses, to be saved in a checkpoint.
+    ///
+    /// Schedules that depend only on the `Progress` keep the default, which returns their kind
+    /// without any values.
+    fn save_state(&self) -> ScheduleState {
+        ScheduleState::new(&self.kind(), vec![])
+    }
+
+    /// Restores a state returned by `save_state`.
+    ///
+    /// # Returns
+    /// An error if the state was saved by a different kind of schedule.
+    fn load_state(&mut self, state: &ScheduleState) -> Result<(), SerializationError> {
+        state.expect(&self.kind())?;
+        Ok(())
+    }
+}
+
+/// The state of a learning-rate schedule in its saved form.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleState {
+    /// The kind of schedule that saved the state, e.g. `"reduce_on_plateau"`.
+    pub kind: String,
+    /// The state values, in an order fixed by each schedule.
+    pub values: Vec<f64>,
+}
+
+impl ScheduleState {
+    /// Creates the state holding the given values.
+    ///
+    /// # Arguments
+    /// * `kind` - The kind of schedule saving the state.
+    /// * `values` - The state values.
+    pub fn new(kind: &str, values: Vec<f64>) -> Self {
+        ScheduleState {
+            kind: kind.to_string(),
+            values,
+        }
+    }
+
+    /// Checks that the state was saved by a schedule of kind `kind`.
+    ///
+    /// # Returns
+    /// The state values, or an error if the kinds differ.
+    pub fn expect(&self, kind: &str) -> Result<&[f64], SerializationError> {
+        if self.kind != kind {
+            return Err(SerializationError::InvalidCheckpoint(format!(
+                "expected the state of a {:?} schedule, found {:?}",
+                kind, self.kind
+            )));
+        }
+        Ok(&self.values)
+    }
+}
+
+/// Clones a boxed `LrSchedule`, including its state.
+///
+/// This is implemented for every schedule that implements `Clone`.
+pub trait LrScheduleClone {
+    /// Returns a boxed copy of the schedule.
+    fn clone_box(&self) -> Box<dyn LrSchedule>;
+}
+
+impl<S: LrSchedule + Clone + 'static> LrScheduleClone for S {
+    fn clone_box(&self) -> Box<dyn LrSchedule> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn LrSchedule> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Keeps the learning rate fixed. This is the default schedule of a `Network`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Constant;
+
+impl LrSchedule for Constant {
+    fn kind(&self) -> String {
+        "constant".to_string()
+    }
+
+    fn learning_rate(&mut self, base: f64, _progress: Progress) -> f64 {
+        base
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `step_size` epochs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepDecay {
+    /// The number of epochs between two decays.
+    pub step_size: usize,
+    /// The factor applied at each decay.
+    pub gamma: f64,
+}
+
+impl LrSchedule for StepDecay {
+    fn kind(&self) -> String {
+        "step_decay".to_string()
+    }
+
+    fn learning_rate(&mut self, base: f64, progress: Progress) -> f64 {
+        base * self.gamma.powi((progress.epoch / self.step_size.max(1)) as i32)
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExponentialDecay {
+    /// The factor applied each epoch.
+    pub gamma: f64,
+}
+
+impl LrSchedule for ExponentialDecay {
+    fn kind(&self) -> String {
+        "exponential_decay".to_string()
+    }
+
+    fn learning_rate(&mut self, base: f64, progress: Progress) -> f64 {
+        base * self.gamma.powi(progress.epoch as i32)
+    }
+}
+
+/// Cosine annealing with warm restarts (SGDR): the learning rate follows half a cosine from
+/// `base` down to `min_learning_rate` over `period` epochs, then restarts at `base`, each
+/// period being `period_multiplier` times longer than the previous one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CosineAnnealing {
+    /// The length of the first period, in epochs.
+    pub period: usize,
+    /// The factor by which each period is longer than the previous one; `1` keeps them equal.
+    pub period_multiplier: usize,
+    /// The learning rate at the end of each period.
+    pub min_learning_rate: f64,
+}
+
+impl LrSchedule for CosineAnnealing {
+    fn kind(&self) -> String {
+        "cosine_annealing".to_string()
+    }
+
+    fn learning_rate(&mut self, base: f64, progress: Progress) -> f64 {
+        let mut position = progress.fractional_epoch();
+        let mut period = self.period.max(1) as f64;
+        while position >= period {
+            position -= period;
+            period *= self.period_multiplier.max(1) as f64;
+        }
+        let cosine = (1.0 + (PI * position / period).cos()) / 2.0;
+        self.min_learning_rate + (base - self.min_learning_rate) * cosine
+    }
+}
+
+/// Increases the learning rate linearly from almost zero to the rate of another schedule over
+/// the first `warmup_steps` steps, then follows that schedule.
+#[derive(Clone, Debug)]
+pub struct LinearWarmup {
+    /// The number of steps to warm up over.
+    pub warmup_steps: usize,
+    /// The schedule followed during and after the warmup.
+    pub then: Box<dyn LrSchedule>,
+}
+
+impl LinearWarmup {
+    /// Creates a warmup followed by `then`.
+    ///
+    /// # Arguments
+    /// * `warmup_steps` - The number of steps to warm up over.
+    /// * `then` - The schedule followed during and after the warmup.
+    pub fn new(warmup_steps: usize, then: impl LrSchedule + 'static) -> Self {
+        LinearWarmup {
+            warmup_steps,
+            then: Box::new(then),
+        }
+    }
+}
+
+impl LrSchedule for LinearWarmup {
+    fn kind(&self) -> String {
+        format!("linear_warmup/{}", self.then.kind())
+    }
+
+    fn learning_rate(&mut self, base: f64, progress: Progress) -> f64 {
+        let rate = self.then.learning_rate(base, progress);
+        if progress.step < self.warmup_steps {
+            rate * (progress.step + 1) as f64 / self.warmup_steps as f64
+        } else {
+            rate
+        }
+    }
+
+    fn observe(&mut self, loss: f64) {
+        self.then.observe(loss);
+    }
+
+    /// Saves the state of `then` under the kind of the warmup.
+    fn save_state(&self) -> ScheduleState {
+        ScheduleState::new(&self.kind(), self.then.save_state().values)
+    }
+
+    fn load_state(&mut self, state: &ScheduleState) -> Result<(), SerializationError> {
+        let values = state.expect(&self.kind())?;
+        self.then.load_state(&ScheduleState::new(&self.then.kind(), values.to_vec()))
+    }
+}
+
+/// The one-cycle policy: the learning rate rises from `base / div_factor` to `base` over the
+/// first `warmup_fraction` of training, then falls along a cosine to
+/// `base / (div_factor * final_div_factor)` at the last step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OneCycle {
+    /// The fraction of the training steps spent increasing the learning rate.
+    pub warmup_fraction: f64,
+    /// The ratio between the peak and the initial learning rate.
+    pub div_factor: f64,
+    /// The ratio between the initial and the final learning rate.
+    pub final_div_factor: f64,
+}
+
+impl Default for OneCycle {
+    fn default() -> Self {
+        OneCycle {
+            warmup_fraction: 0.3,
+            div_factor: 25.0,
+            final_div_factor: 1e4,
+        }
+    }
+}
+
+impl LrSchedule for OneCycle {
+    fn kind(&self) -> String {
+        "one_cycle".to_string()
+    }
+
+    fn learning_rate(&mut self, base: f64, progress: Progress) -> f64 {
+        let initial = base / self.div_factor;
+        let last_step = progress.total_steps().saturating_sub(1).max(1) as f64;
+        let peak_step = (self.warmup_fraction * last_step).max(1.0);
+        let step = progress.step as f64;
+        let anneal = |from: f64, to: f64, fraction: f64| to + (from - to) * (1.0 + (PI * fraction).cos()) / 2.0;
+        if step <= peak_step {
+            anneal(initial, base, step / peak_step)
+        } else {
+            let fraction = ((step - peak_step) / (last_step - peak_step).max(1.0)).min(1.0);
+            anneal(base, initial / self.final_div_factor, fraction)
+        }
+    }
+}
+
+/// Multiplies the learning rate by `factor` whenever the observed loss has not improved by at
+/// least `min_delta` for more than `patience` epochs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReduceOnPlateau {
+    /// The factor applied to the learning rate on a plateau.
+    pub factor: f64,
+    /// The number of epochs without improvement tolerated before reducing the learning rate.
+    pub patience: usize,
+    /// The minimum decrease of the loss that counts as an improvement.
+    pub min_delta: f64,
+    /// The learning rate is never reduced below this value.
+    pub min_learning_rate: f64,
+    best: f64,
+    wait: usize,
+    scale: f64,
+}
+
+impl ReduceOnPlateau {
+    /// Creates a schedule that multiplies the learning rate by `factor` after `patience`
+    /// epochs without improvement.
+    ///
+    /// # Arguments
+    /// * `factor` - The factor applied to the learning rate on a plateau, e.g. `0.1`.
+    /// * `patience` - The number of epochs without improvement tolerated.
+    pub fn new(factor: f64, patience: usize) -> Self {
+        ReduceOnPlateau {
+            factor,
+            patience,
+            min_delta: 0.0,
+            min_learning_rate: 0.0,
+            best: f64::INFINITY,
+            wait: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl LrSchedule for ReduceOnPlateau {
+    fn kind(&self) -> String {
+        "reduce_on_plateau".to_string()
+    }
+
+    fn learning_rate(&mut self, base: f64, _progress: Progress) -> f64 {
+        (base * self.scale).max(self.min_learning_rate)
+    }
+
+    fn observe(&mut self, loss: f64) {
+        if loss < self.best - self.min_delta {
+            self.best = loss;
+            self.wait = 0;
+        } else {
+            self.wait += 1;
+            if self.wait > self.patience {
+                self.scale *= self.factor;
+                self.wait = 0;
+            }
+        }
+    }
+
+    /// Saves the scale, the epochs waited and, once a loss was observed, the best loss.
+    fn save_state(&self) -> ScheduleState {
+        let mut values = vec![self.scale, self.wait as f64];
+        if self.best.is_finite() {
+            values.push(self.best);
+        }
+        ScheduleState::new(&self.kind(), values)
+    }
+
+    fn load_state(&mut self, state: &ScheduleState) -> Result<(), SerializationError> {
+        match *state.expect(&self.kind())? {
+            [scale, wait, ref rest @ ..] if rest.len() <= 1 => {
+                self.scale = scale;
+                self.wait = wait as usize;
+                self.best = rest.first().copied().unwrap_or(f64::INFINITY);
+                Ok(())
+            }
+            ref values => Err(SerializationError::InvalidCheckpoint(format!(
+                "expected 2 or 3 schedule state values, found {}",
+                values.len()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+/// Tests for the `LrSchedule` implementations.
+///
+/// The tests cover:
+/// - The learning rate of each schedule at known points
+/// - Reducing the learning rate on a plateau
+/// - Saving and restoring the state of schedules, and rejecting the state of another kind
+mod tests {
+    use super::*;
+
+    fn at(epoch: usize, step_in_epoch: usize) -> Progress {
+        Progress {
+            epoch,
+            step: epoch * 10 + step_in_epoch,
+            steps_per_epoch: 10,
+            epochs: 20,
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn test_decays() {
+        let mut step = StepDecay { step_size: 5, gamma: 0.5 };
+        assert_eq!(step.learning_rate(0.8, at(4, 9)), 0.8);
+        assert_eq!(step.learning_rate(0.8, at(5, 0)), 0.4);
+        assert_eq!(step.learning_rate(0.8, at(12, 0)), 0.2);
+        let mut exponential = ExponentialDecay { gamma: 0.9 };
+        assert!(close(exponential.learning_rate(1.0, at(3, 5)), 0.729));
+        assert_eq!(Constant.learning_rate(0.3, at(7, 1)), 0.3);
+    }
+
+    #[test]
+    fn test_cosine_annealing_restarts() {
+        let mut cosine = CosineAnnealing {
+            period: 4,
+            period_multiplier: 2,
+            min_learning_rate: 0.0,
+        };
+        assert!(close(cosine.learning_rate(1.0, at(0, 0)), 1.0));
+        assert!(close(cosine.learning_rate(1.0, at(2, 0)), 0.5));
+        // The second period starts at epoch 4 and lasts 8 epochs.
+        assert!(close(cosine.learning_rate(1.0, at(4, 0)), 1.0));
+        assert!(close(cosine.learning_rate(1.0, at(8, 0)), 0.5));
+        assert!(close(cosine.learning_rate(1.0, at(12, 0)), 1.0));
+    }
+
+    #[test]
+    fn test_linear_warmup() {
+        let mut warmup = LinearWarmup::new(4, StepDecay { step_size: 1, gamma: 0.5 });
+        assert!(close(warmup.learning_rate(1.0, at(0, 0)), 0.25));
+        assert!(close(warmup.learning_rate(1.0, at(0, 3)), 1.0));
+        assert!(close(warmup.learning_rate(1.0, at(1, 0)), 0.5));
+    }
+
+    #[test]
+    fn test_one_cycle() {
+        let mut one_cycle = OneCycle::default();
+        let last = at(19, 9);
+        assert!(close(one_cycle.learning_rate(1.0, at(0, 0)), 1.0 / 25.0));
+        let peak = (0.3 * 199.0f64).round() as usize;
+        let rates: Vec<f64> = (0..200).map(|step| one_cycle.learning_rate(1.0, at(step / 10, step % 10))).collect();
+        let highest = (0..200).max_by(|&a, &b| rates[a].total_cmp(&rates[b])).unwrap();
+        assert!(highest.abs_diff(peak) <= 1);
+        assert!(rates[highest] > 0.999);
+        assert!(close(one_cycle.learning_rate(1.0, last), 1.0 / 25.0 / 1e4));
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut plateau = ReduceOnPlateau::new(0.5, 2);
+        for loss in [1.0, 0.8, 0.8, 0.8] {
+            plateau.observe(loss);
+        }
+        assert_eq!(plateau.learning_rate(0.1, at(4, 0)), 0.1);
+        plateau.observe(0.9);
+        assert_eq!(plateau.learning_rate(0.1, at(5, 0)), 0.05);
+        plateau.observe(0.7);
+        plateau.observe(0.7);
+        assert_eq!(plateau.learning_rate(0.1, at(7, 0)), 0.05);
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut plateau = ReduceOnPlateau::new(0.5, 0);
+        let mut warmup = LinearWarmup::new(2, ReduceOnPlateau::new(0.5, 0));
+        assert_eq!(Constant.save_state(), ScheduleState::new("constant", vec![]));
+        for loss in [1.0, 1.0] {
+            plateau.observe(loss);
+            warmup.observe(loss);
+        }
+        let mut restored = ReduceOnPlateau::new(0.5, 0);
+        restored.load_state(&plateau.save_state()).unwrap();
+        assert_eq!(restored, plateau);
+        let mut restored = LinearWarmup::new(2, ReduceOnPlateau::new(0.5, 0));
+        restored.load_state(&warmup.save_state()).unwrap();
+        assert_eq!(restored.learning_rate(1.0, at(3, 0)), 0.5);
+
+        // A fresh plateau schedule has not seen a loss yet.
+        let mut fresh = ReduceOnPlateau::new(0.5, 0);
+        fresh.load_state(&ReduceOnPlateau::new(0.5, 0).save_state()).unwrap();
+        assert_eq!(fresh, ReduceOnPlateau::new(0.5, 0));
+
+        // Schedules of another kind do not load the state, even without any values.
+        assert!(Constant.load_state(&plateau.save_state()).is_err());
+        assert!(fresh.load_state(&Constant.save_state()).is_err());
+        assert!(fresh.load_state(&warmup.save_state()).is_err());
+        assert!(warmup.load_state(&plateau.save_state()).is_err());
+        assert!(StepDecay { step_size: 5, gamma: 0.5 }.load_state(&Constant.save_state()).is_err());
+        assert!(OneCycle::default().load_state(&ExponentialDecay { gamma: 0.9 }.save_state()).is_err());
+        let annealing = CosineAnnealing {
+            period: 4,
+            period_multiplier: 1,
+            min_learning_rate: 0.0,
+        };
+        let mut cosine = LinearWarmup::new(2, annealing);
+        assert!(cosine.load_state(&LinearWarmup::new(2, Constant).save_state()).is_err());
+        cosine.load_state(&cosine.save_state()).unwrap();
+        assert!(fresh.load_state(&ScheduleState::new("reduce_on_plateau", vec![1.0])).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Saving trained networks to disk and loading them back.
+//!
+//! A network is saved as a `SavedNetwork`: its layer sizes, activation functions, base learning
+//! rate, weights and biases, and the normalization of its normalized layers with their learnable
+//! parameters and running statistics. The loss, optimizer, schedule and training options are not saved;
+//! a loaded network uses the defaults of `Network::builder` for them, which can be changed with
+//! `set_loss`, `set_optimizer` and `set_schedule`.
+//!
+//! Two formats are supported:
+//! - `Format::Json`, human readable, for inspecting or editing a model by hand.
+//! - `Format::Binary`, compact: the `MAGIC` bytes, the format version as a little-endian `u32`,
+//!   then the network encoded with `bincode`.
+//!
+//! Both formats record `FORMAT_VERSION`. Loading a file written by a newer, unknown version fails
+//! with `SerializationError::UnsupportedVersion` instead of misreading it. Networks saved in
+//! version 1, before normalization layers, still load.
+//!
+//! # Example
+//! ```no_run
+//! use neural_network::network::Network;
+//!
+//! let network: Network = Network::builder().layers(vec![2, 3, 1]).build().unwrap();
+//! network.save("xor.json").unwrap();
+//! let loaded: Network = Network::load("xor.json").unwrap();
+//! ```
+use crate::activations::Activation;
+use crate::normalization::Normalization;
+use matrix::matrix::Matrix;
+use matrix::scalar::Float;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The bytes every file in the binary format starts with.
+pub const MAGIC: [u8; 4] = *b"NNRS";
+
+/// The version of the saved format written by this version of the crate.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The file format of a saved network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Human-readable JSON.
+    Json,
+    /// The compact versioned binary format.
+    Binary,
+}
+
+impl Format {
+    /// Chooses the format from the extension of a path: `Json` for `.json`, `Binary` otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Binary,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// The error type returned when saving or loading a network fails.
+pub enum SerializationError {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+    /// The JSON form could not be written or parsed.
+    Json(serde_json::Error),
+    /// The binary form could not be encoded or decoded.
+    Binary(bincode::Error),
+    /// The file was written by a newer version of the format.
+    UnsupportedVersion {
+        /// The version recorded in the file.
+        found: u32,
+        /// The newest version this crate can read.
+        supported: u32,
+    },
+    /// The file was read, but does not describe a valid network.
+    InvalidNetwork(String),
+    /// The checkpoint does not match the network or the training data it is resumed with.
+    InvalidCheckpoint(String),
+}
+
+/// Implements the `fmt::Display` trait for `SerializationError`, producing a human readable description of the error.
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializationError::Io(error) => write!(f, "Cannot read or write the network: {}", error),
+            SerializationError::Json(error) => write!(f, "Invalid JSON network: {}", error),
+            SerializationError::Binary(error) => write!(f, "Invalid binary network: {}", error),
+            SerializationError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Unsupported network format version {}: this version reads up to version {}",
+                found, supported
+            ),
+            SerializationError::InvalidNetwork(reason) => write!(f, "Invalid network: {}", reason),
+            SerializationError::InvalidCheckpoint(reason) => write!(f, "Invalid checkpoint: {}", reason),
+        }
+    }
+}
+
+impl Error for SerializationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SerializationError::Io(error) => Some(error),
+            SerializationError::Json(error) => Some(error),
+            SerializationError::Binary(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SerializationError {
+    fn from(error: io::Error) -> Self {
+        SerializationError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SerializationError {
+    fn from(error: serde_json::Error) -> Self {
+        SerializationError::Json(error)
+    }
+}
+
+impl From<bincode::Error> for SerializationError {
+    fn from(error: bincode::Error) -> Self {
+        SerializationError::Binary(error)
+    }
+}
+
+/// A matrix in its saved form, with its elements as `f64` in row-major order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedMatrix {
+    /// The number of rows.
+    pub rows: usize,
+    /// The number of columns.
+    pub cols: usize,
+    /// The `rows * cols` elements, row by row.
+    pub data: Vec<f64>,
+}
+
+impl<T: Float> From<&Matrix<T>> for SavedMatrix {
+    fn from(matrix: &Matrix<T>) -> Self {
+        SavedMatrix {
+            rows: matrix.rows,
+            cols: matrix.cols,
+            data: matrix.data.iter().map(|&value| value.to_f64()).collect(),
+        }
+    }
+}
+
+impl SavedMatrix {
+    /// Converts the saved matrix back into a `Matrix`.
+    ///
+    /// # Returns
+    /// The matrix, or `SerializationError::InvalidNetwork` if the number of elements does not
+    /// match the dimensions.
+    pub fn to_matrix<T: Float>(&self) -> Result<Matrix<T>, SerializationError> {
+        if self.data.len() != self.rows * self.cols {
+            return Err(SerializationError::InvalidNetwork(format!(
+                "a {}x{} matrix has {} elements",
+                self.rows,
+                self.cols,
+                self.data.len()
+            )));
+        }
+        let data = self.data.iter().map(|&value| T::from_f64(value)).collect();
+        Ok(Matrix::new(self.rows, self.cols, data))
+    }
+}
+
+/// Everything needed to restore a trained network.
+///
+/// Use `Network::to_saved` and `Network::from_saved` to convert between a network and its saved form.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedNetwork {
+    /// The version of the format, `FORMAT_VERSION` when written by this crate.
+    pub format_version: u32,
+    /// The number of neurons in each layer, starting with the input layer.
+    pub layers: Vec<usize>,
+    /// The activation function applied to the output of each layer after the input layer.
+    pub activations: Vec<Activation>,
+    /// The base learning rate.
+    pub learning_rate: f64,
+    /// The weight matrix of each layer after the input layer.
+    pub weights: Vec<SavedMatrix>,
+    /// The bias vector of each layer after the input layer.
+    pub biases: Vec<SavedMatrix>,
+    /// The normalization of each normalized layer, keyed by the index of the layer transition.
+    #[serde(default)]
+    pub normalizations: BTreeMap<usize, SavedNormalization>,
+}
+
+/// The normalization of a layer, with its learnable parameters and running statistics.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedNormalization {
+    /// The kind of normalization and its options.
+    pub normalization: Normalization,
+    /// The scale of each neuron.
+    pub gamma: SavedMatrix,
+    /// The shift of each neuron.
+    pub beta: SavedMatrix,
+    /// The running mean of each neuron, empty without running statistics.
+    pub running_mean: SavedMatrix,
+    /// The running variance of each neuron, empty without running statistics.
+    pub running_variance: SavedMatrix,
+}
+
+/// A network saved in version 1 of the format, which had no normalization layers.
+#[derive(Deserialize)]
+struct SavedNetworkV1 {
+    format_version: u32,
+    layers: Vec<usize>,
+    activations: Vec<Activation>,
+    learning_rate: f64,
+    weights: Vec<SavedMatrix>,
+    biases: Vec<SavedMatrix>,
+}
+
+impl From<SavedNetworkV1> for SavedNetwork {
+    fn from(saved: SavedNetworkV1) -> Self {
+        SavedNetwork {
+            format_version: saved.format_version,
+            layers: saved.layers,
+            activations: saved.activations,
+            learning_rate: saved.learning_rate,
+            weights: saved.weights,
+            biases: saved.biases,
+            normalizations: BTreeMap::new(),
+        }
+    }
+}
+
+impl SavedNetwork {
+    /// Writes the network in the given format.
+    ///
+    /// # Arguments
+    /// * `writer` - Where to write the network.
+    /// * `format` - The format to write.
+    pub fn write_to(&self, writer: impl Write, format: Format) -> Result<(), SerializationError> {
+        write_versioned(self, MAGIC, self.format_version, writer, format)
+    }
+
+    /// Reads a network written by `write_to` in either format.
+    ///
+    /// The format is detected from the content: data starting with `MAGIC` is read as binary,
+    /// anything else as JSON.
+    ///
+    /// # Arguments
+    /// * `reader` - Where to read the network from.
+    ///
+    /// # Returns
+    /// The saved network, or an error if the data cannot be read, is malformed, or was written
+    /// by a newer version of the format.
+    pub fn read_from(mut reader: impl Read) -> Result<Self, SerializationError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        // The binary format has no optional fields, so version 1 is read with its own layout.
+        let saved: SavedNetwork = match binary_version(&bytes, MAGIC) {
+            Some(1) => read_versioned::<SavedNetworkV1>(bytes.as_slice(), MAGIC)?.into(),
+            _ => read_versioned(bytes.as_slice(), MAGIC)?,
+        };
+        check_version(saved.format_version)?;
+        Ok(saved)
+    }
+
+    /// Writes the network to a file, in the format chosen by `Format::from_path`.
+    ///
+    /// # Arguments
+    /// * `path` - The file to create or overwrite.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SerializationError> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)?;
+        self.write_to(io::BufWriter::new(file), Format::from_path(path))
+    }
+
+    /// Reads a network from a file written by `save` in either format.
+    ///
+    /// # Arguments
+    /// * `path` - The file to read.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SerializationError> {
+        let file = std::fs::File::open(path)?;
+        SavedNetwork::read_from(io::BufReader::new(file))
+    }
+}
+
+/// Writes `value` in the given format; the binary format starts with `magic` and `version`.
+pub(crate) fn write_versioned<S: Serialize>(
+    value: &S,
+    magic: [u8; 4],
+    version: u32,
+    mut writer: impl Write,
+    format: Format,
+) -> Result<(), SerializationError> {
+    match format {
+        Format::Json => serde_json::to_writer_pretty(&mut writer, value)?,
+        Format::Binary => {
+            writer.write_all(&magic)?;
+            writer.write_all(&version.to_le_bytes())?;
+            bincode::serialize_into(&mut writer, value)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a value written by `write_versioned`, detecting the format from the content.
+///
+/// The version is checked before the value is parsed, so that a newer file is reported as such
+/// rather than as a parse error on whatever changed. The caller checks the `format_version`
+/// field of the value, which is the only version a JSON file without that field has.
+pub(crate) fn read_versioned<S: DeserializeOwned>(mut reader: impl Read, magic: [u8; 4]) -> Result<S, SerializationError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    match bytes.strip_prefix(&magic) {
+        Some(rest) => {
+            let version = binary_version(&bytes, magic)
+                .ok_or_else(|| SerializationError::InvalidNetwork("missing format version".to_string()))?;
+            check_version(version)?;
+            Ok(bincode::deserialize(&rest[4..])?)
+        }
+        None => {
+            let value: serde_json::Value = serde_json::from_slice(&bytes)?;
+            if let Some(version) = value.get("format_version").and_then(|version| version.as_u64()) {
+                check_version(u32::try_from(version).unwrap_or(u32::MAX))?;
+            }
+            Ok(serde_json::from_value(value)?)
+        }
+    }
+}
+
+/// Returns the format version of data in the binary format starting with `magic`, or `None` if
+/// the data is not in that format or too short.
+fn binary_version(bytes: &[u8], magic: [u8; 4]) -> Option<u32> {
+    let version = bytes.strip_prefix(&magic)?.get(..4)?;
+    Some(u32::from_le_bytes([version[0], version[1], version[2], version[3]]))
+}
+
+/// Rejects format versions newer than `FORMAT_VERSION`.
+pub(crate) fn check_version(version: u32) -> Result<(), SerializationError> {
+    if version > FORMAT_VERSION {
+        return Err(SerializationError::UnsupportedVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+/// Tests for the saved format.
+///
+/// The tests cover:
+/// - Round trips through both formats
+/// - Detecting the format from the path and from the content
+/// - Rejecting newer format versions and malformed data
+/// - Reading networks saved in version 1 of the format
+mod tests {
+    use super::*;
+
+    fn saved() -> SavedNetwork {
+        SavedNetwork {
+            format_version: FORMAT_VERSION,
+            layers: vec![2, 1],
+            activations: vec![Activation::LeakyRelu { alpha: 0.1 }],
+            learning_rate: 0.25,
+            weights: vec![SavedMatrix { rows: 1, cols: 2, data: vec![0.5, -1.5] }],
+            biases: vec![SavedMatrix { rows: 1, cols: 1, data: vec![0.125] }],
+            normalizations: BTreeMap::from([(
+                0,
+                SavedNormalization {
+                    normalization: Normalization::batch_norm(),
+                    gamma: SavedMatrix { rows: 1, cols: 1, data: vec![1.5] },
+                    beta: SavedMatrix { rows: 1, cols: 1, data: vec![-0.5] },
+                    running_mean: SavedMatrix { rows: 1, cols: 1, data: vec![0.25] },
+                    running_variance: SavedMatrix { rows: 1, cols: 1, data: vec![2.0] },
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        let mut expected = saved();
+        expected.format_version = 1;
+        expected.normalizations.clear();
+        let fields = (1u32, &expected.layers, &expected.activations, 0.25, &expected.weights, &expected.biases);
+        let mut binary = MAGIC.to_vec();
+        binary.extend(1u32.to_le_bytes());
+        binary.extend(bincode::serialize(&fields).unwrap());
+        assert_eq!(SavedNetwork::read_from(binary.as_slice()).unwrap(), expected);
+
+        let mut json = serde_json::to_value(&expected).unwrap();
+        json.as_object_mut().unwrap().remove("normalizations");
+        let json = serde_json::to_vec(&json).unwrap();
+        assert_eq!(SavedNetwork::read_from(json.as_slice()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::Json, Format::Binary] {
+            let mut bytes = vec![];
+            saved().write_to(&mut bytes, format).unwrap();
+            assert_eq!(bytes.starts_with(&MAGIC), format == Format::Binary);
+            assert_eq!(SavedNetwork::read_from(bytes.as_slice()).unwrap(), saved());
+        }
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("model.json")), Format::Json);
+        assert_eq!(Format::from_path(Path::new("model.JSON")), Format::Json);
+        assert_eq!(Format::from_path(Path::new("model.bin")), Format::Binary);
+        assert_eq!(Format::from_path(Path::new("model")), Format::Binary);
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut newer = saved();
+        newer.format_version = FORMAT_VERSION + 1;
+        for format in [Format::Json, Format::Binary] {
+            let mut bytes = vec![];
+            newer.write_to(&mut bytes, format).unwrap();
+            match SavedNetwork::read_from(bytes.as_slice()) {
+                Err(SerializationError::UnsupportedVersion { found, supported }) => {
+                    assert_eq!((found, supported), (FORMAT_VERSION + 1, FORMAT_VERSION));
+                }
+                other => panic!("Expected an unsupported version, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_data_is_rejected() {
+        assert!(matches!(SavedNetwork::read_from(&b"NNRS"[..]), Err(SerializationError::InvalidNetwork(_))));
+        assert!(matches!(SavedNetwork::read_from(&b"NNRS\x01\0\0\0\x07"[..]), Err(SerializationError::Binary(_))));
+        assert!(matches!(SavedNetwork::read_from(&b"{\"layers\":"[..]), Err(SerializationError::Json(_))));
+        let matrix = SavedMatrix { rows: 2, cols: 2, data: vec![1.0] };
+        assert!(matches!(matrix.to_matrix::<f64>(), Err(SerializationError::InvalidNetwork(_))));
+    }
+}